@@ -0,0 +1,122 @@
+use crate::keyring::{KeyScheme, Keyring};
+use crate::recipients::encode_public;
+use age::armor::ArmoredReader;
+use age::secrecy::SecretString;
+use std::{
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+// age-format output (https://age-encryption.org/v1) so .enc files can be
+// opened with stock `age -d` in an emergency. Recipients become X25519
+// stanzas (our keys are age keys already); the shared key becomes an scrypt
+// passphrase stanza. age has no associated data, so these files are not
+// bound to a path or environment.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+const AGE_ARMOR_MAGIC: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+// fixed so the pre-commit change check stays fast; age's own default is
+// calibrated to ~1s, which is paid for every file on every commit
+const SCRYPT_WORK_FACTOR: u8 = 16;
+
+pub fn is_age_file(data: &[u8]) -> bool {
+    data.starts_with(AGE_MAGIC) || data.trim_ascii_start().starts_with(AGE_ARMOR_MAGIC)
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn passphrase(key: &Keyring) -> io::Result<SecretString> {
+    key.secret()
+        .map(|s| SecretString::from(s.to_string()))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "age file uses a passphrase but no shared key is configured",
+            )
+        })
+}
+
+pub fn encrypt_age(key: &Keyring, scheme: &KeyScheme, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    let encryptor = match scheme {
+        KeyScheme::Shared(_) => {
+            let mut r = age::scrypt::Recipient::new(passphrase(key)?);
+            r.set_work_factor(SCRYPT_WORK_FACTOR);
+            age::Encryptor::with_recipients(std::iter::once(&r as &dyn age::Recipient))
+        }
+        KeyScheme::Recipients(pks) => {
+            let rs = pks
+                .iter()
+                .map(|pk| age::x25519::Recipient::from_str(&encode_public(pk)).map_err(invalid))
+                .collect::<io::Result<Vec<_>>>()?;
+            age::Encryptor::with_recipients(rs.iter().map(|r| r as &dyn age::Recipient))
+        }
+    }
+    .map_err(|e| io::Error::other(format!("age encrypt failed: {e}")))?;
+
+    let mut out = Vec::new();
+    let mut w = encryptor.wrap_output(&mut out)?;
+    w.write_all(plaintext)?;
+    w.finish()?;
+    Ok(out)
+}
+
+/// Decrypts a binary or armored age file with the shared key (scrypt) or any
+/// of the local identities (X25519).
+pub fn decrypt_age(key: &Keyring, data: &[u8]) -> io::Result<Vec<u8>> {
+    let decryptor = age::Decryptor::new(ArmoredReader::new(data))
+        .map_err(|e| invalid(format!("bad age file: {e}")))?;
+    let result = if decryptor.is_scrypt() {
+        let id = age::scrypt::Identity::new(passphrase(key)?);
+        decryptor.decrypt(std::iter::once(&id as &dyn age::Identity))
+    } else {
+        if key.identities().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "age file is encrypted to recipients but no identity is configured (eenv identity new)",
+            ));
+        }
+        let ids = key
+            .identities()
+            .iter()
+            .map(|id| age::x25519::Identity::from_str(&id.to_secret_string()).map_err(invalid))
+            .collect::<io::Result<Vec<_>>>()?;
+        decryptor.decrypt(ids.iter().map(|i| i as &dyn age::Identity))
+    };
+    let mut reader = result.map_err(|e| invalid(format!("age decrypt failed: {e}")))?;
+    let mut out = Vec::new();
+    reader
+        .read_to_end(&mut out)
+        .map_err(|e| invalid(format!("age decrypt failed: {e}")))?;
+    Ok(out)
+}
+
+// stanza types ("X25519", "scrypt", ...) listed in an age header
+fn stanza_types(data: &[u8]) -> Vec<String> {
+    let mut raw = Vec::new();
+    if ArmoredReader::new(data).read_to_end(&mut raw).is_err() {
+        return Vec::new();
+    }
+    let text = String::from_utf8_lossy(&raw);
+    text.lines()
+        .skip(1)
+        .take_while(|l| !l.starts_with("---"))
+        .filter_map(|l| l.strip_prefix("-> "))
+        .filter_map(|l| l.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether an age file was written under this scheme. X25519 stanzas don't
+/// name their recipient, so only the recipient count can be compared (the
+/// caller has to catch a swapped recipient some other way); age also adds
+/// random "grease" stanzas, which are ignored.
+pub fn matches_scheme(data: &[u8], scheme: &KeyScheme) -> bool {
+    let types = stanza_types(data);
+    let count = |ty: &str| types.iter().filter(|t| *t == ty).count();
+    match scheme {
+        KeyScheme::Shared(_) => count("scrypt") == 1,
+        KeyScheme::Recipients(pks) => count("scrypt") == 0 && count("X25519") == pks.len(),
+    }
+}
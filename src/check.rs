@@ -0,0 +1,299 @@
+use crate::config::read_key_map;
+use crate::crypto::{binding_for, decrypt_bytes};
+use crate::dotenv::{self, Document};
+use crate::envscan::DEFAULT_ENV;
+use crate::expand;
+use crate::keyring::KeyMap;
+use crate::manifest::{self, Problem};
+use crate::resolve;
+use crate::schema::{self, SCHEMA_FILE};
+use std::{fs, io, path::Path};
+
+/// Reads `.env*` files, decrypting `.env*.enc` in memory. The keys are
+/// loaded and eenv.manifest is checked once, when the first `.enc` needs
+/// them; a `.enc` that doesn't match the manifest, or isn't signed as
+/// eenv.signers requires, is refused.
+pub struct EnvReader<'a> {
+    repo_root: &'a Path,
+    keys: Option<io::Result<KeyMap>>,
+    problems: Option<io::Result<Vec<Problem>>>,
+}
+
+impl<'a> EnvReader<'a> {
+    pub fn new(repo_root: &'a Path) -> Self {
+        EnvReader {
+            repo_root,
+            keys: None,
+            problems: None,
+        }
+    }
+
+    pub fn text(&mut self, path: &Path) -> io::Result<String> {
+        let bytes = fs::read(path)?;
+        let plaintext = if path.extension().is_some_and(|e| e == "enc") {
+            let repo_root = self.repo_root;
+            let keys = self
+                .keys
+                .get_or_insert_with(|| read_key_map(repo_root))
+                .as_ref()
+                .map_err(|e| io::Error::new(e.kind(), e.to_string()))?;
+            let binding = binding_for(repo_root, path);
+            let problems = self
+                .problems
+                .get_or_insert_with(|| {
+                    manifest::check(repo_root, keys).map(Option::unwrap_or_default)
+                })
+                .as_ref()
+                .map_err(|e| io::Error::new(e.kind(), e.to_string()))?;
+            if let Some(p) = problems.iter().find(|p| p.path() == binding.path) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, p.to_string()));
+            }
+            crate::signing::verify_file(repo_root, path)?;
+            decrypt_bytes(keys.keyring_for(&binding)?, &bytes, Some(&binding))?
+        } else {
+            bytes
+        };
+        String::from_utf8(plaintext)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not UTF-8"))
+    }
+
+    pub fn document(&mut self, path: &Path) -> io::Result<Document> {
+        dotenv::parse(&self.text(path)?)
+    }
+
+    pub fn vars(&mut self, path: &Path) -> io::Result<Vec<(String, String)>> {
+        Ok(self.document(path)?.vars())
+    }
+}
+
+// The layers with each plaintext file that has a `.enc` sibling read from
+// the `.enc` instead, or `None` when no layer has one: `layers` prefers the
+// plaintext, so this is the only way an out-of-date `.enc` gets checked.
+fn sealed_layers(
+    repo_root: &Path,
+    reader: &mut EnvReader,
+    layers: &[resolve::Layer],
+) -> Option<io::Result<Vec<Document>>> {
+    let mut swapped = false;
+    let docs = layers
+        .iter()
+        .map(|layer| {
+            let name = layer.path.file_name()?.to_str()?;
+            if name.ends_with(".enc") {
+                return None;
+            }
+            let enc = layer.path.with_file_name(format!("{name}.enc"));
+            enc.is_file().then_some(enc)
+        })
+        .zip(layers)
+        .map(|(enc, layer)| match enc {
+            Some(enc) => {
+                swapped = true;
+                reader.document(&enc).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("{}: {e}", binding_for(repo_root, &enc).path),
+                    )
+                })
+            }
+            None => Ok(layer.doc.clone()),
+        })
+        .collect();
+    swapped.then_some(docs)
+}
+
+// Merges `docs` (expanding `${VAR}` references unless `expand` is false),
+// validates the result against `schema` and prints the report under
+// `label`. Returns whether it passed.
+fn check_docs(
+    schema: &[schema::VarSpec],
+    label: &str,
+    docs: io::Result<Vec<Document>>,
+    expand: bool,
+) -> bool {
+    let vars = docs.and_then(|docs| {
+        let docs: Vec<&Document> = docs.iter().collect();
+        if expand {
+            expand::expand(&docs)
+        } else {
+            Ok(expand::merge(&docs))
+        }
+    });
+    let vars = match vars {
+        Ok(vars) => vars,
+        Err(e) => {
+            println!("[check] ✖ {label}: {e}");
+            return false;
+        }
+    };
+    let report = schema::validate(schema, &vars);
+    if report.errors.is_empty() {
+        println!("[check] ✔ {label}");
+    } else {
+        println!("[check] ✖ {label}");
+        for e in &report.errors {
+            println!("    - {e}");
+        }
+    }
+    if !report.defaulted.is_empty() {
+        println!("    defaults used: {}", report.defaulted.join(", "));
+    }
+    if !report.unknown.is_empty() {
+        println!("    not in {SCHEMA_FILE}: {}", report.unknown.join(", "));
+    }
+    report.errors.is_empty()
+}
+
+/// `eenv check`: validates every environment (or only `env`) against
+/// eenv.schema, as `run` would build it: its layers merged, `${VAR}`
+/// references expanded across them unless `expand` is false, and schema
+/// defaults applied. When a layer has both a plaintext file and a `.enc`,
+/// the environment is checked a second time with the `.enc`, as a fresh
+/// clone would see it. Without `env`, an env file that is a layer of no
+/// environment is validated on its own. Only variable names and rules are
+/// printed.
+pub fn run_check(repo_root: &Path, env: Option<&str>, expand: bool) -> io::Result<()> {
+    let schema = schema::load(repo_root)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no {SCHEMA_FILE} in the repo root"),
+        )
+    })?;
+    let (envs, stray) = resolve::environments(repo_root)?;
+    let envs: Vec<resolve::Stack> = envs
+        .into_iter()
+        .filter(|s| env.is_none_or(|e| s.env.as_deref().unwrap_or(DEFAULT_ENV) == e))
+        .collect();
+    if envs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            match env {
+                Some(e) => format!("no .env files for environment \"{e}\""),
+                None => "no .env files found".to_string(),
+            },
+        ));
+    }
+    let mut reader = EnvReader::new(repo_root);
+    let mut failed = 0;
+    if env.is_none() {
+        // a file no environment picks up is checked on its own
+        for path in &stray {
+            let label = format!(
+                "{} (not a layer of any environment, see \"layers\" in eenv.config.json)",
+                binding_for(repo_root, path).path
+            );
+            let docs = reader.document(path).map(|doc| vec![doc]);
+            if !check_docs(&schema, &label, docs, expand) {
+                failed += 1;
+            }
+        }
+    }
+
+    for resolve::Stack { dir, env: name } in &envs {
+        let mut label = name.as_deref().unwrap_or(DEFAULT_ENV).to_string();
+        if dir != repo_root {
+            label = format!("{label} in {}", binding_for(repo_root, dir).path);
+        }
+        let layers = match resolve::layers(repo_root, dir, name.as_deref()) {
+            Ok(layers) => layers,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!("[check] - {label}: skipped ({e})");
+                continue;
+            }
+            Err(e) => {
+                println!("[check] ✖ {label}: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        let working = Ok(layers.iter().map(|l| l.doc.clone()).collect());
+        let sealed = sealed_layers(repo_root, &mut reader, &layers)
+            .map(|docs| (format!("{label} (.enc)"), docs));
+        for (label, docs) in std::iter::once((label, working)).chain(sealed) {
+            if !check_docs(&schema, &label, docs, expand) {
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{failed} environment(s) failed the check"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{scratch_keyed, seal_env};
+
+    // a repo with a schema requiring an int PORT, and `.env` as given
+    fn setup(name: &str, plain: Option<&str>, sealed: Option<&str>) -> std::path::PathBuf {
+        let dir = scratch_keyed(name);
+        fs::write(dir.join(SCHEMA_FILE), r#"{ "PORT": "int" }"#).unwrap();
+        if let Some(plain) = plain {
+            fs::write(dir.join(".env"), plain).unwrap();
+        }
+        if let Some(sealed) = sealed {
+            seal_env(&dir, ".env.enc", sealed);
+        }
+        dir
+    }
+
+    #[test]
+    fn valid_environments_pass() {
+        for (name, plain, sealed) in [
+            ("check-plain", Some("PORT=1\n"), None),
+            ("check-enc", None, Some("PORT=2\n")),
+            ("check-both", Some("PORT=1\n"), Some("PORT=2\n")),
+        ] {
+            let dir = setup(name, plain, sealed);
+            run_check(&dir, None, true).unwrap();
+            let _ = fs::remove_dir_all(&dir);
+        }
+    }
+
+    #[test]
+    fn enc_behind_a_plaintext_sibling_is_checked_too() {
+        let dir = setup("check-stale-enc", Some("PORT=1\n"), Some("PORT=oops\n"));
+        let err = run_check(&dir, None, true).unwrap_err();
+        assert_eq!(err.to_string(), "1 environment(s) failed the check");
+
+        let layers = resolve::layers(&dir, &dir, None).unwrap();
+        let mut reader = EnvReader::new(&dir);
+        let sealed = sealed_layers(&dir, &mut reader, &layers).unwrap().unwrap();
+        assert_eq!(sealed[0].vars(), [("PORT".to_string(), "oops".to_string())]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stray_files_are_checked_on_their_own() {
+        let dir = setup("check-stray", Some("PORT=1\n"), None);
+        let config = serde_json::json!({ "layers": [".env"] });
+        let mut map = crate::config::read_config_object(&dir).unwrap();
+        map.extend(config.as_object().unwrap().clone());
+        crate::config::write_config_object(&dir, map).unwrap();
+        fs::write(dir.join(".env.staging"), "PORT=2\n").unwrap();
+        seal_env(&dir, ".env.production.enc", "PORT=3\n");
+        run_check(&dir, None, true).unwrap();
+
+        seal_env(&dir, ".env.production.enc", "PORT=oops\n");
+        let err = run_check(&dir, None, true).unwrap_err();
+        assert_eq!(err.to_string(), "1 environment(s) failed the check");
+        fs::write(dir.join(".env.staging"), "HOST=db\n").unwrap();
+        let err = run_check(&dir, None, true).unwrap_err();
+        assert_eq!(err.to_string(), "2 environment(s) failed the check");
+        // only checking one environment leaves them out
+        run_check(&dir, Some(DEFAULT_ENV), true).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn invalid_plaintext_fails() {
+        let dir = setup("check-bad-plain", Some("PORT=x\n"), Some("PORT=2\n"));
+        assert!(run_check(&dir, None, true).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
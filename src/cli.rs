@@ -1,9 +1,10 @@
 use clap::{Parser, Subcommand};
 use std::io;
 
-use crate::util::find_repo_root;
-use crate::{hooks, precommit, types::HookAction};
 use crate::about;
+use crate::types::{IdentityAction, ManifestAction};
+use crate::util::find_repo_root;
+use crate::{hooks, keysource, precommit, recipients, types::HookAction};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -14,6 +15,12 @@ pub struct Cli {
     pub name: String,
     #[arg(short, long, default_value_t = 1)]
     pub count: u8,
+    /// Read the key from this file instead of eenv.config.json
+    #[arg(long, global = true, conflicts_with = "key_stdin")]
+    pub key_file: Option<std::path::PathBuf>,
+    /// Read the key from stdin instead of eenv.config.json
+    #[arg(long, global = true)]
+    pub key_stdin: bool,
 }
 
 #[derive(Subcommand, Debug)]
@@ -34,11 +41,136 @@ pub enum Command {
         action: HookAction,
         #[arg(long, default_value_t = false)]
         force: bool,
-    }
+    },
+    /// Create or show this machine's X25519 identity for eenv.recipients
+    Identity {
+        #[arg(value_enum)]
+        action: IdentityAction,
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Create or show this machine's ed25519 signing key for eenv.signers
+    Signer {
+        #[arg(value_enum)]
+        action: IdentityAction,
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Move/rename an encrypted env file and re-bind it to its new path
+    Mv {
+        from: std::path::PathBuf,
+        to: std::path::PathBuf,
+    },
+    /// Encrypt any file (e.g. a keystore or service-account JSON) as a chunked stream
+    Encrypt {
+        file: std::path::PathBuf,
+        /// Output path (default: <file>.enc)
+        #[arg(short, long)]
+        out: Option<std::path::PathBuf>,
+    },
+    /// Decrypt one .enc file
+    Decrypt {
+        file: std::path::PathBuf,
+        /// Output path (default: the input without .enc)
+        #[arg(short, long)]
+        out: Option<std::path::PathBuf>,
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Check eenv.manifest against the .enc files, or re-sign it after intended changes
+    Manifest {
+        #[arg(value_enum)]
+        action: ManifestAction,
+    },
+    /// Run a command with an environment's secrets, decrypted in memory only
+    Run {
+        /// Environment name, filling `{env}` in the layers (.env.<name>, .env.<name>.local)
+        #[arg(long)]
+        env: Option<String>,
+        /// Pass `${VAR}` references through literally instead of expanding them
+        #[arg(long, default_value_t = false)]
+        no_expand: bool,
+        /// Command and arguments, after `--`
+        #[arg(last = true, required = true)]
+        cmd: Vec<String>,
+    },
+    /// Validate env files (and decrypted .enc files) against eenv.schema without printing values
+    Check {
+        /// Only check this environment, as in .env.<name>(.enc) ("default" is .env and .env.local)
+        #[arg(long)]
+        env: Option<String>,
+        /// Check values as written, without expanding `${VAR}` references
+        #[arg(long, default_value_t = false)]
+        no_expand: bool,
+    },
+    /// Compare each .env with its .example and .enc: missing, extra and changed keys (no values)
+    Drift,
+    /// Add keys your .env lacks but its .example or .enc has, asking for each value
+    Sync {
+        /// Take every value from the .enc (or the example) without asking
+        #[arg(short, long, default_value_t = false)]
+        yes: bool,
+    },
+    /// Print an environment's effective variables, merged from its layered env files
+    Resolve {
+        /// Environment name, filling `{env}` in the layers (.env.<name>, .env.<name>.local)
+        #[arg(long)]
+        env: Option<String>,
+        /// Show which file and line each variable comes from and what it overrides, without values
+        #[arg(long, default_value_t = false)]
+        explain: bool,
+        /// Print values as written, without expanding `${VAR}` references
+        #[arg(long, default_value_t = false)]
+        no_expand: bool,
+    },
+    /// Inspect, protect and back up the configured keys
+    Key {
+        #[command(subcommand)]
+        action: KeyCommand,
+    },
+    /// Re-encrypt every .env*.enc under a new key (generated, or from --key-file/--key-stdin) and update eenv.config.json
+    Rotate {
+        /// Rotate the "keys" entry for this environment/glob instead of the default key
+        #[arg(long)]
+        env: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyCommand {
+    /// Show the short id of each configured key and of the key each .enc file uses
+    Fingerprint,
+    /// Encrypt the keys in eenv.config.json under a passphrase (asked once per session)
+    Protect,
+    /// Store the keys in eenv.config.json in plaintext again
+    Unprotect,
+    /// Forget the cached passphrase for this session
+    Lock,
+    /// Print recovery shares of the shared key (Shamir secret sharing)
+    Split {
+        #[arg(long)]
+        shares: u8,
+        #[arg(long)]
+        threshold: u8,
+    },
+    /// Rebuild the key from recovery shares (arguments, or one per line on stdin)
+    Combine { shares: Vec<String> },
 }
 
 pub fn dispatch(cli: Cli) -> io::Result<()> {
-    match cli.command.unwrap_or(Command::About) {
+    let source = match (cli.key_file, cli.key_stdin) {
+        (Some(path), _) => Some(keysource::CliSource::File(path)),
+        (None, true) => Some(keysource::CliSource::Stdin),
+        (None, false) => None,
+    };
+    let command = cli.command.unwrap_or(Command::About);
+    // for `rotate`, --key-file/--key-stdin give the new key rather than the current one
+    let (source, new_key_source) = match command {
+        Command::Rotate { .. } => (None, source),
+        _ => (source, None),
+    };
+    keysource::set_cli_source(source);
+    match command {
         Command::About => {
             about::print_about();
         }
@@ -81,6 +213,166 @@ pub fn dispatch(cli: Cli) -> io::Result<()> {
                 }
             }
         }
+        Command::Identity { action, force } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            let result = match action {
+                IdentityAction::New => recipients::new_identity(&repo_root, force),
+                IdentityAction::Show => recipients::show_identity(&repo_root),
+            };
+            match result {
+                Ok(pk) => {
+                    println!("{}", recipients::encode_public(&pk));
+                    if let IdentityAction::New = action {
+                        eprintln!(
+                            "[identity] add the line above to {} and re-encrypt to grant access",
+                            recipients::RECIPIENTS_FILE
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("[identity] ERROR: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Signer { action, force } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            let result = match action {
+                IdentityAction::New => crate::signing::new_signer(&repo_root, force),
+                IdentityAction::Show => crate::signing::show_signer(&repo_root),
+            };
+            match result {
+                Ok(pk) => {
+                    println!("{}", crate::signing::encode_public(&pk));
+                    if let IdentityAction::New = action {
+                        eprintln!(
+                            "[signer] add `<environment> <key above> # name` to {} to approve it",
+                            crate::signing::SIGNERS_FILE
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("[signer] ERROR: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Mv { from, to } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) = crate::crypto::move_enc(&repo_root, &from, &to) {
+                eprintln!("[mv] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Encrypt { file, out } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) = crate::crypto::encrypt_artifact(&repo_root, &file, out.as_deref()) {
+                eprintln!("[encrypt] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Decrypt { file, out, force } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) =
+                crate::crypto::decrypt_artifact(&repo_root, &file, out.as_deref(), force)
+            {
+                eprintln!("[decrypt] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Manifest { action } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) = crate::manifest::run(&repo_root, action) {
+                eprintln!("[manifest] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Run {
+            env,
+            no_expand,
+            cmd,
+        } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) = crate::run::run(&repo_root, env.as_deref(), &cmd, !no_expand) {
+                eprintln!("[run] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Check { env, no_expand } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) = crate::check::run_check(&repo_root, env.as_deref(), !no_expand) {
+                eprintln!("[check] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Drift => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) = crate::drift::run_drift(&repo_root) {
+                eprintln!("[drift] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Sync { yes } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) = crate::sync::run_sync(&repo_root, yes) {
+                eprintln!("[sync] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Resolve {
+            env,
+            explain,
+            no_expand,
+        } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            if let Err(e) =
+                crate::resolve::run_resolve(&repo_root, env.as_deref(), explain, !no_expand)
+            {
+                eprintln!("[resolve] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Key { action } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            let result = match action {
+                KeyCommand::Fingerprint => crate::fingerprint::print_fingerprints(&repo_root),
+                KeyCommand::Protect => crate::passphrase::protect(&repo_root),
+                KeyCommand::Unprotect => crate::passphrase::unprotect(&repo_root),
+                KeyCommand::Lock => crate::passphrase::lock(&repo_root),
+                KeyCommand::Split { shares, threshold } => {
+                    crate::shamir::run_split(&repo_root, shares, threshold)
+                }
+                KeyCommand::Combine { shares } => crate::shamir::run_combine(&repo_root, shares),
+            };
+            if let Err(e) = result {
+                eprintln!("[key] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
+        Command::Rotate { env } => {
+            let cwd = std::env::current_dir()?;
+            let repo_root = find_repo_root(&cwd)?;
+            let result = new_key_source
+                .map(|s| s.read())
+                .transpose()
+                .and_then(|key| crate::rotate::rotate_key(&repo_root, key, env));
+            if let Err(e) = result {
+                eprintln!("[rotate] ERROR: {e}");
+                std::process::exit(1);
+            }
+        }
     }
     Ok(())
 }
@@ -1,5 +1,13 @@
+use crate::kdf::KdfParams;
+use crate::keyring::{KeyMap, Keyring};
+use crate::keysource::{external_key, has_external};
+use crate::passphrase::{conceal, reveal};
+use crate::recipients::Identity;
+use crate::signing::SigningIdentity;
+use crate::types::EncMode;
 use serde_json::{Value, json};
 use std::{
+    collections::BTreeMap,
     fs, io,
     path::{Path, PathBuf},
 };
@@ -10,7 +18,7 @@ pub fn eenv_config_path(repo_root: &Path) -> PathBuf {
 
 pub fn prompt_for_key() -> io::Result<String> {
     use std::io::Write;
-    print!("eenv: existing eenv.config.json is invalid.\nEnter key to use: ");
+    print!("eenv: existing eenv.config.json is invalid.\nEnter key (or a recovery share) to use: ");
     std::io::stdout().flush()?;
     let mut s = String::new();
     std::io::stdin().read_line(&mut s)?;
@@ -27,13 +35,18 @@ pub fn prompt_for_key() -> io::Result<String> {
 pub fn validate_eenv_config(repo_root: &Path) -> io::Result<bool> {
     let path = eenv_config_path(repo_root);
     if !path.exists() {
-        return Ok(false);
+        return Ok(has_external(&serde_json::Map::new()));
     }
     let text = fs::read_to_string(&path)?;
     match serde_json::from_str::<serde_json::Value>(&text) {
-        Ok(v) if v.is_object() => {
-            Ok(matches!(v.get("key"), Some(serde_json::Value::String(s)) if !s.is_empty()))
-        }
+        Ok(Value::Object(map)) if has_external(&map) => Ok(true),
+        Ok(v) if v.is_object() => Ok(["key", "identity"]
+            .iter()
+            .any(|f| matches!(v.get(f), Some(serde_json::Value::String(s)) if !s.is_empty()))
+            || v.get("keys").and_then(|k| k.as_object()).is_some_and(|k| {
+                k.values()
+                    .any(|x| x.as_str().is_some_and(|s| !s.is_empty()))
+            })),
         _ => Ok(false),
     }
 }
@@ -50,6 +63,10 @@ pub fn ensure_eenv_config(repo_root: &Path) -> io::Result<ConfigStatus> {
     let path = eenv_config_path(repo_root);
 
     if !path.exists() {
+        // the key comes from EENV_KEY or the command line; don't invent one
+        if has_external(&serde_json::Map::new()) {
+            return Ok(ConfigStatus::Valid);
+        }
         let key = super::util::generate_key();
         let pretty = format!("{{\n  \"key\": \"{}\"\n}}\n", key);
         super::util::write_string_atomic(&path, &pretty)?;
@@ -71,7 +88,7 @@ pub fn ensure_eenv_config(repo_root: &Path) -> io::Result<ConfigStatus> {
             let needs_key = match v.get("key") {
                 Some(Value::String(s)) => s.is_empty(),
                 _ => true,
-            };
+            } && !v.as_object().is_some_and(has_external);
 
             if needs_key {
                 let key = super::util::generate_key();
@@ -100,10 +117,63 @@ pub fn ensure_eenv_config(repo_root: &Path) -> io::Result<ConfigStatus> {
     }
 }
 
+/// Sets `"key"`, keeping the other fields of an existing, readable config.
 pub fn write_eenv_config_with_key(repo_root: &Path, key_str: &str) -> io::Result<()> {
-    let path = eenv_config_path(repo_root);
-    let pretty = format!("{{\n  \"key\": \"{}\"\n}}\n", key_str);
-    super::util::write_string_atomic(&path, &pretty)
+    let Ok(mut map) = read_config_object(repo_root) else {
+        let path = eenv_config_path(repo_root);
+        let pretty = format!("{{\n  \"key\": \"{}\"\n}}\n", key_str);
+        return super::util::write_string_atomic(&path, &pretty);
+    };
+    let value = conceal(&map, key_str)?;
+    map.insert("key".into(), value);
+    write_config_object(repo_root, map)
+}
+
+/// The config as a JSON object; empty when there is no eenv.config.json
+/// (e.g. in CI, with the key from `EENV_KEY`).
+pub fn read_config_object(repo_root: &Path) -> io::Result<serde_json::Map<String, Value>> {
+    let text = match fs::read_to_string(eenv_config_path(repo_root)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(serde_json::Map::new()),
+        Err(e) => return Err(e),
+    };
+    match serde_json::from_str::<Value>(&text) {
+        Ok(Value::Object(map)) => Ok(map),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad eenv.config.json: not an object",
+        )),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("bad eenv.config.json: {e}"),
+        )),
+    }
+}
+
+pub fn write_config_object(
+    repo_root: &Path,
+    map: serde_json::Map<String, Value>,
+) -> io::Result<()> {
+    let mut pretty = serde_json::to_string_pretty(&Value::Object(map))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if !pretty.ends_with('\n') {
+        pretty.push('\n');
+    }
+    super::util::write_string_atomic(&eenv_config_path(repo_root), &pretty)
+}
+
+pub fn read_kdf_params(repo_root: &Path) -> io::Result<Option<KdfParams>> {
+    let map = read_config_object(repo_root)?;
+    match map.get("kdf") {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => KdfParams::from_json(v).map(Some),
+    }
+}
+
+pub fn write_kdf_params(repo_root: &Path, params: &KdfParams) -> io::Result<()> {
+    let mut map = read_config_object(repo_root)?;
+    map.insert("kdf".into(), params.to_json());
+    write_config_object(repo_root, map)
 }
 
 pub fn ensure_gitignore_has_config(repo_root: &Path) -> io::Result<()> {
@@ -146,29 +216,202 @@ pub fn ensure_gitignore_has_config(repo_root: &Path) -> io::Result<()> {
     Ok(())
 }
 
-pub fn read_eenv_key(repo_root: &Path) -> io::Result<[u8; 32]> {
-    let cfg_path = eenv_config_path(repo_root);
-    let text = fs::read_to_string(&cfg_path)?;
-    let v: serde_json::Value = serde_json::from_str(&text).map_err(|e| {
-        io::Error::new(
+fn parse_enc_mode(v: &Value) -> io::Result<EncMode> {
+    match v.as_str() {
+        Some("file") => Ok(EncMode::File),
+        Some("values") => Ok(EncMode::Values),
+        Some("age") => Ok(EncMode::Age),
+        _ => Err(io::Error::new(
             io::ErrorKind::InvalidData,
-            format!("bad eenv.config.json: {e}"),
-        )
-    })?;
-    let key_str = v
-        .get("key")
-        .and_then(|x| x.as_str())
+            format!("bad eenv.config.json: mode must be \"file\", \"values\" or \"age\", got {v}"),
+        )),
+    }
+}
+
+/// Encryption mode for one env file: `"modes"` (keyed by repo-relative path
+/// or environment name) wins over the project-wide `"mode"`; default is file.
+pub fn read_enc_mode(repo_root: &Path, rel_path: &str, env: &str) -> io::Result<EncMode> {
+    let map = read_config_object(repo_root)?;
+    if let Some(modes) = map.get("modes").and_then(|m| m.as_object()) {
+        let rel_plain = rel_path.strip_suffix(".enc").unwrap_or(rel_path);
+        if let Some(v) = modes.get(rel_plain).or_else(|| modes.get(env)) {
+            return parse_enc_mode(v);
+        }
+    }
+    match map.get("mode") {
+        Some(v) => parse_enc_mode(v),
+        None => Ok(EncMode::File),
+    }
+}
+
+pub const DEFAULT_LAYERS: [&str; 4] = [".env", ".env.local", ".env.{env}", ".env.{env}.local"];
+
+/// The file names an environment is built from, lowest precedence first:
+/// `"layers"` if set, else [`DEFAULT_LAYERS`]. `{env}` stands for the
+/// environment name.
+pub fn read_layers(repo_root: &Path) -> io::Result<Vec<String>> {
+    let map = read_config_object(repo_root)?;
+    let Some(v) = map.get("layers") else {
+        return Ok(DEFAULT_LAYERS.iter().map(|s| s.to_string()).collect());
+    };
+    v.as_array()
+        .filter(|a| !a.is_empty())
+        .and_then(|a| {
+            a.iter()
+                .map(|l| {
+                    l.as_str()
+                        .filter(|s| !s.contains(['/', '\\']))
+                        .map(String::from)
+                })
+                .collect::<Option<Vec<_>>>()
+        })
         .ok_or_else(|| {
             io::Error::new(
                 io::ErrorKind::InvalidData,
-                "eenv.config.json missing non-empty \"key\"",
+                "bad eenv.config.json: layers must be a non-empty list of file names",
             )
-        })?
-        .trim()
-        .to_string();
-    if key_str.is_empty() {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "empty key"));
-    }
-    let hash = blake3::hash(key_str.as_bytes());
-    Ok(*hash.as_bytes())
+        })
+}
+
+pub fn read_identity(repo_root: &Path) -> io::Result<Option<Identity>> {
+    let map = read_config_object(repo_root)?;
+    match map.get("identity").and_then(|x| x.as_str()) {
+        Some(s) if !s.trim().is_empty() => Identity::parse(&reveal(&map, s)?).map(Some),
+        _ => Ok(None),
+    }
+}
+
+pub fn read_signing_key(repo_root: &Path) -> io::Result<Option<SigningIdentity>> {
+    let map = read_config_object(repo_root)?;
+    match map.get("signing_key").and_then(|x| x.as_str()) {
+        Some(s) if !s.trim().is_empty() => SigningIdentity::parse(&reveal(&map, s)?).map(Some),
+        _ => Ok(None),
+    }
+}
+
+pub fn write_signing_key(repo_root: &Path, key: &SigningIdentity) -> io::Result<()> {
+    let mut map = read_config_object(repo_root)?;
+    let value = conceal(&map, &key.to_secret_string())?;
+    map.insert("signing_key".into(), value);
+    write_config_object(repo_root, map)
+}
+
+pub fn write_identity(repo_root: &Path, identity: &Identity) -> io::Result<()> {
+    let mut map = if eenv_config_path(repo_root).exists() {
+        read_config_object(repo_root)?
+    } else {
+        serde_json::Map::new()
+    };
+    let value = conceal(&map, &identity.to_secret_string())?;
+    map.insert("identity".into(), value);
+    write_config_object(repo_root, map)
+}
+
+/// Sets the `"keys"` entry for `selector`, keeping everything else.
+pub fn write_env_key(repo_root: &Path, selector: &str, key_str: &str) -> io::Result<()> {
+    let mut map = read_config_object(repo_root)?;
+    let value = conceal(&map, key_str)?;
+    let keys = map
+        .entry("keys")
+        .or_insert_with(|| Value::Object(serde_json::Map::new()));
+    let Some(keys) = keys.as_object_mut() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad eenv.config.json: \"keys\" must be an object",
+        ));
+    };
+    keys.insert(selector.to_string(), value);
+    write_config_object(repo_root, map)
+}
+
+/// The default keyring plus one per `"keys"` entry (environment name or path
+/// glob to key). A `null` or empty entry means "not available here".
+pub fn read_key_map(repo_root: &Path) -> io::Result<KeyMap> {
+    let map = read_config_object(repo_root)?;
+    let identities = read_identity(repo_root)?.into_iter().collect::<Vec<_>>();
+    let key_str = match (
+        external_key(&map)?,
+        map.get("key")
+            .and_then(|x| x.as_str())
+            .filter(|k| !k.trim().is_empty()),
+    ) {
+        (Some((k, _)), _) => Some(k),
+        (None, Some(k)) => Some(reveal(&map, k)?),
+        (None, None) => None,
+    };
+    let default = match (key_str, identities.is_empty()) {
+        (None, true) => None,
+        (key_str, _) => Some(Keyring::new(key_str.as_deref(), identities.clone())?),
+    };
+    let mut rules = Vec::new();
+    if let Some(keys) = map.get("keys") {
+        let keys = keys.as_object().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad eenv.config.json: \"keys\" must map environments or globs to keys",
+            )
+        })?;
+        for (selector, v) in keys {
+            let keyring = match v.as_str().filter(|k| !k.trim().is_empty()) {
+                Some(k) => Some(Keyring::new(Some(&reveal(&map, k)?), identities.clone())?),
+                None if v.is_null() || v.is_string() => None,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("bad eenv.config.json: key for \"{selector}\" must be a string"),
+                    ));
+                }
+            };
+            rules.push((selector.clone(), keyring));
+        }
+    }
+    if default.is_none() && rules.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no key: set EENV_KEY, pass --key-file/--key-stdin, or add \"key\" (or \"identity\") to eenv.config.json",
+        ));
+    }
+    Ok(KeyMap::new(default, rules))
+}
+
+/// The highest version of each eenv.manifest entry this machine has verified.
+pub fn read_manifest_seen(repo_root: &Path) -> io::Result<BTreeMap<String, u64>> {
+    let map = read_config_object(repo_root)?;
+    Ok(map
+        .get("manifest_seen")
+        .and_then(|x| x.as_object())
+        .map(|o| {
+            o.iter()
+                .filter_map(|(p, v)| Some((p.clone(), v.as_u64()?)))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+pub fn write_manifest_seen(repo_root: &Path, seen: &BTreeMap<String, u64>) -> io::Result<()> {
+    if !eenv_config_path(repo_root).exists() {
+        return Ok(());
+    }
+    let mut map = read_config_object(repo_root)?;
+    map.remove("manifest_version");
+    map.insert("manifest_seen".into(), json!(seen));
+    write_config_object(repo_root, map)
+}
+
+/// BLAKE3 (hex) of the last eenv.signers this machine accepted.
+pub fn read_signers_seen(repo_root: &Path) -> io::Result<Option<String>> {
+    let map = read_config_object(repo_root)?;
+    Ok(map
+        .get("signers_seen")
+        .and_then(|x| x.as_str())
+        .map(str::to_string))
+}
+
+pub fn write_signers_seen(repo_root: &Path, hash: &str) -> io::Result<()> {
+    if !eenv_config_path(repo_root).exists() {
+        return Ok(());
+    }
+    let mut map = read_config_object(repo_root)?;
+    map.insert("signers_seen".into(), json!(hash));
+    write_config_object(repo_root, map)
 }
@@ -0,0 +1,463 @@
+use crate::kdf::KdfParams;
+use std::io;
+
+// "EENV" followed by the format version as an ASCII digit.
+pub const MAGIC_PREFIX: &[u8; 4] = b"EENV";
+pub const FORMAT_V1: u8 = 1;
+pub const FORMAT_V2: u8 = 2;
+
+pub const NONCE_LEN: usize = 24;
+pub const TAG_LEN: usize = 16;
+pub const KEY_ID_LEN: usize = 8;
+
+/// Plaintext is padded to a multiple of `PAD_BLOCK` to hide exact secret lengths.
+pub const FLAG_PADDED: u8 = 0x01;
+const KNOWN_FLAGS: u8 = FLAG_PADDED;
+const PAD_BLOCK: usize = 64;
+
+// header fields are tag u8 | len u16 LE | value; tags with the high bit set
+// may be skipped by readers that don't know them, any other unknown tag is an error
+const TAG_SUITE: u8 = 0x01;
+const TAG_KDF: u8 = 0x02;
+const TAG_KEY_ID: u8 = 0x03;
+const TAG_FLAGS: u8 = 0x04;
+const TAG_PATH: u8 = 0x05;
+const TAG_ENV: u8 = 0x06;
+const TAG_RECIPIENT: u8 = 0x07;
+const TAG_OPTIONAL: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherSuite {
+    XChaCha20Poly1305,
+    /// Chunked XChaCha20-Poly1305 STREAM for large files (see `stream`).
+    XChaCha20Poly1305Stream,
+}
+
+impl CipherSuite {
+    pub fn id(self) -> u8 {
+        match self {
+            CipherSuite::XChaCha20Poly1305 => 1,
+            CipherSuite::XChaCha20Poly1305Stream => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> io::Result<Self> {
+        match id {
+            1 => Ok(CipherSuite::XChaCha20Poly1305),
+            2 => Ok(CipherSuite::XChaCha20Poly1305Stream),
+            other => Err(invalid(format!("unsupported cipher suite {other}"))),
+        }
+    }
+}
+
+/// Where a ciphertext is meant to live: the normalized repo-relative path of
+/// the `.enc` file and its environment name. Authenticated as part of the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub path: String,
+    pub env: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: u8,
+    pub suite: CipherSuite,
+    /// Set when the content key comes from the shared key.
+    pub kdf: Option<KdfParams>,
+    pub key_id: Option<[u8; KEY_ID_LEN]>,
+    pub flags: u8,
+    pub binding: Option<Binding>,
+    /// One wrapped file key per recipient (see `recipients::wrap`).
+    pub recipients: Vec<Vec<u8>>,
+}
+
+impl Header {
+    /// A V2 header with no key material yet; callers fill in `kdf`/`key_id`
+    /// or `recipients`.
+    pub fn new(binding: Binding) -> Self {
+        Header {
+            version: FORMAT_V2,
+            suite: CipherSuite::XChaCha20Poly1305,
+            kdf: None,
+            key_id: None,
+            flags: FLAG_PADDED,
+            binding: Some(binding),
+            recipients: Vec::new(),
+        }
+    }
+
+    fn legacy() -> Self {
+        Header {
+            version: FORMAT_V1,
+            suite: CipherSuite::XChaCha20Poly1305,
+            kdf: Some(KdfParams::legacy()),
+            key_id: None,
+            flags: 0,
+            binding: None,
+            recipients: Vec::new(),
+        }
+    }
+
+    // MAGIC | header_len u16 LE | fields
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let mut fields = Vec::new();
+        push_field(&mut fields, TAG_SUITE, &[self.suite.id()])?;
+        if let Some(params) = &self.kdf {
+            let mut kdf = Vec::new();
+            params.encode(&mut kdf);
+            push_field(&mut fields, TAG_KDF, &kdf)?;
+        }
+        for stanza in &self.recipients {
+            push_field(&mut fields, TAG_RECIPIENT, stanza)?;
+        }
+        if let Some(id) = &self.key_id {
+            push_field(&mut fields, TAG_KEY_ID, id)?;
+        }
+        push_field(&mut fields, TAG_FLAGS, &[self.flags])?;
+        if let Some(b) = &self.binding {
+            push_field(&mut fields, TAG_PATH, b.path.as_bytes())?;
+            push_field(&mut fields, TAG_ENV, b.env.as_bytes())?;
+        }
+
+        let mut out = Vec::with_capacity(MAGIC_PREFIX.len() + 3 + fields.len());
+        out.extend_from_slice(MAGIC_PREFIX);
+        out.push(b'0' + self.version);
+        out.extend_from_slice(&u16_len(fields.len(), "header")?.to_le_bytes());
+        out.extend_from_slice(&fields);
+        Ok(out)
+    }
+}
+
+fn u16_len(len: usize, what: &str) -> io::Result<u16> {
+    u16::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{what} too large for the .enc header ({len} bytes, max 65535)"),
+        )
+    })
+}
+
+fn push_field(out: &mut Vec<u8>, tag: u8, value: &[u8]) -> io::Result<()> {
+    out.push(tag);
+    out.extend_from_slice(&u16_len(value.len(), "header field")?.to_le_bytes());
+    out.extend_from_slice(value);
+    Ok(())
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// A parsed container: `aad` is the authenticated header bytes (empty for
+/// EENV1), `body` is nonce | ciphertext.
+pub struct Parsed<'a> {
+    pub header: Header,
+    pub aad: &'a [u8],
+    pub body: &'a [u8],
+}
+
+pub fn is_container(data: &[u8]) -> bool {
+    data.len() > MAGIC_PREFIX.len()
+        && &data[..MAGIC_PREFIX.len()] == MAGIC_PREFIX
+        && data[MAGIC_PREFIX.len()].is_ascii_digit()
+}
+
+pub fn parse(data: &[u8]) -> io::Result<Parsed<'_>> {
+    let (header, header_end) = parse_header(data)?;
+    let body = &data[header_end..];
+    let min_body = match header.suite {
+        CipherSuite::XChaCha20Poly1305 => NONCE_LEN,
+        CipherSuite::XChaCha20Poly1305Stream => crate::stream::PREFIX_LEN,
+    } + TAG_LEN;
+    if body.len() < min_body {
+        return Err(invalid("enc file too short"));
+    }
+    let aad = if header.version == FORMAT_V1 {
+        &data[..0]
+    } else {
+        &data[..header_end]
+    };
+    Ok(Parsed { header, aad, body })
+}
+
+/// Parses just the header, returning it and the offset where the body starts.
+pub fn parse_header(data: &[u8]) -> io::Result<(Header, usize)> {
+    if !is_container(data) {
+        return Err(invalid("bad magic/version"));
+    }
+    let version = data[MAGIC_PREFIX.len()] - b'0';
+    let prefix = MAGIC_PREFIX.len() + 1;
+    match version {
+        FORMAT_V1 => Ok((Header::legacy(), prefix)),
+        FORMAT_V2 => parse_v2(data, prefix),
+        other => Err(invalid(format!(
+            "unsupported eenv format version {other} (upgrade eenv?)"
+        ))),
+    }
+}
+
+fn parse_v2(data: &[u8], prefix: usize) -> io::Result<(Header, usize)> {
+    if data.len() < prefix + 2 {
+        return Err(invalid("truncated header"));
+    }
+    let len = u16::from_le_bytes([data[prefix], data[prefix + 1]]) as usize;
+    let start = prefix + 2;
+    let end = start + len;
+    if data.len() < end {
+        return Err(invalid("truncated header"));
+    }
+
+    let mut suite = None;
+    let mut kdf = None;
+    let mut key_id = None;
+    let mut flags = 0u8;
+    let mut path = None;
+    let mut env = None;
+    let mut recipients = Vec::new();
+    let mut i = start;
+    while i < end {
+        if end - i < 3 {
+            return Err(invalid("truncated header field"));
+        }
+        let tag = data[i];
+        let flen = u16::from_le_bytes([data[i + 1], data[i + 2]]) as usize;
+        let value = data
+            .get(i + 3..i + 3 + flen)
+            .filter(|_| i + 3 + flen <= end)
+            .ok_or_else(|| invalid("truncated header field"))?;
+        match tag {
+            TAG_SUITE if flen == 1 => suite = Some(CipherSuite::from_id(value[0])?),
+            TAG_KDF => kdf = Some(KdfParams::decode(value)?.0),
+            TAG_KEY_ID if flen == KEY_ID_LEN => {
+                let mut id = [0u8; KEY_ID_LEN];
+                id.copy_from_slice(value);
+                key_id = Some(id);
+            }
+            TAG_FLAGS if flen == 1 => flags = value[0],
+            TAG_PATH => path = Some(utf8_field(value)?),
+            TAG_ENV => env = Some(utf8_field(value)?),
+            TAG_RECIPIENT => recipients.push(value.to_vec()),
+            t if t & TAG_OPTIONAL != 0 => {}
+            t => {
+                return Err(invalid(format!(
+                    "unknown or malformed header field {t:#04x}"
+                )));
+            }
+        }
+        i += 3 + flen;
+    }
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(invalid(format!("unsupported header flags {flags:#04x}")));
+    }
+    if kdf.is_none() == recipients.is_empty() {
+        return Err(invalid("header needs either kdf params or recipients"));
+    }
+    let header = Header {
+        version: FORMAT_V2,
+        suite: suite.ok_or_else(|| invalid("header missing cipher suite"))?,
+        kdf,
+        key_id,
+        flags,
+        binding: match (path, env) {
+            (Some(path), Some(env)) => Some(Binding { path, env }),
+            (None, None) => None,
+            _ => return Err(invalid("header has a partial path binding")),
+        },
+        recipients,
+    };
+    Ok((header, end))
+}
+
+fn utf8_field(value: &[u8]) -> io::Result<String> {
+    String::from_utf8(value.to_vec()).map_err(|_| invalid("header field is not utf-8"))
+}
+
+pub fn pad(plaintext: &[u8]) -> Vec<u8> {
+    let padded_len = (plaintext.len() / PAD_BLOCK + 1) * PAD_BLOCK;
+    let mut out = Vec::with_capacity(padded_len);
+    out.extend_from_slice(plaintext);
+    out.push(0x80);
+    out.resize(padded_len, 0);
+    out
+}
+
+pub fn unpad(mut padded: Vec<u8>) -> io::Result<Vec<u8>> {
+    while padded.last() == Some(&0) {
+        padded.pop();
+    }
+    if padded.pop() != Some(0x80) {
+        return Err(invalid("bad padding"));
+    }
+    Ok(padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding() -> Binding {
+        Binding {
+            path: "apps/web/.env.production.enc".into(),
+            env: "production".into(),
+        }
+    }
+
+    fn kdf_header() -> Header {
+        let mut h = Header::new(binding());
+        h.kdf = Some(KdfParams::generate());
+        h.key_id = Some([7; KEY_ID_LEN]);
+        h
+    }
+
+    // header bytes with `fields` appended to those of `kdf_header()`
+    fn with_extra_field(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut data = kdf_header().encode().unwrap();
+        push_field(&mut data, tag, value).unwrap();
+        let len = u16::from_le_bytes([data[5], data[6]]) + 3 + value.len() as u16;
+        data[5..7].copy_from_slice(&len.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let h = kdf_header();
+        let mut data = h.encode().unwrap();
+        let aad_len = data.len();
+        data.extend_from_slice(&[0; NONCE_LEN + TAG_LEN]);
+
+        let parsed = parse(&data).unwrap();
+        assert_eq!(parsed.aad, &data[..aad_len]);
+        assert_eq!(parsed.body.len(), NONCE_LEN + TAG_LEN);
+        assert_eq!(parsed.header.version, FORMAT_V2);
+        assert_eq!(parsed.header.suite, CipherSuite::XChaCha20Poly1305);
+        assert_eq!(parsed.header.kdf, h.kdf);
+        assert_eq!(parsed.header.key_id, h.key_id);
+        assert_eq!(parsed.header.flags, FLAG_PADDED);
+        assert_eq!(parsed.header.binding, Some(binding()));
+    }
+
+    #[test]
+    fn recipients_round_trip() {
+        let mut h = Header::new(binding());
+        h.recipients = vec![vec![1; 80], vec![2; 80]];
+        let (parsed, _) = parse_header(&h.encode().unwrap()).unwrap();
+        assert!(parsed.kdf.is_none());
+        assert_eq!(parsed.recipients, h.recipients);
+    }
+
+    #[test]
+    fn v1_has_no_aad() {
+        let mut data = b"EENV1".to_vec();
+        data.extend_from_slice(&[0; NONCE_LEN + TAG_LEN]);
+        let parsed = parse(&data).unwrap();
+        assert_eq!(parsed.header.version, FORMAT_V1);
+        assert!(parsed.aad.is_empty());
+        assert_eq!(parsed.body.len(), NONCE_LEN + TAG_LEN);
+    }
+
+    #[test]
+    fn v2_refuses_the_v1_kdf() {
+        let mut h = kdf_header();
+        h.kdf = Some(KdfParams::legacy());
+        let err = parse_header(&h.encode().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("EENV1"), "{err}");
+        // while EENV1 itself still reads with it
+        let (v1, _) = parse_header(b"EENV1").unwrap();
+        assert_eq!(v1.kdf, Some(KdfParams::legacy()));
+    }
+
+    #[test]
+    fn rejects_bad_magic_and_versions() {
+        assert!(parse_header(b"NOPE2....").is_err());
+        assert!(parse_header(b"EENV").is_err());
+        let err = parse_header(b"EENV9\0\0").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("unsupported eenv format version 9")
+        );
+    }
+
+    #[test]
+    fn rejects_truncation() {
+        let data = kdf_header().encode().unwrap();
+        for cut in 0..data.len() {
+            assert!(parse_header(&data[..cut]).is_err(), "accepted {cut} bytes");
+        }
+        // a complete header without a body
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn field_length_past_header_end_is_rejected() {
+        let mut data = kdf_header().encode().unwrap();
+        // shrink the header length so the last field runs past it
+        let len = u16::from_le_bytes([data[5], data[6]]) - 1;
+        data[5..7].copy_from_slice(&len.to_le_bytes());
+        assert!(parse_header(&data).is_err());
+    }
+
+    #[test]
+    fn unknown_fields() {
+        assert!(parse_header(&with_extra_field(0x7f, b"x")).is_err());
+        let (h, _) = parse_header(&with_extra_field(TAG_OPTIONAL | 0x10, b"x")).unwrap();
+        assert_eq!(h.binding, Some(binding()));
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        let mut h = kdf_header();
+        h.flags = 0x02;
+        let err = parse_header(&h.encode().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("unsupported header flags"));
+    }
+
+    #[test]
+    fn needs_exactly_one_key_source() {
+        let neither = Header::new(binding());
+        assert!(parse_header(&neither.encode().unwrap()).is_err());
+        let mut both = kdf_header();
+        both.recipients = vec![vec![1; 80]];
+        assert!(parse_header(&both.encode().unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_partial_binding() {
+        let mut h = kdf_header();
+        h.binding = None;
+        let mut data = h.encode().unwrap();
+        let extra = [TAG_PATH, 1, 0, b'x'];
+        data.extend_from_slice(&extra);
+        let len = u16::from_le_bytes([data[5], data[6]]) + extra.len() as u16;
+        data[5..7].copy_from_slice(&len.to_le_bytes());
+        let err = parse_header(&data).unwrap_err();
+        assert!(err.to_string().contains("partial path binding"));
+    }
+
+    #[test]
+    fn oversized_fields_are_an_error() {
+        let mut h = kdf_header();
+        h.binding = Some(Binding {
+            path: "a/".repeat(40_000),
+            env: "default".into(),
+        });
+        let err = h.encode().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // fields that fit on their own but not together
+        let mut h = Header::new(binding());
+        h.recipients = vec![vec![0; 40_000], vec![0; 40_000]];
+        assert_eq!(h.encode().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn padding_round_trips() {
+        for len in [0, 1, 63, 64, 65, 200] {
+            let plain = vec![0xab; len];
+            let padded = pad(&plain);
+            assert_eq!(padded.len() % PAD_BLOCK, 0);
+            assert!(padded.len() > len);
+            assert_eq!(unpad(padded).unwrap(), plain);
+        }
+        assert!(unpad(vec![1, 2, 0, 0]).is_err());
+    }
+}
@@ -1,14 +1,30 @@
-use crate::config::{ensure_gitignore_has_config, read_eenv_key, write_eenv_config_with_key};
-use crate::envscan::{find_env_files_recursive, split_env_files};
-use crate::util::write_bytes_atomic;
+use crate::agefmt;
+use crate::config::{
+    ensure_gitignore_has_config, read_enc_mode, read_kdf_params, read_key_map,
+    write_eenv_config_with_key, write_kdf_params,
+};
+use crate::container::{self, Binding, CipherSuite, FLAG_PADDED, Header, MAGIC_PREFIX, NONCE_LEN};
+use crate::envscan::{env_name_for, find_env_files_recursive, split_env_files};
+use crate::kdf::{KdfAlg, KdfParams};
+use crate::keyring::{KeyScheme, Keyring, fingerprint};
+use crate::recipients::read_recipients;
+use crate::types::EncMode;
+use crate::util::{write_bytes_atomic, write_stream_atomic};
+use crate::{manifest, shamir, signing, stream, valuecrypt};
 use chacha20poly1305::{
     XChaCha20Poly1305, XNonce,
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
 };
 use rand::Rng;
-use std::{fs, io, path::Path};
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
 
-pub const MAGIC: &[u8; 5] = b"EENV1";
+/// Plain (file mode) env files bigger than this are encrypted as a chunked
+/// stream so they never have to fit in memory.
+pub const STREAM_THRESHOLD: u64 = 1024 * 1024;
 
 pub fn enc_output_path(input: &std::path::Path) -> std::path::PathBuf {
     let mut name = input
@@ -29,61 +45,407 @@ pub fn dec_output_path(input_enc: &std::path::Path) -> std::path::PathBuf {
     }
 }
 
-pub fn encrypt_file_to_enc(aead: &XChaCha20Poly1305, src: &Path, dst: &Path) -> io::Result<()> {
-    let plaintext = fs::read(src)?;
-    let nonce_bytes: [u8; 24] = rand::rng().random();
+pub fn binding_for(repo_root: &Path, enc_path: &Path) -> Binding {
+    let path = match enc_path.strip_prefix(repo_root) {
+        Ok(rel) => rel.to_string_lossy().replace('\\', "/"),
+        Err(_) => enc_path
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    Binding {
+        path,
+        env: env_name_for(enc_path),
+    }
+}
+
+pub fn encrypt_bytes(
+    key: &Keyring,
+    scheme: &KeyScheme,
+    plaintext: &[u8],
+    binding: &Binding,
+) -> io::Result<Vec<u8>> {
+    let (header, content_key) = scheme.new_header(key, binding)?;
+    let aead = XChaCha20Poly1305::new((&content_key).into());
+    let mut out = header.encode()?;
+    let aad_len = out.len();
+    let padded = container::pad(plaintext);
+    let nonce_bytes: [u8; NONCE_LEN] = rand::rng().random();
     let nonce = XNonce::from_slice(&nonce_bytes);
-    let mut out = Vec::with_capacity(MAGIC.len() + nonce_bytes.len() + plaintext.len() + 32);
-    out.extend_from_slice(MAGIC);
-    out.extend_from_slice(&nonce_bytes);
     let ciphertext = aead
-        .encrypt(nonce, plaintext.as_ref())
-        .map_err(|_| io::Error::new(io::ErrorKind::Other, "encrypt failed"))?;
+        .encrypt(
+            nonce,
+            Payload {
+                msg: &padded,
+                aad: &out[..aad_len],
+            },
+        )
+        .map_err(|_| io::Error::other("encrypt failed"))?;
+    out.extend_from_slice(&nonce_bytes);
     out.extend_from_slice(&ciphertext);
-    write_bytes_atomic(dst, &out)
+    Ok(out)
+}
+
+/// Which format an encrypted file is in, judging by its first bytes.
+pub fn detect_mode(data: &[u8]) -> EncMode {
+    if valuecrypt::is_values_file(data) {
+        EncMode::Values
+    } else if agefmt::is_age_file(data) {
+        EncMode::Age
+    } else {
+        EncMode::File
+    }
+}
+
+/// Decrypts a container, values file or age file. With `expected` set, a
+/// ciphertext bound to another path or environment is rejected even though
+/// it authenticates (age files carry no binding).
+pub fn decrypt_bytes(
+    key: &Keyring,
+    data: &[u8],
+    expected: Option<&Binding>,
+) -> io::Result<Vec<u8>> {
+    match detect_mode(data) {
+        EncMode::Values => return valuecrypt::decrypt_values(key, data, expected),
+        EncMode::Age => return agefmt::decrypt_age(key, data),
+        EncMode::File => {}
+    }
+    let parsed = container::parse(data)?;
+    if parsed.header.suite == CipherSuite::XChaCha20Poly1305Stream {
+        let mut out = Vec::new();
+        let base = data.len() - parsed.body.len();
+        let aead = key.aead(&parsed.header)?;
+        stream::decrypt_stream(&aead, parsed.aad, base, parsed.body, &mut out)?;
+        check_binding(&parsed.header, expected)?;
+        return Ok(out);
+    }
+    let (nonce_bytes, ciphertext) = parsed.body.split_at(NONCE_LEN);
+    let plaintext = key
+        .aead(&parsed.header)?
+        .decrypt(
+            XNonce::from_slice(nonce_bytes),
+            Payload {
+                msg: ciphertext,
+                aad: parsed.aad,
+            },
+        )
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "decrypt failed (wrong key?)"))?;
+    check_binding(&parsed.header, expected)?;
+    if parsed.header.flags & FLAG_PADDED != 0 {
+        container::unpad(plaintext)
+    } else {
+        Ok(plaintext)
+    }
+}
+
+pub fn check_binding(header: &Header, expected: Option<&Binding>) -> io::Result<()> {
+    if let (Some(found), Some(want)) = (&header.binding, expected)
+        && found != want
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "this ciphertext belongs to {} (env {}), not {}; use `eenv mv` to re-bind it",
+                found.path, found.env, want.path
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub fn encrypt_for_mode(
+    mode: EncMode,
+    key: &Keyring,
+    scheme: &KeyScheme,
+    plaintext: &[u8],
+    binding: &Binding,
+    previous: Option<&[u8]>,
+) -> io::Result<Vec<u8>> {
+    match mode {
+        EncMode::File => encrypt_bytes(key, scheme, plaintext, binding),
+        EncMode::Values => valuecrypt::encrypt_values(key, scheme, plaintext, binding, previous),
+        EncMode::Age => agefmt::encrypt_age(key, scheme, plaintext),
+    }
+}
+
+/// Encrypts `plaintext` in the same format as `existing` (mode, and stream vs
+/// one-shot container), e.g. when re-keying or re-binding a file.
+pub fn encrypt_like(
+    existing: &[u8],
+    key: &Keyring,
+    scheme: &KeyScheme,
+    plaintext: &[u8],
+    binding: &Binding,
+) -> io::Result<Vec<u8>> {
+    let mode = detect_mode(existing);
+    let streamed = mode == EncMode::File
+        && container::parse_header(existing)
+            .is_ok_and(|(h, _)| h.suite == CipherSuite::XChaCha20Poly1305Stream);
+    if !streamed {
+        return encrypt_for_mode(mode, key, scheme, plaintext, binding, None);
+    }
+    let (aad, aead) = stream_header(key, scheme, binding)?;
+    let mut out = aad.clone();
+    stream::encrypt_stream(&aead, &aad, plaintext, &mut out)?;
+    Ok(out)
+}
+
+/// Whether `data` looks like any format eenv writes.
+pub fn is_eenv_file(data: &[u8]) -> bool {
+    container::is_container(data) || detect_mode(data) != EncMode::File
+}
+
+pub fn read_header(data: &[u8]) -> io::Result<Header> {
+    if valuecrypt::is_values_file(data) {
+        valuecrypt::read_header(data)
+    } else {
+        Ok(container::parse_header(data)?.0)
+    }
+}
+
+// True when `existing` already holds `plaintext` under this key, binding, key
+// scheme and mode, so re-encrypting would only churn the nonce. age files
+// can't name their recipients, so any change to them (`recipients_changed`)
+// makes an age file stale.
+fn enc_is_current(
+    mode: EncMode,
+    key: &Keyring,
+    scheme: &KeyScheme,
+    existing: &[u8],
+    plaintext: &[u8],
+    binding: &Binding,
+    recipients_changed: bool,
+) -> bool {
+    if detect_mode(existing) != mode {
+        return false;
+    }
+    let same_scheme = match mode {
+        EncMode::Age => !recipients_changed && agefmt::matches_scheme(existing, scheme),
+        _ => read_header(existing).is_ok_and(|h| scheme.matches(&h)),
+    };
+    if !same_scheme {
+        return false;
+    }
+    matches!(decrypt_bytes(key, existing, Some(binding)), Ok(old) if old == plaintext)
+}
+
+/// Encrypts `src` into `dst` unless `dst` already decrypts to the same
+/// plaintext. Returns whether `dst` was written. `recipients_changed` says
+/// eenv.recipients changed since HEAD, which age files can't tell by
+/// themselves.
+pub fn encrypt_file_to_enc(
+    mode: EncMode,
+    key: &Keyring,
+    scheme: &KeyScheme,
+    src: &Path,
+    dst: &Path,
+    binding: &Binding,
+    recipients_changed: bool,
+) -> io::Result<bool> {
+    if mode == EncMode::File && fs::metadata(src)?.len() > STREAM_THRESHOLD {
+        return encrypt_file_streaming(key, scheme, src, dst, binding);
+    }
+    let plaintext = fs::read(src)?;
+    let previous = fs::read(dst).ok();
+    if let Some(prev) = &previous
+        && enc_is_current(
+            mode,
+            key,
+            scheme,
+            prev,
+            &plaintext,
+            binding,
+            recipients_changed,
+        )
+    {
+        return Ok(false);
+    }
+    let out = encrypt_for_mode(mode, key, scheme, &plaintext, binding, previous.as_deref())?;
+    write_bytes_atomic(dst, &out)?;
+    Ok(true)
 }
 
 pub fn decrypt_file_from_enc(
-    aead: &XChaCha20Poly1305,
+    key: &Keyring,
     src_enc: &Path,
     dst: &Path,
+    binding: &Binding,
 ) -> io::Result<()> {
+    if let Some((header, aad)) = read_file_header(src_enc)?
+        && header.suite == CipherSuite::XChaCha20Poly1305Stream
+    {
+        check_binding(&header, Some(binding))?;
+        let aead = key.aead(&header)?;
+        let mut input = io::BufReader::new(fs::File::open(src_enc)?);
+        input.seek_relative(aad.len() as i64)?;
+        return write_stream_atomic(dst, |w| {
+            stream::decrypt_stream(&aead, &aad, aad.len(), input, w)
+        });
+    }
     let data = fs::read(src_enc)?;
-    if data.len() < MAGIC.len() + 24 + 16 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "enc file too short",
-        ));
+    let plaintext = decrypt_bytes(key, &data, Some(binding))?;
+    write_bytes_atomic(dst, &plaintext)
+}
+
+/// Header and header bytes of an EENV2 container, read without loading the
+/// rest of the file; `None` for any other format.
+fn read_file_header(path: &Path) -> io::Result<Option<(Header, Vec<u8>)>> {
+    let mut f = fs::File::open(path)?;
+    let mut buf = vec![0u8; MAGIC_PREFIX.len() + 3];
+    if f.read_exact(&mut buf).is_err()
+        || !container::is_container(&buf)
+        || buf[MAGIC_PREFIX.len()] != b'0' + container::FORMAT_V2
+    {
+        return Ok(None);
     }
-    if &data[..MAGIC.len()] != MAGIC {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "bad magic/version",
-        ));
+    let len = u16::from_le_bytes([buf[buf.len() - 2], buf[buf.len() - 1]]) as usize;
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    f.read_exact(&mut buf[start..])?;
+    let (header, _) = container::parse_header(&buf)?;
+    Ok(Some((header, buf)))
+}
+
+// encoded header for a new stream and the AEAD its chunks are sealed with
+fn stream_header(
+    key: &Keyring,
+    scheme: &KeyScheme,
+    binding: &Binding,
+) -> io::Result<(Vec<u8>, XChaCha20Poly1305)> {
+    let (mut header, content_key) = scheme.new_header(key, binding)?;
+    header.suite = CipherSuite::XChaCha20Poly1305Stream;
+    header.flags = 0;
+    Ok((
+        header.encode()?,
+        XChaCha20Poly1305::new((&content_key).into()),
+    ))
+}
+
+/// Streaming counterpart of `encrypt_file_to_enc` for file mode: same
+/// skip-if-unchanged check, but compares BLAKE3 hashes instead of buffers.
+pub fn encrypt_file_streaming(
+    key: &Keyring,
+    scheme: &KeyScheme,
+    src: &Path,
+    dst: &Path,
+    binding: &Binding,
+) -> io::Result<bool> {
+    if let Ok(Some((header, aad))) = read_file_header(dst)
+        && header.suite == CipherSuite::XChaCha20Poly1305Stream
+        && scheme.matches(&header)
+        && header.binding.as_ref() == Some(binding)
+    {
+        let mut wanted = blake3::Hasher::new();
+        wanted.update_reader(fs::File::open(src)?)?;
+        let mut current = blake3::Hasher::new();
+        let mut input = io::BufReader::new(fs::File::open(dst)?);
+        input.seek_relative(aad.len() as i64)?;
+        let same = key.aead(&header).is_ok_and(|aead| {
+            stream::decrypt_stream(&aead, &aad, aad.len(), input, &mut current).is_ok()
+        }) && current.finalize() == wanted.finalize();
+        if same {
+            return Ok(false);
+        }
+    }
+
+    let (aad, aead) = stream_header(key, scheme, binding)?;
+    let input = io::BufReader::new(fs::File::open(src)?);
+    write_stream_atomic(dst, |w| {
+        use std::io::Write;
+        w.write_all(&aad)?;
+        stream::encrypt_stream(&aead, &aad, input, w)
+    })?;
+    Ok(true)
+}
+
+/// The repo's KDF params if it has any yet: the ones in eenv.config.json,
+/// else the ones already used by its .enc files. Never writes anything.
+pub fn existing_repo_kdf_params(repo_root: &Path) -> io::Result<Option<KdfParams>> {
+    if let Some(params) = read_kdf_params(repo_root)? {
+        return Ok(Some(params));
+    }
+    let files = find_env_files_recursive(repo_root)?;
+    let (_real, _examples, encs) = split_env_files(files);
+    Ok(encs.iter().find_map(|p| {
+        let data = fs::read(p).ok()?;
+        let params = read_header(&data).ok()?.kdf?;
+        (params.alg == KdfAlg::Argon2id).then_some(params)
+    }))
+}
+
+/// KDF params for new ciphertexts: the existing ones, else a fresh salt.
+/// Persisted to config when there is one.
+pub fn ensure_repo_kdf_params(repo_root: &Path) -> io::Result<KdfParams> {
+    if let Some(params) = read_kdf_params(repo_root)? {
+        return Ok(params);
+    }
+    let params = existing_repo_kdf_params(repo_root)?.unwrap_or_else(KdfParams::generate);
+    // without a config (key from EENV_KEY etc.) there is nowhere to keep it
+    if crate::config::eenv_config_path(repo_root).exists() {
+        write_kdf_params(repo_root, &params)?;
+    }
+    Ok(params)
+}
+
+/// Recipients from `eenv.recipients` when that file lists any, else the shared key.
+pub fn repo_key_scheme(repo_root: &Path) -> io::Result<KeyScheme> {
+    let pks = read_recipients(repo_root)?;
+    if pks.is_empty() {
+        Ok(KeyScheme::Shared(ensure_repo_kdf_params(repo_root)?))
+    } else {
+        Ok(KeyScheme::Recipients(pks))
     }
-    let nonce_bytes = &data[MAGIC.len()..MAGIC.len() + 24];
-    let nonce = XNonce::from_slice(nonce_bytes);
-    let ciphertext = &data[MAGIC.len() + 24..];
-    let plaintext = aead
-        .decrypt(nonce, ciphertext)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "decrypt failed (wrong key?)"))?;
-    write_bytes_atomic(dst, &plaintext)
 }
 
 pub fn handle_enc_workflow(repo_root: &Path) -> io::Result<()> {
-    let key = read_eenv_key(repo_root)?;
-    let aead = XChaCha20Poly1305::new((&key).into());
+    let keys = read_key_map(repo_root)?;
 
     let files = find_env_files_recursive(repo_root)?;
     let (_real, _examples, encs) = split_env_files(files);
 
+    // never decrypt a ciphertext the manifest doesn't vouch for
+    let mut untrusted = Vec::new();
+    match manifest::check(repo_root, &keys)? {
+        Some(problems) => {
+            for p in &problems {
+                eprintln!("[manifest] WARN: {p}");
+            }
+            untrusted.extend(problems.iter().map(|p| p.path().to_string()));
+        }
+        None if !encs.is_empty() => eprintln!(
+            "[manifest] no {} yet; `eenv pre-commit --write` creates one",
+            manifest::MANIFEST_FILE
+        ),
+        None => {}
+    }
+
     for enc_path in encs {
         let dst = dec_output_path(&enc_path);
         if dst.exists() {
             eprintln!("[enc] skip decrypt (target exists): {}", dst.display());
             continue;
         }
-        match decrypt_file_from_enc(&aead, &enc_path, &dst) {
+        let binding = binding_for(repo_root, &enc_path);
+        if untrusted.contains(&binding.path) {
+            eprintln!(
+                "[enc] skip decrypt {}: does not match {}",
+                enc_path.display(),
+                manifest::MANIFEST_FILE
+            );
+            continue;
+        }
+        if let Err(e) = signing::verify_file(repo_root, &enc_path) {
+            eprintln!("[enc] skip decrypt {}: {e}", enc_path.display());
+            continue;
+        }
+        let key = match keys.keyring_for(&binding) {
+            Ok(k) => k,
+            Err(e) => {
+                eprintln!("[enc] skip decrypt {}: {e}", enc_path.display());
+                continue;
+            }
+        };
+        match decrypt_file_from_enc(key, &enc_path, &dst, &binding) {
             Ok(()) => println!(
                 "[enc] decrypted {} -> {}",
                 enc_path.display(),
@@ -103,8 +465,12 @@ pub fn encrypt_envs_to_enc(
     repo_root: &Path,
     real_envs: &[std::path::PathBuf],
 ) -> io::Result<Vec<std::path::PathBuf>> {
-    let key = read_eenv_key(repo_root)?;
-    let aead = XChaCha20Poly1305::new((&key).into());
+    let keys = read_key_map(repo_root)?;
+    let scheme = repo_key_scheme(repo_root)?;
+    let recipients_changed = match scheme {
+        KeyScheme::Recipients(_) => crate::recipients::changed_since_head(repo_root)?,
+        KeyScheme::Shared(_) => false,
+    };
     let mut produced = Vec::new();
     for src in real_envs {
         let Some(name) = src.file_name().and_then(|s| s.to_str()) else {
@@ -114,25 +480,39 @@ pub fn encrypt_envs_to_enc(
             continue;
         }
         let dst = enc_output_path(src);
-        encrypt_file_to_enc(&aead, src, &dst)?;
+        let binding = binding_for(repo_root, &dst);
+        let key = match keys.keyring_for(&binding) {
+            Ok(k) => k,
+            Err(e) => {
+                eprintln!("[enc] skip {}: {e}", src.display());
+                continue;
+            }
+        };
+        let mode = read_enc_mode(repo_root, &binding.path, &binding.env)?;
+        if !encrypt_file_to_enc(mode, key, &scheme, src, &dst, &binding, recipients_changed)? {
+            println!("[enc] unchanged {}", dst.display());
+            continue;
+        }
         println!("[enc] wrote {}", dst.display());
         produced.push(dst);
     }
+    signing::sign_files(repo_root, &produced)?;
     Ok(produced)
 }
 
-pub fn aead_from_key_str(key_str: &str) -> io::Result<XChaCha20Poly1305> {
-    if key_str.trim().is_empty() {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty key"));
+// bootstrap flow
+pub fn bootstrap_key_and_decrypt(repo_root: &Path) -> io::Result<()> {
+    let mut key_str = crate::config::prompt_for_key()?;
+    if shamir::is_share(&key_str) {
+        key_str = shamir::combine(&shamir::read_shares(Some(key_str))?)?;
     }
-    let hash = blake3::hash(key_str.as_bytes());
-    Ok(XChaCha20Poly1305::new(hash.as_bytes().into()))
+    bootstrap_with_key(repo_root, &key_str)
 }
 
-// bootstrap flow
-pub fn bootstrap_key_and_decrypt(repo_root: &Path) -> io::Result<()> {
-    let key_str = crate::config::prompt_for_key()?;
-    let aead = aead_from_key_str(&key_str)?;
+/// Checks `key_str` against the repo's .enc files, then saves it to
+/// eenv.config.json and decrypts.
+pub fn bootstrap_with_key(repo_root: &Path, key_str: &str) -> io::Result<()> {
+    let key = Keyring::from_secret(key_str)?;
 
     let files = find_env_files_recursive(repo_root)?;
     let (_real, _examples, encs) = split_env_files(files);
@@ -143,40 +523,372 @@ pub fn bootstrap_key_and_decrypt(repo_root: &Path) -> io::Result<()> {
         ));
     }
 
+    // files that carry a key id are checked against it without decrypting
+    // anything; only legacy files need a trial decryption
     let mut validated = false;
+    let mut mismatch = None;
     for enc_path in &encs {
-        let dst = dec_output_path(enc_path);
-        if dst.exists() {
-            let tmp = dst.with_extension("validate.tmp~");
-            match decrypt_file_from_enc(&aead, enc_path, &tmp) {
-                Ok(()) => {
-                    let _ = std::fs::remove_file(&tmp);
+        let Ok(data) = fs::read(enc_path) else {
+            continue;
+        };
+        match read_header(&data) {
+            Ok(Header {
+                kdf: Some(params),
+                key_id: Some(id),
+                ..
+            }) => {
+                let ours = key.key_id(&params)?;
+                if ours == id {
                     validated = true;
                     break;
                 }
-                Err(_) => {
-                    let _ = std::fs::remove_file(&tmp);
-                    continue;
-                }
+                mismatch.get_or_insert((id, ours));
             }
-        } else {
-            if decrypt_file_from_enc(&aead, enc_path, &dst).is_ok() {
-                validated = true;
-                break;
-            } else {
-                let _ = std::fs::remove_file(&dst);
+            _ => {
+                let binding = binding_for(repo_root, enc_path);
+                if decrypt_bytes(&key, &data, Some(&binding)).is_ok() {
+                    validated = true;
+                    break;
+                }
             }
         }
     }
 
     if !validated {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "provided key did not decrypt any .env*.enc",
-        ));
+        let msg = match mismatch {
+            Some((theirs, ours)) => format!(
+                "the files were encrypted with key {}, you have key {}",
+                fingerprint(&theirs),
+                fingerprint(&ours)
+            ),
+            None => "provided key did not decrypt any .env*.enc".to_string(),
+        };
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
     }
 
-    write_eenv_config_with_key(repo_root, &key_str)?;
+    write_eenv_config_with_key(repo_root, key_str)?;
+    ensure_repo_kdf_params(repo_root)?;
     ensure_gitignore_has_config(repo_root)?;
     handle_enc_workflow(repo_root)
 }
+
+/// Re-binds an encrypted file to a new location: decrypts `from` whatever path
+/// it was bound to, re-encrypts it bound to `to`, and moves the plaintext
+/// sibling along. `from == to` re-binds a file that was already moved by hand.
+/// `from` may also be any age file (e.g. `secrets.age`), which adopts it.
+pub fn move_enc(repo_root: &Path, from: &Path, to: &Path) -> io::Result<()> {
+    let is_enc_name = |p: &Path| {
+        matches!(p.file_name().and_then(|s| s.to_str()),
+            Some(n) if n.starts_with(".env") && n.ends_with(".enc"))
+    };
+    if !is_enc_name(to) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "target must be a .env*.enc file",
+        ));
+    }
+    let adopting = !is_enc_name(from);
+    let from = from.canonicalize()?;
+    let to_parent = to
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(to_parent)?;
+    let to = to_parent.canonicalize()?.join(to.file_name().unwrap());
+    if from != to && to.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        ));
+    }
+
+    let keys = read_key_map(repo_root)?;
+    let rebinding = (from == to).then(|| binding_for(repo_root, &to).path);
+    manifest::ensure_clean(repo_root, &keys, rebinding.as_deref())?;
+    let data = fs::read(&from)?;
+    if adopting && !agefmt::is_age_file(&data) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "source must be a .env*.enc file or an age file",
+        ));
+    }
+    let from_binding = read_header(&data)
+        .ok()
+        .and_then(|h| h.binding)
+        .unwrap_or_else(|| binding_for(repo_root, &from));
+    // it is about to be re-signed, so it must carry a valid signature first
+    if !adopting {
+        signing::verify_bytes(repo_root, &from_binding, &data)?;
+    }
+    let plaintext = decrypt_bytes(keys.keyring_for(&from_binding)?, &data, None)?;
+    let scheme = repo_key_scheme(repo_root)?;
+    let binding = binding_for(repo_root, &to);
+    let out = encrypt_like(
+        &data,
+        keys.keyring_for(&binding)?,
+        &scheme,
+        &plaintext,
+        &binding,
+    )?;
+    write_bytes_atomic(&to, &out)?;
+    if from != to {
+        fs::remove_file(&from)?;
+    }
+    if from != to && !adopting {
+        let (from_plain, to_plain) = (dec_output_path(&from), dec_output_path(&to));
+        if from_plain.exists() && !to_plain.exists() {
+            fs::rename(&from_plain, &to_plain)?;
+            println!(
+                "[mv] moved {} -> {}",
+                from_plain.display(),
+                to_plain.display()
+            );
+        }
+    }
+    signing::sign_files(repo_root, &[from.clone(), to.clone()])?;
+    manifest::update(repo_root, &keys)?;
+    println!(
+        "[mv] {} -> {} (bound to {}, env {})",
+        from.display(),
+        to.display(),
+        binding.path,
+        binding.env
+    );
+    Ok(())
+}
+
+/// `eenv encrypt`: stream-encrypts any file (keystores, service-account
+/// bundles, ...) to `out` (default `<file>.enc`) and gitignores the plaintext.
+pub fn encrypt_artifact(repo_root: &Path, src: &Path, out: Option<&Path>) -> io::Result<()> {
+    let src = src.canonicalize()?;
+    let dst = resolve_path(&out.map_or_else(|| enc_output_path(&src), Path::to_path_buf))?;
+    let binding = binding_for(repo_root, &dst);
+    let keys = read_key_map(repo_root)?;
+    manifest::ensure_clean(repo_root, &keys, None)?;
+    let key = keys.keyring_for(&binding)?;
+    let scheme = repo_key_scheme(repo_root)?;
+    if encrypt_file_streaming(key, &scheme, &src, &dst, &binding)? {
+        signing::sign_files(repo_root, std::slice::from_ref(&dst))?;
+        manifest::update(repo_root, &keys)?;
+        println!("[encrypt] wrote {}", dst.display());
+    } else {
+        println!("[encrypt] unchanged {}", dst.display());
+    }
+    let report = crate::gitignore::fix_gitignore_from_found(repo_root, &[src])?;
+    if !report.added.is_empty() {
+        println!("[gitignore] added: {:?}", report.added);
+    }
+    Ok(())
+}
+
+/// `eenv decrypt`: decrypts one `.enc` file to `out` (default: the name
+/// without `.enc`), refusing to overwrite an existing file unless `force`.
+pub fn decrypt_artifact(
+    repo_root: &Path,
+    src_enc: &Path,
+    out: Option<&Path>,
+    force: bool,
+) -> io::Result<()> {
+    let src_enc = src_enc.canonicalize()?;
+    let dst = resolve_path(&out.map_or_else(|| dec_output_path(&src_enc), Path::to_path_buf))?;
+    if dst == src_enc {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "input has no .enc suffix; pass an output path with -o",
+        ));
+    }
+    if dst.exists() && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists (use --force to overwrite)",
+                dst.display()
+            ),
+        ));
+    }
+    let binding = binding_for(repo_root, &src_enc);
+    let keys = read_key_map(repo_root)?;
+    if let Some(problems) = manifest::check(repo_root, &keys)?
+        && let Some(p) = problems.iter().find(|p| p.path() == binding.path)
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, p.to_string()));
+    }
+    if let Some(signer) = signing::verify_file(repo_root, &src_enc)? {
+        println!("[decrypt] signed by {signer}");
+    }
+    decrypt_file_from_enc(keys.keyring_for(&binding)?, &src_enc, &dst, &binding)?;
+    println!("[decrypt] {} -> {}", src_enc.display(), dst.display());
+    Ok(())
+}
+
+// absolute path with the parent directory canonicalized (the file itself may
+// not exist yet), so it strips cleanly against the canonical repo root
+fn resolve_path(p: &Path) -> io::Result<std::path::PathBuf> {
+    let parent = p
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = p
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    Ok(parent.canonicalize()?.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipients::{Identity, RECIPIENTS_FILE, encode_public};
+    use crate::util::{TEST_KEY, git_in, scratch_keyed, scratch_repo, seal_env};
+
+    #[test]
+    fn swapped_age_recipients_are_re_encrypted() {
+        let dir = scratch_repo("age-swap");
+        let (me, bob, carol) = (
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        );
+        let list = |ids: &[&Identity]| {
+            let lines: Vec<String> = ids.iter().map(|i| encode_public(&i.public())).collect();
+            fs::write(dir.join(RECIPIENTS_FILE), lines.join("\n") + "\n").unwrap();
+            read_recipients(&dir).unwrap()
+        };
+        let key = Keyring::new(None, vec![me.clone()]).unwrap();
+        let (src, dst) = (dir.join(".env"), dir.join(".env.enc"));
+        fs::write(&src, "A=1\n").unwrap();
+        let binding = binding_for(&dir, &dst);
+        let encrypt = |pks: Vec<_>| {
+            let changed = crate::recipients::changed_since_head(&dir).unwrap();
+            let scheme = KeyScheme::Recipients(pks);
+            encrypt_file_to_enc(EncMode::Age, &key, &scheme, &src, &dst, &binding, changed).unwrap()
+        };
+
+        let before = list(&[&me, &bob]);
+        assert!(encrypt(before.clone()));
+        git_in(&dir, &["add", RECIPIENTS_FILE, ".env.enc"]);
+        git_in(&dir, &["commit", "-qm", "init"]);
+        assert!(!encrypt(before), "unchanged set must not churn");
+
+        // same count, different people
+        let after = list(&[&me, &carol]);
+        let old = fs::read(&dst).unwrap();
+        assert!(encrypt(after.clone()));
+        assert_ne!(fs::read(&dst).unwrap(), old);
+        let carol_key = Keyring::new(None, vec![carol.clone()]).unwrap();
+        let bob_key = Keyring::new(None, vec![bob.clone()]).unwrap();
+        let data = fs::read(&dst).unwrap();
+        assert_eq!(decrypt_bytes(&carol_key, &data, None).unwrap(), b"A=1\n");
+        assert!(decrypt_bytes(&bob_key, &data, None).is_err());
+
+        git_in(&dir, &["commit", "-qam", "swap"]);
+        assert!(!encrypt(after));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unchanged_files_are_not_re_encrypted() {
+        let dir = scratch_keyed("enc-unchanged");
+        let key = Keyring::from_secret(TEST_KEY).unwrap();
+        let scheme = repo_key_scheme(&dir).unwrap();
+        let (src, dst) = (dir.join(".env"), dir.join(".env.enc"));
+        fs::write(&src, "A=1\nB=2\n").unwrap();
+        let binding = binding_for(&dir, &dst);
+
+        for mode in [EncMode::File, EncMode::Values] {
+            let _ = fs::remove_file(&dst);
+            let encrypt = |scheme: &KeyScheme, binding: &Binding| {
+                encrypt_file_to_enc(mode, &key, scheme, &src, &dst, binding, false).unwrap()
+            };
+            assert!(encrypt(&scheme, &binding), "{mode:?}");
+            let before = fs::read(&dst).unwrap();
+            assert!(!encrypt(&scheme, &binding), "{mode:?}");
+            assert_eq!(fs::read(&dst).unwrap(), before, "{mode:?}");
+
+            // a new salt or a new path must rewrite it
+            let resalted = KeyScheme::Shared(KdfParams::cheap());
+            assert!(encrypt(&resalted, &binding), "{mode:?}");
+            assert!(encrypt(&scheme, &binding), "{mode:?}");
+            let moved = binding_for(&dir, &dir.join(".env.other.enc"));
+            assert!(encrypt(&scheme, &moved), "{mode:?}");
+        }
+
+        // `eenv enc` doesn't count (or re-sign) what it left alone
+        let srcs = [src];
+        assert_eq!(encrypt_envs_to_enc(&dir, &srcs).unwrap(), [dst.as_path()]);
+        let before = fs::read(&dst).unwrap();
+        assert!(encrypt_envs_to_enc(&dir, &srcs).unwrap().is_empty());
+        assert_eq!(fs::read(&dst).unwrap(), before);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn mv_refuses_a_file_its_signers_did_not_sign() {
+        let dir = scratch_keyed("mv-unsigned");
+        let signer = signing::SigningIdentity::generate();
+        let line = format!("production {}\n", signing::encode_public(&signer.public()));
+        fs::write(dir.join(signing::SIGNERS_FILE), line).unwrap();
+        let from = seal_env(&dir, ".env.production.enc", "A=1\n");
+
+        let to = dir.join(".env.prod.enc");
+        let err = move_enc(&dir, &from, &to).unwrap_err();
+        assert!(err.to_string().contains("must be signed"), "{err}");
+        assert!(from.exists() && !to.exists());
+        assert!(!signing::signatures_path(&dir).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn swapped_containers_belong_to_their_old_path() {
+        let dir = scratch_keyed("swap-containers");
+        let key = Keyring::from_secret(TEST_KEY).unwrap();
+        let scheme = repo_key_scheme(&dir).unwrap();
+        let dev = seal_env(&dir, ".env.enc", "A=dev\n");
+        let prod = seal_env(&dir, ".env.production.enc", "A=prod\n");
+        // stream files, as `eenv encrypt` writes them
+        let blob = dir.join("blob");
+        fs::write(&blob, "bytes").unwrap();
+        let (a, b) = (dir.join("a.json.enc"), dir.join("b.json.enc"));
+        for p in [&a, &b] {
+            encrypt_file_streaming(&key, &scheme, &blob, p, &binding_for(&dir, p)).unwrap();
+        }
+
+        for (x, y) in [(&dev, &prod), (&a, &b)] {
+            let (bx, by) = (fs::read(x).unwrap(), fs::read(y).unwrap());
+            fs::write(x, &by).unwrap();
+            fs::write(y, &bx).unwrap();
+            let out = dir.join("out");
+            let err = decrypt_file_from_enc(&key, x, &out, &binding_for(&dir, x)).unwrap_err();
+            let want = format!("belongs to {}", binding_for(&dir, y).path);
+            assert!(err.to_string().contains(&want), "{err}");
+            let err = decrypt_bytes(&key, &by, Some(&binding_for(&dir, x))).unwrap_err();
+            assert!(err.to_string().contains(&want), "{err}");
+            assert!(!out.exists());
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn mv_rebinds_a_file_moved_by_hand() {
+        let dir = scratch_keyed("mv-rebind");
+        let key = Keyring::from_secret(TEST_KEY).unwrap();
+        let staging = seal_env(&dir, ".env.staging.enc", "A=1\n");
+        let qa = dir.join(".env.qa.enc");
+        fs::rename(&staging, &qa).unwrap();
+        let out = dir.join(".env.qa");
+        let err = decrypt_file_from_enc(&key, &qa, &out, &binding_for(&dir, &qa)).unwrap_err();
+        assert!(err.to_string().contains("eenv mv"), "{err}");
+
+        move_enc(&dir, &qa, &qa).unwrap();
+        decrypt_file_from_enc(&key, &qa, &out, &binding_for(&dir, &qa)).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "A=1\n");
+
+        // and a move through eenv keeps it readable at the new path
+        let uat = dir.join(".env.uat.enc");
+        move_enc(&dir, &qa, &uat).unwrap();
+        assert!(!qa.exists());
+        let data = fs::read(&uat).unwrap();
+        let plain = decrypt_bytes(&key, &data, Some(&binding_for(&dir, &uat))).unwrap();
+        assert_eq!(plain, b"A=1\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,608 @@
+use std::{fmt, io};
+
+// Lossless dotenv syntax tree. Every line of the input becomes an entry that
+// keeps its exact text, so `parse(text)?.to_string() == text`, and editing a
+// value only rewrites that value. Supported syntax:
+//
+//   # comment                     blank lines and comments are kept as is
+//   export KEY=value              `export ` is optional
+//   KEY = value  # note           spaces around `=`; ` #` starts a comment
+//   KEY='literal $ \ "'           single quotes: no escapes
+//   KEY="a\nb \"q\" \\ \$"       double quotes: \n \r \t \" \\ \$
+//   KEY="-----BEGIN KEY-----      quoted values may span lines
+//   ...
+//   -----END KEY-----"
+//
+// Lines are split on '\n' only, so "\r\n" files and a missing final newline
+// survive a round trip.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pair {
+    /// 1-based line the assignment starts on (0 for added ones).
+    pub line: usize,
+    pub key: String,
+    /// The unescaped value.
+    pub value: String,
+    pub quote: Quote,
+    leading: String,
+    export: String,
+    before_eq: String,
+    after_eq: String,
+    value_raw: String,
+    trailing: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum Entry {
+    Blank(String),
+    Comment(String),
+    Pair(Pair),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub entries: Vec<Entry>,
+}
+
+fn invalid(line: usize, msg: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {line}: {msg}"))
+}
+
+fn split_ws(s: &str) -> (&str, &str) {
+    let n = s.len() - s.trim_start_matches([' ', '\t']).len();
+    s.split_at(n)
+}
+
+// byte index just past the closing quote of the value starting at `s[0]`
+fn closing_quote(s: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote == '"' {
+            escaped = true;
+        } else if c == quote {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+/// A piece of a value for `${VAR}` expansion: single-quoted text and `\$`
+/// are `Literal`, everything else is a `Template` that may hold references.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Literal(String),
+    Template(String),
+}
+
+fn double_segments(inner: &str) -> Vec<Segment> {
+    let mut out = Vec::new();
+    let mut text = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            Some('t') => text.push('\t'),
+            Some(c @ ('"' | '\\')) => text.push(c),
+            Some('$') => {
+                out.push(Segment::Template(std::mem::take(&mut text)));
+                out.push(Segment::Literal("$".into()));
+            }
+            Some(other) => {
+                text.push('\\');
+                text.push(other);
+            }
+            None => text.push('\\'),
+        }
+    }
+    out.push(Segment::Template(text));
+    out
+}
+
+fn unescape_double(inner: &str) -> String {
+    double_segments(inner)
+        .into_iter()
+        .map(|s| match s {
+            Segment::Literal(t) | Segment::Template(t) => t,
+        })
+        .collect()
+}
+
+fn escape_double(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '$' => out.push_str("\\$"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// an unquoted value that reads back as itself, unexpanded
+fn plain_ok(value: &str) -> bool {
+    value.trim() == value
+        && !value.contains(['\n', '\r', '"', '\''])
+        && !value.contains("${")
+        && !value.contains(" #")
+        && !value.contains("\t#")
+}
+
+// trailing text after a value: empty, whitespace, or whitespace then `#...`
+fn trailing_ok(s: &str) -> bool {
+    let (ws, rest) = split_ws(s);
+    let rest = rest.trim_end_matches('\r');
+    rest.is_empty() || (rest.starts_with('#') && (!ws.is_empty() || s.starts_with('#')))
+}
+
+impl Pair {
+    /// Everything before the `=` (indentation, `export`, key, spaces).
+    pub fn lhs(&self) -> String {
+        format!(
+            "{}{}{}{}",
+            self.leading, self.export, self.key, self.before_eq
+        )
+    }
+
+    /// Everything after the `=`, exactly as written.
+    pub fn rhs_raw(&self) -> String {
+        format!("{}{}{}", self.after_eq, self.value_raw, self.trailing)
+    }
+
+    pub fn is_exported(&self) -> bool {
+        !self.export.is_empty()
+    }
+
+    /// The value as written, with its quotes.
+    pub fn value_raw(&self) -> &str {
+        &self.value_raw
+    }
+
+    /// The value split for expansion (see [`Segment`]).
+    pub fn segments(&self) -> Vec<Segment> {
+        match self.quote {
+            Quote::Single => vec![Segment::Literal(self.value.clone())],
+            Quote::None => vec![Segment::Template(self.value.clone())],
+            Quote::Double => double_segments(&self.value_raw[1..self.value_raw.len() - 1]),
+        }
+    }
+
+    /// The inline comment after the value, without `#`, if any.
+    pub fn comment(&self) -> Option<&str> {
+        self.trailing.trim().strip_prefix('#').map(str::trim)
+    }
+
+    /// Replaces the value, keeping the quoting style when it can represent
+    /// the new value (otherwise switching to double quotes).
+    pub fn set_value(&mut self, value: &str) {
+        self.quote = match self.quote {
+            Quote::None if plain_ok(value) => Quote::None,
+            Quote::Single if !value.contains('\'') => Quote::Single,
+            _ => Quote::Double,
+        };
+        self.value_raw = match self.quote {
+            Quote::None => value.to_string(),
+            Quote::Single => format!("'{value}'"),
+            Quote::Double => escape_double(value),
+        };
+        self.value = value.to_string();
+        if self.trailing.trim().is_empty() {
+            self.trailing = self.trailing.trim_matches([' ', '\t']).to_string();
+        } else if self.trailing.starts_with('#') {
+            self.trailing.insert(0, ' ');
+        }
+    }
+
+    fn new(key: &str, value: &str) -> Self {
+        let mut pair = Pair {
+            line: 0,
+            key: key.to_string(),
+            value: String::new(),
+            quote: Quote::None,
+            leading: String::new(),
+            export: String::new(),
+            before_eq: String::new(),
+            after_eq: String::new(),
+            value_raw: String::new(),
+            trailing: String::new(),
+        };
+        pair.set_value(value);
+        pair
+    }
+}
+
+impl fmt::Display for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.lhs(), self.rhs_raw())
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Blank(s) | Entry::Comment(s) => f.write_str(s),
+            Entry::Pair(p) => p.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.entries.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            e.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Document {
+    pub fn pairs(&self) -> impl Iterator<Item = &Pair> {
+        self.entries.iter().filter_map(|e| match e {
+            Entry::Pair(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    /// Key/value pairs in file order.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        self.pairs()
+            .map(|p| (p.key.clone(), p.value.clone()))
+            .collect()
+    }
+}
+
+impl Document {
+    /// Index in `entries` of the last assignment of `key` (later ones win,
+    /// as when sourced).
+    pub fn position(&self, key: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .rposition(|e| matches!(e, Entry::Pair(p) if p.key == key))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Pair> {
+        match &self.entries[self.position(key)?] {
+            Entry::Pair(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Inserts a new `KEY=value` line before `entries[index]`, or at the end
+    /// of the file (keeping its final newline) when `index` is `None`.
+    pub fn insert(&mut self, index: Option<usize>, key: &str, value: &str) {
+        self.insert_pair(index, Pair::new(key, value));
+    }
+
+    /// Like `insert`, with the value of `pair` (from another document) as
+    /// written there, so its quoting and `${VAR}` references carry over.
+    pub fn insert_copy(&mut self, index: Option<usize>, pair: &Pair) {
+        let mut copy = Pair::new(&pair.key, "");
+        copy.value = pair.value.clone();
+        copy.quote = pair.quote;
+        copy.value_raw = pair.value_raw.clone();
+        self.insert_pair(index, copy);
+    }
+
+    fn insert_pair(&mut self, index: Option<usize>, pair: Pair) {
+        let end = match self.entries.last() {
+            Some(Entry::Blank(s)) if s.is_empty() => self.entries.len() - 1,
+            _ => self.entries.len(),
+        };
+        let at = index.unwrap_or(end).min(end);
+        self.entries.insert(at, Entry::Pair(pair));
+    }
+}
+
+#[cfg(test)]
+impl Document {
+    /// Sets `key`, editing its last assignment in place or appending a new
+    /// `KEY=value` line.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.position(key).map(|i| &mut self.entries[i]) {
+            Some(Entry::Pair(p)) => p.set_value(value),
+            _ => self.insert(None, key, value),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> io::Result<Document> {
+    let mut entries = Vec::new();
+    let mut lines = text.split('\n').enumerate();
+    while let Some((idx, line)) = lines.next() {
+        let lineno = idx + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            entries.push(Entry::Blank(line.to_string()));
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            entries.push(Entry::Comment(line.to_string()));
+            continue;
+        }
+
+        let (leading, rest) = split_ws(line);
+        let (export, rest) = match rest.strip_prefix("export") {
+            Some(after) if after.starts_with([' ', '\t']) => {
+                let (ws, rest) = split_ws(after);
+                (format!("export{ws}"), rest)
+            }
+            _ => (String::new(), rest),
+        };
+        let key_end = rest.find(['=', ' ', '\t']).unwrap_or(rest.len());
+        let (key, rest) = rest.split_at(key_end);
+        let (before_eq, rest) = split_ws(rest);
+        let Some(rhs) = rest.strip_prefix('=') else {
+            return Err(invalid(lineno, "expected KEY=VALUE"));
+        };
+        if key.is_empty() {
+            return Err(invalid(lineno, "missing key before `=`"));
+        }
+        let (after_eq, value_part) = split_ws(rhs);
+
+        let quote = match value_part.chars().next() {
+            Some('"') => Quote::Double,
+            Some('\'') => Quote::Single,
+            _ => Quote::None,
+        };
+        let (value_raw, trailing, value) = if quote == Quote::None {
+            // ` #` (or `#` right after `= `) starts a comment
+            let cut = if value_part.starts_with('#') && !after_eq.is_empty() {
+                0
+            } else {
+                value_part
+                    .char_indices()
+                    .find(|&(i, c)| c == '#' && value_part[..i].ends_with([' ', '\t']))
+                    .map_or(value_part.len(), |(i, _)| i)
+            };
+            let raw = value_part[..cut].trim_end_matches([' ', '\t', '\r']);
+            let trailing = &value_part[raw.len()..];
+            (raw.to_string(), trailing.to_string(), raw.to_string())
+        } else {
+            let q = if quote == Quote::Double { '"' } else { '\'' };
+            let mut text = value_part.to_string();
+            let end = loop {
+                if let Some(end) = closing_quote(&text, q) {
+                    break end;
+                }
+                let Some((_, next)) = lines.next() else {
+                    return Err(invalid(lineno, "unterminated quoted value"));
+                };
+                text.push('\n');
+                text.push_str(next);
+            };
+            let (raw, trailing) = text.split_at(end);
+            if !trailing_ok(trailing) {
+                return Err(invalid(lineno, "unexpected text after closing quote"));
+            }
+            let inner = &raw[1..raw.len() - 1];
+            let value = match quote {
+                Quote::Double => unescape_double(inner),
+                _ => inner.to_string(),
+            };
+            (raw.to_string(), trailing.to_string(), value)
+        };
+
+        entries.push(Entry::Pair(Pair {
+            line: lineno,
+            key: key.to_string(),
+            value,
+            quote,
+            leading: leading.to_string(),
+            export,
+            before_eq: before_eq.to_string(),
+            after_eq: after_eq.to_string(),
+            value_raw,
+            trailing,
+        }));
+    }
+    Ok(Document { entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "# app settings\n\
+        \n\
+        export DATABASE_URL=\"postgres://u:p@h/db\" # main db\n\
+        \x20 PORT = 3000\n\
+        NAME='single'\n\
+        LITERAL='a $b \\ \"c\"'\n\
+        ESCAPED=\"a\\nb \\\"q\\\" \\\\ \\$HOME\"\n\
+        PEM=\"-----BEGIN KEY-----\n\
+        abc\n\
+        -----END KEY-----\"\n\
+        URL=http://x/#frag # real comment\n\
+        EMPTY=\n\
+        HASH= # comment only\n\
+        COLOR=#fff\n";
+
+    fn value<'a>(doc: &'a Document, key: &str) -> &'a str {
+        &doc.get(key).unwrap().value
+    }
+
+    #[test]
+    fn round_trips_byte_for_byte() {
+        for text in [
+            SAMPLE,
+            "",
+            "\n",
+            "A=1",
+            "A=1\r\nB=\"two\"\r\n# c\r\n",
+            "\t export\tA\t=\t'x'\t#\tnote\n\n\n",
+            "A=\"multi\r\nline\"\r\n",
+        ] {
+            let doc = parse(text).unwrap();
+            assert_eq!(doc.to_string(), text, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn parses_values() {
+        let doc = parse(SAMPLE).unwrap();
+        assert_eq!(value(&doc, "DATABASE_URL"), "postgres://u:p@h/db");
+        assert!(doc.get("DATABASE_URL").unwrap().is_exported());
+        assert_eq!(doc.get("DATABASE_URL").unwrap().comment(), Some("main db"));
+        assert_eq!(value(&doc, "PORT"), "3000");
+        assert_eq!(value(&doc, "LITERAL"), "a $b \\ \"c\"");
+        assert_eq!(value(&doc, "ESCAPED"), "a\nb \"q\" \\ $HOME");
+        assert_eq!(
+            value(&doc, "PEM"),
+            "-----BEGIN KEY-----\nabc\n-----END KEY-----"
+        );
+        assert_eq!(doc.get("PEM").unwrap().line, 8);
+        assert_eq!(doc.get("URL").unwrap().line, 11);
+        assert_eq!(value(&doc, "URL"), "http://x/#frag");
+        assert_eq!(value(&doc, "EMPTY"), "");
+        assert_eq!(value(&doc, "HASH"), "");
+        assert_eq!(doc.get("HASH").unwrap().comment(), Some("comment only"));
+        assert_eq!(value(&doc, "COLOR"), "#fff");
+    }
+
+    #[test]
+    fn crlf_is_not_part_of_the_value() {
+        let doc = parse("A=1\r\nB='two'\r\n").unwrap();
+        assert_eq!(value(&doc, "A"), "1");
+        assert_eq!(value(&doc, "B"), "two");
+    }
+
+    #[test]
+    fn later_assignments_win() {
+        let doc = parse("A=1\nB=2\nA=3\n").unwrap();
+        assert_eq!(value(&doc, "A"), "3");
+        assert_eq!(
+            doc.vars(),
+            vec![
+                ("A".into(), "1".into()),
+                ("B".into(), "2".into()),
+                ("A".into(), "3".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_dollar_is_literal_for_expansion() {
+        let doc = parse("A=\"x${B}\\${C}\"\nD='${E}'\nF=${G}\n").unwrap();
+        assert_eq!(
+            doc.get("A").unwrap().segments(),
+            vec![
+                Segment::Template("x${B}".into()),
+                Segment::Literal("$".into()),
+                Segment::Template("{C}".into()),
+            ]
+        );
+        assert_eq!(
+            doc.get("D").unwrap().segments(),
+            vec![Segment::Literal("${E}".into())]
+        );
+        assert_eq!(
+            doc.get("F").unwrap().segments(),
+            vec![Segment::Template("${G}".into())]
+        );
+    }
+
+    #[test]
+    fn set_only_rewrites_that_value() {
+        let mut doc = parse(SAMPLE).unwrap();
+        doc.set("PORT", "8080");
+        doc.set("LITERAL", "plain");
+        assert_eq!(
+            doc.to_string(),
+            SAMPLE
+                .replace("PORT = 3000", "PORT = 8080")
+                .replace("LITERAL='a $b \\ \"c\"'", "LITERAL='plain'")
+        );
+    }
+
+    #[test]
+    fn set_values_read_back() {
+        let tricky = [
+            "",
+            "plain",
+            " padded ",
+            "it's",
+            "say \"hi\"",
+            "back\\slash",
+            "two\nlines",
+            "tab\there",
+            "cr\rhere",
+            "a #b",
+            "#start",
+            "$HOME",
+            "${B}",
+            "a${B:-x}c",
+            "cost $5 \\$",
+        ];
+        for start in ["A=x", "A='x'", "A=\"x\"", "A=x # note", "A='x'#note"] {
+            for v in tricky {
+                let mut doc = parse(&format!("{start}\nB=after\n")).unwrap();
+                doc.set("A", v);
+                let text = doc.to_string();
+                let back = parse(&text).unwrap();
+                assert_eq!(value(&back, "A"), v, "{start:?} <- {v:?}: {text:?}");
+                assert_eq!(value(&back, "B"), "after");
+                assert_eq!(
+                    back.get("A").unwrap().comment(),
+                    doc.get("A").unwrap().comment()
+                );
+                // a value set is taken literally, never as a reference
+                let vars = crate::expand::expand(&[&back]).unwrap();
+                assert_eq!(vars[0], ("A".to_string(), v.to_string()), "{text:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn insert_keeps_the_final_newline() {
+        let mut doc = parse("A=1\n").unwrap();
+        doc.set("B", "two words");
+        assert_eq!(doc.to_string(), "A=1\nB=two words\n");
+
+        let mut doc = parse("A=1").unwrap();
+        doc.insert(Some(0), "Z", "0");
+        assert_eq!(doc.to_string(), "Z=0\nA=1");
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for (text, msg) in [
+            ("A=1\nJUST_A_KEY\n", "line 2: expected KEY=VALUE"),
+            ("=value\n", "line 1: missing key"),
+            (
+                "A=\"open\nstill open\n",
+                "line 1: unterminated quoted value",
+            ),
+            ("A='x' y\n", "line 1: unexpected text after closing quote"),
+            (
+                "A=\"x\"# no space is fine\nB=\"y\"z\n",
+                "line 2: unexpected text",
+            ),
+        ] {
+            let e = parse(text).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert!(e.to_string().starts_with(msg), "{text:?}: {e}");
+        }
+    }
+}
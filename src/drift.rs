@@ -0,0 +1,218 @@
+use crate::check::EnvReader;
+use crate::crypto::binding_for;
+use crate::envscan::{find_env_files_recursive, split_env_files};
+use rand::Rng;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// An env file's three forms: the local plaintext, its .example and its .enc.
+#[derive(Default)]
+pub struct Group {
+    pub local: Option<PathBuf>,
+    pub example: Option<PathBuf>,
+    pub enc: Option<PathBuf>,
+}
+
+// Keys in file order, with the effective (last) value of each.
+struct Vars {
+    order: Vec<String>,
+    values: HashMap<String, String>,
+}
+
+impl Vars {
+    fn new(vars: Vec<(String, String)>) -> Self {
+        let mut order = Vec::new();
+        let mut values = HashMap::new();
+        for (k, v) in vars {
+            if values.insert(k.clone(), v).is_none() {
+                order.push(k);
+            }
+        }
+        Vars { order, values }
+    }
+
+    fn has(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+}
+
+/// Every env file in the repo with its .example and .enc, keyed by the
+/// plaintext path (whether or not that exists).
+pub fn groups(repo_root: &Path) -> io::Result<BTreeMap<PathBuf, Group>> {
+    let (real, examples, encs) = split_env_files(find_env_files_recursive(repo_root)?);
+    let mut out: BTreeMap<PathBuf, Group> = BTreeMap::new();
+    let base = |p: &Path, suffix: &str| {
+        let s = p.to_string_lossy();
+        PathBuf::from(s.strip_suffix(suffix).unwrap_or(&s))
+    };
+    for p in real {
+        let key = p.clone();
+        out.entry(key).or_default().local = Some(p);
+    }
+    for p in examples {
+        let key = base(&p, ".example");
+        out.entry(key).or_default().example = Some(p);
+    }
+    for p in encs {
+        let key = base(&p, ".enc");
+        out.entry(key).or_default().enc = Some(p);
+    }
+    Ok(out)
+}
+
+/// `eenv drift`: for each env file, the keys its local copy lacks compared
+/// with its .example and .enc, the local keys neither has, and the keys whose
+/// local value differs from the encrypted one. Values are shown only as
+/// hashes salted per run, so they can't be matched against guesses. Any
+/// drift is an error.
+pub fn run_drift(repo_root: &Path) -> io::Result<()> {
+    let salt: [u8; 32] = rand::rng().random();
+    let mask = |v: &str| blake3::keyed_hash(&salt, v.as_bytes()).to_hex()[..8].to_string();
+
+    let mut reader = EnvReader::new(repo_root);
+    let mut drifted = 0;
+    for (base, group) in groups(repo_root)? {
+        let rel = binding_for(repo_root, &base).path;
+        let mut notes = Vec::new();
+        let mut read = |path: &Option<PathBuf>, what: &str, reader: &mut EnvReader| match reader
+            .vars(path.as_ref()?)
+        {
+            Ok(vars) => Some(Vars::new(vars)),
+            Err(e) => {
+                notes.push(format!("can't read {what}: {e}"));
+                None
+            }
+        };
+        let local = read(&group.local, "local file", &mut reader);
+        let example = read(&group.example, "example", &mut reader);
+        let enc = read(&group.enc, ".enc", &mut reader);
+
+        let mut missing: Vec<(String, String)> = Vec::new();
+        let mut extra = Vec::new();
+        let mut differs = Vec::new();
+        match &local {
+            Some(local) => {
+                let refs = [("example", &example), (".enc", &enc)];
+                for (_, vars) in refs {
+                    for key in vars.iter().flat_map(|v| &v.order) {
+                        if local.has(key) || missing.iter().any(|(k, _)| k == key) {
+                            continue;
+                        }
+                        let found: Vec<&str> = refs
+                            .iter()
+                            .filter(|(_, v)| v.as_ref().is_some_and(|v| v.has(key)))
+                            .map(|(name, _)| *name)
+                            .collect();
+                        missing.push((key.clone(), found.join(", ")));
+                    }
+                }
+                if example.is_some() || enc.is_some() {
+                    for key in &local.order {
+                        let known = [&example, &enc]
+                            .iter()
+                            .any(|v| v.as_ref().is_some_and(|v| v.has(key)));
+                        if !known {
+                            extra.push(key.clone());
+                        }
+                    }
+                }
+                if let Some(enc) = &enc {
+                    for key in &local.order {
+                        if let Some(sealed) = enc.values.get(key)
+                            && *sealed != local.values[key]
+                        {
+                            differs.push(format!(
+                                "{key} (local {}, .enc {})",
+                                mask(&local.values[key]),
+                                mask(sealed)
+                            ));
+                        }
+                    }
+                }
+            }
+            None if group.local.is_none() => {
+                notes.push("no local file (run `eenv init` to decrypt it)".to_string());
+            }
+            None => {}
+        }
+
+        if notes.is_empty() && missing.is_empty() && extra.is_empty() && differs.is_empty() {
+            println!("[drift] ✔ {rel}");
+            continue;
+        }
+        drifted += 1;
+        println!("[drift] ✖ {rel}");
+        for n in &notes {
+            println!("    {n}");
+        }
+        let missing: Vec<String> = missing
+            .iter()
+            .map(|(key, found)| format!("{key} (in {found})"))
+            .collect();
+        for (label, keys) in [
+            ("missing locally", &missing),
+            ("extra locally", &extra),
+            ("differs from .enc", &differs),
+        ] {
+            if !keys.is_empty() {
+                println!("    {label}: {}", keys.join(", "));
+            }
+        }
+    }
+    if drifted > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{drifted} env file(s) drifted"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{scratch_keyed, seal_env};
+    use std::fs;
+
+    // a repo whose .env.enc holds `sealed`
+    fn setup(name: &str, sealed: &str) -> PathBuf {
+        let dir = scratch_keyed(name);
+        seal_env(&dir, ".env.enc", sealed);
+        dir
+    }
+
+    #[test]
+    fn in_sync_files_pass() {
+        let dir = setup("drift-clean", "A=1\nB=2\n");
+        fs::write(dir.join(".env"), "A=1\nB=2\n").unwrap();
+        fs::write(dir.join(".env.example"), "A=\nB=\n").unwrap();
+        run_drift(&dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn each_kind_of_drift_is_an_error() {
+        for (name, local, example) in [
+            ("drift-missing", "A=1\n", "A=\nB=\n"),
+            ("drift-extra", "A=1\nB=2\nC=3\n", "A=\nB=\n"),
+            ("drift-differs", "A=1\nB=changed\n", "A=\nB=\n"),
+        ] {
+            let dir = setup(name, "A=1\nB=2\n");
+            fs::write(dir.join(".env"), local).unwrap();
+            fs::write(dir.join(".env.example"), example).unwrap();
+            let err = run_drift(&dir).unwrap_err();
+            assert_eq!(err.to_string(), "1 env file(s) drifted", "{name}");
+            let _ = fs::remove_dir_all(&dir);
+        }
+    }
+
+    #[test]
+    fn an_enc_without_a_local_file_is_drift() {
+        let dir = setup("drift-nolocal", "A=1\n");
+        assert!(run_drift(&dir).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
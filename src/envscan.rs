@@ -1,17 +1,33 @@
-use crate::config::validate_eenv_config;
+use crate::config::{DEFAULT_LAYERS, validate_eenv_config};
+use crate::resolve;
 use crate::types::EenvState;
 use ignore::{DirEntry, WalkBuilder};
 use std::{io, path::Path, path::PathBuf};
 
 pub fn find_env_files_recursive(root: &Path) -> io::Result<Vec<PathBuf>> {
+    find_files_recursive(root, is_env_file)
+}
+
+/// Every `*.enc` file, including stream-encrypted artifacts that don't
+/// follow the `.env*` naming.
+pub fn find_enc_files_recursive(root: &Path) -> io::Result<Vec<PathBuf>> {
+    find_files_recursive(root, |d| {
+        d.file_type().is_some_and(|t| t.is_file())
+            && d.path()
+                .file_name()
+                .and_then(|s| s.to_str())
+                .is_some_and(|n| n.ends_with(".enc"))
+    })
+}
+
+fn find_files_recursive(root: &Path, keep: impl Fn(&DirEntry) -> bool) -> io::Result<Vec<PathBuf>> {
     let mut builder = WalkBuilder::new(root);
     builder
         .hidden(true)
         .follow_links(false)
         .standard_filters(false)
         .parents(false)
-        .add_custom_ignore_filename(".eenvignore")
-        .filter_entry(|d| d.depth() == 0 || true);
+        .add_custom_ignore_filename(".eenvignore");
 
     let mut out = Vec::new();
     for result in builder.build() {
@@ -22,7 +38,7 @@ pub fn find_env_files_recursive(root: &Path) -> io::Result<Vec<PathBuf>> {
                 continue;
             }
         };
-        if is_env_file(&dent) {
+        if keep(&dent) {
             let abs = dent
                 .path()
                 .canonicalize()
@@ -55,6 +71,33 @@ pub fn split_env_files(mut files: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<PathBuf>,
     (real, examples, encs)
 }
 
+/// The environment of `.env` / `.env.enc` and the other layers without
+/// `{env}`.
+pub const DEFAULT_ENV: &str = "default";
+
+/// The environment an env file belongs to, as `eenv resolve` places it in
+/// the default layers: `.env` and `.env.local` -> "default",
+/// `.env.production(.local)(.enc)` -> "production". Other names keep what
+/// follows `.env`. The default layers rather than `"layers"`, since the name
+/// is bound into every `.enc` and selects its key and signers.
+pub fn env_name_for(path: &Path) -> String {
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let name = name.strip_suffix(".enc").unwrap_or(name);
+    match resolve::env_of(&DEFAULT_LAYERS.map(String::from), name) {
+        Some(Some(env)) => env,
+        Some(None) => DEFAULT_ENV.to_string(),
+        None => {
+            let rest = name.strip_prefix(".env").unwrap_or(name);
+            let rest = rest.trim_start_matches('.');
+            if rest.is_empty() {
+                DEFAULT_ENV.to_string()
+            } else {
+                rest.to_string()
+            }
+        }
+    }
+}
+
 fn is_env_file(d: &DirEntry) -> bool {
     if !d.file_type().map(|t| t.is_file()).unwrap_or(false) {
         return false;
@@ -0,0 +1,268 @@
+use crate::dotenv::{Document, Segment};
+use std::{collections::HashMap, io};
+
+// `${VAR}` expansion over an environment made of layers (lowest precedence
+// first). In unquoted and double-quoted values:
+//
+//   ${VAR}          the value of VAR, or "" if it is unset
+//   ${VAR:-text}    `text` if VAR is unset or empty (text may hold references)
+//   ${VAR:?msg}     an error saying `msg` if VAR is unset or empty
+//
+// A reference sees VAR's effective value (its last assignment in the top-most
+// layer that sets it), falling back to the process environment. A variable
+// referring to itself sees the value it overrides, from an earlier assignment
+// or a lower layer, so `PATH=${PATH}:/opt/bin` works. Single-quoted values,
+// `\$` in double quotes and a `$` not followed by `{` are literal. Cycles are
+// an error.
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// end of the `${...}` starting at `s[0..2]`: index of its closing brace
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'$' && bytes.get(i + 1) == Some(&b'{') {
+            depth += 1;
+            i += 2;
+            continue;
+        }
+        if bytes[i] == b'}' {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+struct Expander<'a> {
+    // every assignment of each key, in precedence order
+    defs: HashMap<&'a str, Vec<Vec<Segment>>>,
+    done: HashMap<(&'a str, usize), String>,
+    stack: Vec<(&'a str, usize)>,
+}
+
+impl<'a> Expander<'a> {
+    // the value of `key` as of its assignment `idx`
+    fn value(&mut self, key: &'a str, idx: usize) -> io::Result<String> {
+        if let Some(v) = self.done.get(&(key, idx)) {
+            return Ok(v.clone());
+        }
+        if let Some(at) = self.stack.iter().position(|e| *e == (key, idx)) {
+            let mut path: Vec<&str> = self.stack[at..].iter().map(|(k, _)| *k).collect();
+            path.push(key);
+            return Err(invalid(format!("reference cycle: {}", path.join(" -> "))));
+        }
+        self.stack.push((key, idx));
+        let mut out = String::new();
+        for seg in self.defs[key][idx].clone() {
+            match seg {
+                Segment::Literal(t) => out.push_str(&t),
+                Segment::Template(t) => out.push_str(&self.template(&t, key, idx)?),
+            }
+        }
+        self.stack.pop();
+        self.done.insert((key, idx), out.clone());
+        Ok(out)
+    }
+
+    // what `${name}` means inside assignment `idx` of `owner`
+    fn lookup(&mut self, name: &str, owner: &'a str, idx: usize) -> io::Result<Option<String>> {
+        if name == owner {
+            if idx > 0 {
+                return self.value(owner, idx - 1).map(Some);
+            }
+        } else if let Some((&key, defs)) = self.defs.get_key_value(name) {
+            let last = defs.len() - 1;
+            return self.value(key, last).map(Some);
+        }
+        Ok(std::env::var(name).ok())
+    }
+
+    fn template(&mut self, text: &str, owner: &'a str, idx: usize) -> io::Result<String> {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(at) = rest.find("${") {
+            out.push_str(&rest[..at]);
+            let Some(end) = closing_brace(&rest[at..]) else {
+                return Err(invalid(format!("{owner}: unterminated ${{ reference")));
+            };
+            let inner = &rest[at + 2..at + end];
+            rest = &rest[at + end + 1..];
+
+            let (name, op) = match inner.find(":-").into_iter().chain(inner.find(":?")).min() {
+                Some(i) => (&inner[..i], Some((&inner[i..i + 2], &inner[i + 2..]))),
+                None => (inner, None),
+            };
+            if !is_name(name) {
+                return Err(invalid(format!(
+                    "{owner}: bad reference ${{{inner}}} (expected ${{NAME}}, ${{NAME:-default}} or ${{NAME:?error}})"
+                )));
+            }
+            let value = self.lookup(name, owner, idx)?.filter(|v| !v.is_empty());
+            match (value, op) {
+                (Some(v), _) => out.push_str(&v),
+                (None, None) => {}
+                (None, Some((":-", default))) => out.push_str(&self.template(default, owner, idx)?),
+                (None, Some((_, msg))) => {
+                    let msg = if msg.is_empty() { "is required" } else { msg };
+                    return Err(invalid(format!("{owner}: {name}: {msg}")));
+                }
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+/// The variables of an environment built from `layers` (lowest precedence
+/// first), in order of first appearance, with references expanded.
+pub fn expand(layers: &[&Document]) -> io::Result<Vec<(String, String)>> {
+    let mut order: Vec<&str> = Vec::new();
+    let mut defs: HashMap<&str, Vec<Vec<Segment>>> = HashMap::new();
+    for pair in layers.iter().flat_map(|d| d.pairs()) {
+        let entry = defs.entry(&pair.key).or_default();
+        if entry.is_empty() {
+            order.push(&pair.key);
+        }
+        entry.push(pair.segments());
+    }
+    let mut ex = Expander {
+        defs,
+        done: HashMap::new(),
+        stack: Vec::new(),
+    };
+    order
+        .into_iter()
+        .map(|key| {
+            let last = ex.defs[key].len() - 1;
+            Ok((key.to_string(), ex.value(key, last)?))
+        })
+        .collect()
+}
+
+/// The same environment without expansion: the last value of each key.
+pub fn merge(layers: &[&Document]) -> Vec<(String, String)> {
+    let mut out: Vec<(String, String)> = Vec::new();
+    for (k, v) in layers.iter().flat_map(|d| d.vars()) {
+        match out.iter_mut().find(|(key, _)| *key == k) {
+            Some(slot) => slot.1 = v,
+            None => out.push((k, v)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dotenv::parse;
+
+    // the environment built from these layer texts; EENV_TEST_UNSET stands
+    // for a name nothing sets, not even the process environment
+    fn env(layers: &[&str]) -> io::Result<Vec<(String, String)>> {
+        let docs: Vec<Document> = layers.iter().map(|t| parse(t).unwrap()).collect();
+        let refs: Vec<&Document> = docs.iter().collect();
+        expand(&refs)
+    }
+
+    fn get(vars: &[(String, String)], key: &str) -> String {
+        vars.iter().find(|(k, _)| k == key).unwrap().1.clone()
+    }
+
+    fn err(layers: &[&str]) -> String {
+        env(layers).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn references_and_defaults() {
+        let vars = env(&[concat!(
+            "HOST=db\n",
+            "PORT=5432\n",
+            "URL=postgres://${HOST}:${PORT}/app\n",
+            "EMPTY=\n",
+            "A=${EENV_TEST_UNSET:-fallback}\n",
+            "B=${EMPTY:-${HOST}-x}\n",
+            "C=x${EENV_TEST_UNSET}y\n",
+            "LIT='${HOST}'\n",
+            "ESC=\"\\${HOST}\"\n",
+            "DOLLAR=$HOST\n",
+        )])
+        .unwrap();
+        assert_eq!(get(&vars, "URL"), "postgres://db:5432/app");
+        assert_eq!(get(&vars, "A"), "fallback");
+        assert_eq!(get(&vars, "B"), "db-x");
+        assert_eq!(get(&vars, "C"), "xy");
+        assert_eq!(get(&vars, "LIT"), "${HOST}");
+        assert_eq!(get(&vars, "ESC"), "${HOST}");
+        assert_eq!(get(&vars, "DOLLAR"), "$HOST");
+    }
+
+    #[test]
+    fn references_see_the_top_most_layer() {
+        let vars = env(&[
+            "HOST=localhost\nURL=http://${HOST}/\n",
+            "HOST=prod.example.com\n",
+        ])
+        .unwrap();
+        assert_eq!(get(&vars, "URL"), "http://prod.example.com/");
+        assert_eq!(get(&vars, "HOST"), "prod.example.com");
+    }
+
+    #[test]
+    fn self_reference_extends_the_lower_value() {
+        let vars = env(&["P=/usr/bin\nP=${P}:/bin\n", "P=${P}:/opt/bin\n"]).unwrap();
+        assert_eq!(get(&vars, "P"), "/usr/bin:/bin:/opt/bin");
+        assert_eq!(vars.len(), 1);
+    }
+
+    #[test]
+    fn required_references_fail_with_their_message() {
+        assert!(
+            err(&["A=${EENV_TEST_UNSET:?set it in .env.local}\n"])
+                .contains("A: EENV_TEST_UNSET: set it in .env.local")
+        );
+        assert!(err(&["E=\nA=${E:?}\n"]).contains("A: E: is required"));
+        // satisfied by a higher layer
+        let vars = env(&["A=${TOKEN:?missing}\n", "TOKEN=t0k\n"]).unwrap();
+        assert_eq!(get(&vars, "A"), "t0k");
+    }
+
+    #[test]
+    fn cycles_are_errors() {
+        assert!(err(&["A=${B}\nB=${A}\n"]).contains("reference cycle: A -> B -> A"));
+        assert!(err(&["A=${B}\n", "B=${C}\nC=${A}\n"]).contains("reference cycle"));
+        assert!(err(&["A=${A:-x}${B}\nB=${A}\n"]).contains("reference cycle"));
+    }
+
+    #[test]
+    fn malformed_references_are_errors() {
+        assert!(err(&["A=${B\n"]).contains("unterminated"));
+        assert!(err(&["A=${1B}\n"]).contains("bad reference"));
+        assert!(err(&["A=${}\n"]).contains("bad reference"));
+    }
+
+    #[test]
+    fn merge_keeps_values_as_written() {
+        let docs = [parse("A=1\nB=${A}\n").unwrap(), parse("A=2\n").unwrap()];
+        let merged = merge(&[&docs[0], &docs[1]]);
+        assert_eq!(
+            merged,
+            vec![("A".into(), "2".into()), ("B".into(), "${A}".into())]
+        );
+    }
+}
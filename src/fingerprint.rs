@@ -0,0 +1,141 @@
+use crate::config::read_key_map;
+use crate::crypto::{
+    binding_for, detect_mode, existing_repo_kdf_params, is_eenv_file, read_header,
+};
+use crate::envscan::find_enc_files_recursive;
+use crate::kdf::KdfParams;
+use crate::keyring::{KeyMap, Keyring, fingerprint};
+use crate::types::EncMode;
+use std::{fs, io, path::Path};
+
+/// Prints the fingerprint of every configured key, then which key each
+/// `.enc` file was encrypted with and whether it is one of ours. Only key ids
+/// are compared, so no file is decrypted, and nothing is written: a repo
+/// with no salt yet has no fingerprints to show.
+pub fn print_fingerprints(repo_root: &Path) -> io::Result<()> {
+    let keys = read_key_map(repo_root)?;
+    let params = existing_repo_kdf_params(repo_root)?;
+    for (label, fp) in key_fingerprints(&keys, params.as_ref())? {
+        println!("{label:<24} {fp}");
+    }
+    let ours: Vec<&Keyring> = keys
+        .entries()
+        .into_iter()
+        .filter_map(|(_, k)| k.filter(|k| k.secret().is_some()))
+        .collect();
+
+    let encs = find_enc_files_recursive(repo_root)?;
+    if encs.is_empty() {
+        return Ok(());
+    }
+    println!();
+    for enc_path in &encs {
+        let data = fs::read(enc_path)?;
+        if !is_eenv_file(&data) {
+            continue;
+        }
+        let rel = binding_for(repo_root, enc_path).path;
+        let status = match read_header(&data) {
+            _ if detect_mode(&data) == EncMode::Age => "age (no key id)".to_string(),
+            Ok(h) if !h.recipients.is_empty() => "recipients".to_string(),
+            Ok(h) => match (h.kdf, h.key_id) {
+                (Some(p), Some(id)) => {
+                    let mut have = false;
+                    for k in &ours {
+                        if k.key_id(&p)? == id {
+                            have = true;
+                            break;
+                        }
+                    }
+                    let note = if have { "ok" } else { "no matching key" };
+                    format!("{}  {note}", fingerprint(&id))
+                }
+                _ => "legacy (no key id)".to_string(),
+            },
+            Err(e) => format!("unreadable: {e}"),
+        };
+        println!("{rel:<32} {status}");
+    }
+    Ok(())
+}
+
+// (label, fingerprint or why there is none) for every configured key
+fn key_fingerprints(
+    keys: &KeyMap,
+    params: Option<&KdfParams>,
+) -> io::Result<Vec<(String, String)>> {
+    let mut out = Vec::new();
+    for (selector, keyring) in keys.entries() {
+        let label = match selector {
+            None => "key".to_string(),
+            Some(sel) => format!("keys.\"{sel}\""),
+        };
+        let fp = match (keyring.filter(|k| k.secret().is_some()), params) {
+            (None, _) => "(not available)".to_string(),
+            (Some(_), None) => {
+                "(no salt yet: no \"kdf\" in eenv.config.json and no .enc files)".to_string()
+            }
+            (Some(k), Some(p)) => fingerprint(&k.key_id(p)?),
+        };
+        out.push((label, fp));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{eenv_config_path, write_config_object};
+    use crate::keyring::KeyScheme;
+    use crate::util::scratch_dir;
+    use serde_json::json;
+
+    fn config(dir: &Path, v: serde_json::Value) {
+        write_config_object(dir, v.as_object().unwrap().clone()).unwrap();
+    }
+
+    fn fingerprints(dir: &Path) -> Vec<(String, String)> {
+        let keys = read_key_map(dir).unwrap();
+        key_fingerprints(&keys, existing_repo_kdf_params(dir).unwrap().as_ref()).unwrap()
+    }
+
+    #[test]
+    fn no_salt_yet_writes_nothing_and_invents_nothing() {
+        let dir = scratch_dir("fingerprint-nosalt");
+        config(&dir, json!({ "key": "same" }));
+        let before = fs::read(eenv_config_path(&dir)).unwrap();
+        let fps = fingerprints(&dir);
+        assert_eq!(fps.len(), 1);
+        assert!(fps[0].1.contains("no salt yet"), "{fps:?}");
+        print_fingerprints(&dir).unwrap();
+        assert_eq!(fs::read(eenv_config_path(&dir)).unwrap(), before);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn fingerprints_are_stable_across_runs() {
+        let dir = scratch_dir("fingerprint-stable");
+        let params = KdfParams::cheap();
+        config(&dir, json!({ "key": "same", "kdf": params.to_json() }));
+        let first = fingerprints(&dir);
+        assert_eq!(first, fingerprints(&dir));
+        let key = Keyring::from_secret("same").unwrap();
+        assert_eq!(first[0].1, fingerprint(&key.key_id(&params).unwrap()));
+
+        // without a config, the salt comes from the .enc headers
+        let path = dir.join(".env.enc");
+        let binding = binding_for(&dir, &path);
+        let enc = crate::crypto::encrypt_bytes(
+            &key,
+            &KeyScheme::Shared(params.clone()),
+            b"A=1\n",
+            &binding,
+        )
+        .unwrap();
+        fs::write(&path, enc).unwrap();
+        fs::remove_file(eenv_config_path(&dir)).unwrap();
+        assert_eq!(existing_repo_kdf_params(&dir).unwrap(), Some(params));
+        assert!(!eenv_config_path(&dir).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -15,7 +15,7 @@ pub fn git_hooks_dir(repo_root: &Path) -> io::Result<PathBuf> {
         .arg("hooks")
         .output()?;
     if !out.status.success() {
-        return Err(io::Error::new(io::ErrorKind::Other, "git rev-parse failed"));
+        return Err(io::Error::other("git rev-parse failed"));
     }
     let p = String::from_utf8_lossy(&out.stdout).trim().to_string();
     Ok(PathBuf::from(p))
@@ -121,10 +121,10 @@ pub fn uninstall_git_hook(repo_root: &Path, force: bool) -> io::Result<()> {
             let _ = fs::remove_file(&p);
             continue;
         }
-        if let Ok(existing) = fs::read_to_string(&p) {
-            if existing.contains(HOOK_MARKER) {
-                let _ = fs::remove_file(&p);
-            }
+        if let Ok(existing) = fs::read_to_string(&p)
+            && existing.contains(HOOK_MARKER)
+        {
+            let _ = fs::remove_file(&p);
         }
     }
     Ok(())
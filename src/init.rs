@@ -104,6 +104,28 @@ pub fn run(repo_root: &Path) -> io::Result<()> {
         for p in &produced {
             println!("[init] encrypted -> {}", p.display());
         }
+        let keys = crate::config::read_key_map(repo_root)?;
+        // only files init just wrote may differ from the manifest; anything
+        // else is left for `eenv manifest verify` rather than re-signed
+        let written: Vec<String> = produced
+            .iter()
+            .map(|p| crate::crypto::binding_for(repo_root, p).path)
+            .collect();
+        let unexpected = crate::manifest::check(repo_root, &keys).map(|problems| {
+            problems
+                .unwrap_or_default()
+                .into_iter()
+                .find(|p| !written.iter().any(|w| w == p.path()))
+        });
+        match unexpected {
+            Ok(None) => {
+                if crate::manifest::update(repo_root, &keys)? {
+                    println!("[init] updated {}", crate::manifest::MANIFEST_FILE);
+                }
+            }
+            Ok(Some(p)) => eprintln!("[init] {} not updated: {p}", crate::manifest::MANIFEST_FILE),
+            Err(e) => eprintln!("[init] {} not updated: {e}", crate::manifest::MANIFEST_FILE),
+        }
     }
 
     // also make sure we ignore generated hooks if hooks path is inside the repo
@@ -0,0 +1,321 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::Rng;
+use serde_json::{Value, json};
+use std::io;
+
+pub const SALT_LEN: usize = 16;
+pub const DEFAULT_M_COST: u32 = 19 * 1024;
+pub const DEFAULT_T_COST: u32 = 2;
+pub const DEFAULT_P_COST: u32 = 1;
+
+// upper bounds for params read from untrusted headers (1 GiB, 10 passes,
+// 16 lanes), so a crafted .enc can't make every reader spend minutes or
+// unbounded memory on a derivation before the key id is even compared
+const MAX_M_COST: u32 = 1024 * 1024;
+const MAX_T_COST: u32 = 10;
+const MAX_P_COST: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KdfAlg {
+    /// Unsalted BLAKE3 of the key string (EENV1 files).
+    Blake3,
+    Argon2id,
+}
+
+impl KdfAlg {
+    pub fn id(self) -> u8 {
+        match self {
+            KdfAlg::Blake3 => 0,
+            KdfAlg::Argon2id => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> io::Result<Self> {
+        match id {
+            0 => Ok(KdfAlg::Blake3),
+            1 => Ok(KdfAlg::Argon2id),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown kdf id {other}"),
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KdfAlg::Blake3 => "blake3",
+            KdfAlg::Argon2id => "argon2id",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KdfParams {
+    pub alg: KdfAlg,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+    pub fn legacy() -> Self {
+        KdfParams {
+            alg: KdfAlg::Blake3,
+            m_cost: 0,
+            t_cost: 0,
+            p_cost: 0,
+            salt: Vec::new(),
+        }
+    }
+
+    pub fn generate() -> Self {
+        let salt: [u8; SALT_LEN] = rand::rng().random();
+        KdfParams {
+            alg: KdfAlg::Argon2id,
+            m_cost: DEFAULT_M_COST,
+            t_cost: DEFAULT_T_COST,
+            p_cost: DEFAULT_P_COST,
+            salt: salt.to_vec(),
+        }
+    }
+
+    pub fn validate(&self) -> io::Result<()> {
+        if self.alg == KdfAlg::Blake3 {
+            return Ok(());
+        }
+        let bad = |msg: &str| Err(io::Error::new(io::ErrorKind::InvalidData, msg.to_string()));
+        if self.salt.len() < 8 || self.salt.len() > 64 {
+            return bad("kdf salt must be 8..=64 bytes");
+        }
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            return bad("kdf cost settings out of range (max 1 GiB, 10 passes, 16 lanes)");
+        }
+        Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map(|_| ())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("bad kdf params: {e}")))
+    }
+
+    // id u8 | m_cost u32 | t_cost u32 | p_cost u32 | salt_len u8 | salt (little endian)
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.alg.id());
+        out.extend_from_slice(&self.m_cost.to_le_bytes());
+        out.extend_from_slice(&self.t_cost.to_le_bytes());
+        out.extend_from_slice(&self.p_cost.to_le_bytes());
+        out.push(self.salt.len() as u8);
+        out.extend_from_slice(&self.salt);
+    }
+
+    /// Reads params encoded in an EENV2 header. The unsalted BLAKE3 KDF only
+    /// exists in EENV1 files, which carry no params; accepting it here would
+    /// let anyone who can write a `.enc` downgrade it from Argon2id.
+    pub fn decode(buf: &[u8]) -> io::Result<(Self, usize)> {
+        let short = || io::Error::new(io::ErrorKind::InvalidData, "truncated kdf params");
+        if buf.len() < 14 {
+            return Err(short());
+        }
+        let alg = KdfAlg::from_id(buf[0])?;
+        if alg == KdfAlg::Blake3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsalted blake3 kdf is only valid in EENV1 files",
+            ));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        let salt_len = buf[13] as usize;
+        if buf.len() < 14 + salt_len {
+            return Err(short());
+        }
+        let params = KdfParams {
+            alg,
+            m_cost: u32_at(1),
+            t_cost: u32_at(5),
+            p_cost: u32_at(9),
+            salt: buf[14..14 + salt_len].to_vec(),
+        };
+        params.validate()?;
+        Ok((params, 14 + salt_len))
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "alg": self.alg.name(),
+            "m_cost": self.m_cost,
+            "t_cost": self.t_cost,
+            "p_cost": self.p_cost,
+            "salt": crate::util::to_hex(&self.salt),
+        })
+    }
+
+    pub fn from_json(v: &Value) -> io::Result<Self> {
+        let bad = |msg: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("bad kdf config: {msg}"))
+        };
+        let alg = match v.get("alg").and_then(|x| x.as_str()) {
+            Some("argon2id") => KdfAlg::Argon2id,
+            _ => return Err(bad("\"alg\" must be \"argon2id\"")),
+        };
+        let cost = |name: &str, default: u32| -> io::Result<u32> {
+            match v.get(name) {
+                None => Ok(default),
+                Some(x) => x
+                    .as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| bad(&format!("\"{name}\" must be an integer"))),
+            }
+        };
+        let salt = v
+            .get("salt")
+            .and_then(|x| x.as_str())
+            .and_then(crate::util::from_hex)
+            .ok_or_else(|| bad("\"salt\" must be hex"))?;
+        let params = KdfParams {
+            alg,
+            m_cost: cost("m_cost", DEFAULT_M_COST)?,
+            t_cost: cost("t_cost", DEFAULT_T_COST)?,
+            p_cost: cost("p_cost", DEFAULT_P_COST)?,
+            salt,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+pub fn derive_key(secret: &str, params: &KdfParams) -> io::Result<[u8; 32]> {
+    match params.alg {
+        KdfAlg::Blake3 => Ok(*blake3::hash(secret.as_bytes()).as_bytes()),
+        KdfAlg::Argon2id => {
+            params.validate()?;
+            let p = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32)).map_err(
+                |e| io::Error::new(io::ErrorKind::InvalidData, format!("bad kdf params: {e}")),
+            )?;
+            let mut out = [0u8; 32];
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, p)
+                .hash_password_into(secret.as_bytes(), &params.salt, &mut out)
+                .map_err(|e| io::Error::other(format!("kdf failed: {e}")))?;
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+impl KdfParams {
+    /// Minimal costs, so tests don't pay for a real derivation.
+    pub fn cheap() -> Self {
+        KdfParams {
+            m_cost: 8,
+            t_cost: 1,
+            ..KdfParams::generate()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(f: impl FnOnce(&mut KdfParams)) -> KdfParams {
+        let mut p = KdfParams::cheap();
+        f(&mut p);
+        p
+    }
+
+    #[test]
+    fn encode_decode_round_trips() {
+        let p = KdfParams::generate();
+        let mut buf = Vec::new();
+        p.encode(&mut buf);
+        buf.extend_from_slice(b"trailing");
+        let (back, used) = KdfParams::decode(&buf).unwrap();
+        assert_eq!(back, p);
+        assert_eq!(used, buf.len() - b"trailing".len());
+    }
+
+    #[test]
+    fn json_round_trips_and_fills_defaults() {
+        let p = KdfParams::generate();
+        assert_eq!(KdfParams::from_json(&p.to_json()).unwrap(), p);
+
+        let v = json!({ "alg": "argon2id", "salt": crate::util::to_hex(&p.salt) });
+        let back = KdfParams::from_json(&v).unwrap();
+        assert_eq!(
+            (back.m_cost, back.t_cost, back.p_cost),
+            (DEFAULT_M_COST, DEFAULT_T_COST, DEFAULT_P_COST)
+        );
+    }
+
+    #[test]
+    fn out_of_range_params_are_rejected() {
+        for p in [
+            with(|p| p.m_cost = MAX_M_COST + 1),
+            with(|p| p.t_cost = MAX_T_COST + 1),
+            with(|p| p.p_cost = MAX_P_COST + 1),
+            with(|p| p.t_cost = 0),
+            with(|p| p.p_cost = 0),
+            with(|p| p.salt = vec![0; 7]),
+            with(|p| p.salt = vec![0; 65]),
+        ] {
+            assert!(p.validate().is_err(), "{p:?}");
+            assert!(derive_key("k", &p).is_err(), "{p:?}");
+        }
+        let edge = with(|p| {
+            p.t_cost = MAX_T_COST;
+            p.p_cost = MAX_P_COST;
+            p.m_cost = 8 * MAX_P_COST;
+        });
+        assert!(edge.validate().is_ok());
+    }
+
+    #[test]
+    fn decode_rejects_bad_headers() {
+        let mut buf = Vec::new();
+        with(|p| p.t_cost = 1000).encode(&mut buf);
+        assert!(KdfParams::decode(&buf).is_err());
+
+        let mut buf = Vec::new();
+        KdfParams::cheap().encode(&mut buf);
+        assert!(KdfParams::decode(&buf[..13]).is_err());
+        assert!(KdfParams::decode(&buf[..buf.len() - 1]).is_err());
+        buf[0] = 9;
+        assert!(KdfParams::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn decode_refuses_the_legacy_kdf() {
+        let mut buf = Vec::new();
+        KdfParams::legacy().encode(&mut buf);
+        let err = KdfParams::decode(&buf).unwrap_err();
+        assert!(err.to_string().contains("EENV1"), "{err}");
+
+        // an Argon2id header downgraded in place
+        let mut buf = Vec::new();
+        KdfParams::cheap().encode(&mut buf);
+        buf[0] = KdfAlg::Blake3.id();
+        assert!(KdfParams::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn from_json_rejects_bad_values() {
+        let salt = crate::util::to_hex(&[1u8; SALT_LEN]);
+        for v in [
+            json!({ "alg": "scrypt", "salt": salt }),
+            json!({ "alg": "argon2id" }),
+            json!({ "alg": "argon2id", "salt": "xyz" }),
+            json!({ "alg": "argon2id", "salt": salt, "t_cost": -1 }),
+            json!({ "alg": "argon2id", "salt": salt, "t_cost": 11 }),
+            json!({ "alg": "argon2id", "salt": salt, "p_cost": 17 }),
+            json!({ "alg": "argon2id", "salt": salt, "m_cost": 5_000_000_000u64 }),
+        ] {
+            assert!(KdfParams::from_json(&v).is_err(), "{v}");
+        }
+    }
+
+    #[test]
+    fn derivation_depends_on_secret_and_salt() {
+        let p = KdfParams::cheap();
+        let k = derive_key("secret", &p).unwrap();
+        assert_eq!(derive_key("secret", &p).unwrap(), k);
+        assert_ne!(derive_key("other", &p).unwrap(), k);
+        assert_ne!(derive_key("secret", &KdfParams::cheap()).unwrap(), k);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::container::{Binding, Header, KEY_ID_LEN};
+use crate::kdf::{KdfParams, derive_key};
+use crate::recipients::{self, Identity};
+use chacha20poly1305::{XChaCha20Poly1305, aead::KeyInit};
+use ignore::gitignore::GitignoreBuilder;
+use rand::Rng;
+use std::{cell::RefCell, collections::HashMap, io, path::Path};
+use x25519_dalek::PublicKey;
+
+/// Everything this machine can decrypt with: the shared key (if any) and the
+/// local X25519 identities. Derived shared keys are cached so each distinct
+/// set of KDF params is only paid for once per run.
+pub struct Keyring {
+    secret: Option<String>,
+    identities: Vec<Identity>,
+    derived: RefCell<HashMap<KdfParams, [u8; 32]>>,
+}
+
+/// Which keyring encrypts each env file: `"keys"` entries (an environment
+/// name, or a glob over the repo-relative path) win over the default `"key"`.
+/// An entry with no key marks an environment this machine has no access to.
+pub struct KeyMap {
+    default: Option<Keyring>,
+    rules: Vec<(String, Option<Keyring>)>,
+}
+
+/// How new ciphertexts are keyed: from the shared key through the KDF, or
+/// with a random file key wrapped once per recipient.
+#[derive(Clone)]
+pub enum KeyScheme {
+    Shared(KdfParams),
+    Recipients(Vec<PublicKey>),
+}
+
+impl Keyring {
+    pub fn new(secret: Option<&str>, identities: Vec<Identity>) -> io::Result<Self> {
+        let secret = secret.map(str::trim).filter(|s| !s.is_empty());
+        if secret.is_none() && identities.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no key or identity configured",
+            ));
+        }
+        Ok(Keyring {
+            secret: secret.map(str::to_string),
+            identities,
+            derived: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub fn from_secret(secret: &str) -> io::Result<Self> {
+        if secret.trim().is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty key"));
+        }
+        Self::new(Some(secret), Vec::new())
+    }
+
+    pub fn secret(&self) -> Option<&str> {
+        self.secret.as_deref()
+    }
+
+    pub fn identities(&self) -> &[Identity] {
+        &self.identities
+    }
+
+    pub fn derive(&self, params: &KdfParams) -> io::Result<[u8; 32]> {
+        if let Some(k) = self.derived.borrow().get(params) {
+            return Ok(*k);
+        }
+        let secret = self.secret.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "file uses the shared key but no key is configured",
+            )
+        })?;
+        let k = derive_key(secret, params)?;
+        self.derived.borrow_mut().insert(params.clone(), k);
+        Ok(k)
+    }
+
+    /// Short public identifier of the derived key; safe to store in headers.
+    pub fn key_id(&self, params: &KdfParams) -> io::Result<[u8; KEY_ID_LEN]> {
+        let k = self.derive(params)?;
+        let h = blake3::derive_key("eenv key-id v1", &k);
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&h[..KEY_ID_LEN]);
+        Ok(id)
+    }
+
+    /// The key that seals the body of a file with this header. A key id in
+    /// the header that isn't ours fails here, naming both fingerprints.
+    pub fn content_key(&self, header: &Header) -> io::Result<[u8; 32]> {
+        if let Some(params) = &header.kdf {
+            if let Some(theirs) = &header.key_id {
+                let ours = self.key_id(params)?;
+                if ours != *theirs {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!(
+                            "this file was encrypted with key {}, you have key {}",
+                            fingerprint(theirs),
+                            fingerprint(&ours)
+                        ),
+                    ));
+                }
+            }
+            return self.derive(params);
+        }
+        for identity in &self.identities {
+            for stanza in &header.recipients {
+                if let Some(k) = recipients::unwrap(stanza, identity) {
+                    return Ok(k);
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            if self.identities.is_empty() {
+                "file is encrypted to recipients but no identity is configured (eenv identity new)"
+            } else {
+                "none of your identities is a recipient of this file"
+            },
+        ))
+    }
+
+    pub fn aead(&self, header: &Header) -> io::Result<XChaCha20Poly1305> {
+        let k = self.content_key(header)?;
+        Ok(XChaCha20Poly1305::new((&k).into()))
+    }
+}
+
+/// Short form of a key id for people to compare (`eenv key fingerprint`).
+pub fn fingerprint(key_id: &[u8; KEY_ID_LEN]) -> String {
+    crate::util::to_hex(&key_id[..4])
+}
+
+impl KeyMap {
+    pub fn new(default: Option<Keyring>, mut rules: Vec<(String, Option<Keyring>)>) -> Self {
+        rules.sort_by_key(|(sel, _)| rule_order(sel));
+        KeyMap { default, rules }
+    }
+
+    pub fn default(&self) -> Option<&Keyring> {
+        self.default.as_ref()
+    }
+
+    /// Every configured key: `None` is the default `"key"`, then each
+    /// `"keys"` entry (with `None` for one that isn't available here).
+    pub fn entries(&self) -> Vec<(Option<&str>, Option<&Keyring>)> {
+        let mut out = vec![(None, self.default.as_ref())];
+        out.extend(
+            self.rules
+                .iter()
+                .map(|(sel, k)| (Some(sel.as_str()), k.as_ref())),
+        );
+        out
+    }
+
+    /// The `"keys"` entry that applies to `binding`, or `None` for the default key.
+    pub fn selector_for(&self, binding: &Binding) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(sel, _)| selector_matches(sel, binding))
+            .map(|(sel, _)| sel.as_str())
+    }
+
+    /// Whether `binding` uses `selector` (`None`: the default key), counting
+    /// a selector that isn't in `"keys"` yet as if it had been added.
+    pub fn selects(&self, selector: Option<&str>, binding: &Binding) -> bool {
+        let current = self.selector_for(binding);
+        let Some(sel) = selector else {
+            return current.is_none();
+        };
+        selector_matches(sel, binding)
+            && current.is_none_or(|cur| cur == sel || rule_order(sel) < rule_order(cur))
+    }
+
+    pub fn keyring_for(&self, binding: &Binding) -> io::Result<&Keyring> {
+        let missing = |what: String| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no key for {what} in eenv.config.json"),
+            )
+        };
+        match self
+            .rules
+            .iter()
+            .find(|(sel, _)| selector_matches(sel, binding))
+        {
+            Some((_, Some(k))) => Ok(k),
+            Some((sel, None)) => Err(missing(format!("\"{sel}\""))),
+            None => self
+                .default
+                .as_ref()
+                .ok_or_else(|| missing(format!("env {}", binding.env))),
+        }
+    }
+}
+
+// environment names before globs, longer (more specific) globs first
+fn rule_order(selector: &str) -> (bool, std::cmp::Reverse<usize>) {
+    (is_glob(selector), std::cmp::Reverse(selector.len()))
+}
+
+fn is_glob(selector: &str) -> bool {
+    selector.contains(['*', '?', '[', '/', '.'])
+}
+
+/// An environment name matches exactly; anything else is a gitignore-style
+/// glob over the repo-relative path of the plaintext file.
+pub fn selector_matches(selector: &str, binding: &Binding) -> bool {
+    if !is_glob(selector) {
+        return selector == binding.env;
+    }
+    let plain = binding.path.strip_suffix(".enc").unwrap_or(&binding.path);
+    let mut builder = GitignoreBuilder::new("");
+    if builder.add_line(None, selector).is_err() {
+        return false;
+    }
+    builder
+        .build()
+        .is_ok_and(|gi| gi.matched(Path::new(plain), false).is_ignore())
+}
+
+impl KeyScheme {
+    /// A fresh header for `binding` and the content key it seals with.
+    pub fn new_header(
+        &self,
+        keyring: &Keyring,
+        binding: &Binding,
+    ) -> io::Result<(Header, [u8; 32])> {
+        let mut header = Header::new(binding.clone());
+        match self {
+            KeyScheme::Shared(params) => {
+                header.kdf = Some(params.clone());
+                header.key_id = Some(keyring.key_id(params)?);
+                Ok((header, keyring.derive(params)?))
+            }
+            KeyScheme::Recipients(pks) => {
+                let file_key: [u8; 32] = rand::rng().random();
+                for pk in pks {
+                    header.recipients.push(recipients::wrap(&file_key, pk)?);
+                }
+                Ok((header, file_key))
+            }
+        }
+    }
+
+    /// Whether `header` was written under this scheme: same KDF params, or
+    /// exactly the same recipient set.
+    pub fn matches(&self, header: &Header) -> bool {
+        match self {
+            KeyScheme::Shared(params) => header.kdf.as_ref() == Some(params),
+            KeyScheme::Recipients(pks) => {
+                header.kdf.is_none()
+                    && header.recipients.len() == pks.len()
+                    && header.recipients.iter().all(|s| {
+                        recipients::stanza_recipient(s).is_some_and(|pk| pks.contains(&pk))
+                    })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(path: &str) -> Binding {
+        Binding {
+            path: path.into(),
+            env: crate::envscan::env_name_for(Path::new(path)),
+        }
+    }
+
+    fn keyring(secret: &str) -> Option<Keyring> {
+        Some(Keyring::from_secret(secret).unwrap())
+    }
+
+    // which secret `keyring_for` picks, or the error
+    fn pick(map: &KeyMap, path: &str) -> Result<String, String> {
+        map.keyring_for(&binding(path))
+            .map(|k| k.secret().unwrap().to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    fn map() -> KeyMap {
+        KeyMap::new(
+            keyring("default"),
+            vec![
+                ("services/*/.env*".into(), keyring("services")),
+                ("services/billing/.env*".into(), keyring("billing")),
+                ("production".into(), keyring("production")),
+                ("staging".into(), None),
+            ],
+        )
+    }
+
+    #[test]
+    fn environment_names_match_exactly() {
+        assert!(selector_matches(
+            "production",
+            &binding(".env.production.enc")
+        ));
+        assert!(!selector_matches("prod", &binding(".env.production.enc")));
+        assert!(!selector_matches("production", &binding(".env.enc")));
+    }
+
+    #[test]
+    fn local_layers_share_their_environment_key() {
+        let map = map();
+        assert_eq!(binding(".env.production.local.enc").env, "production");
+        assert_eq!(
+            pick(&map, ".env.production.local.enc").unwrap(),
+            "production"
+        );
+        assert_eq!(binding(".env.local.enc").env, "default");
+        assert_eq!(pick(&map, ".env.local.enc").unwrap(), "default");
+        let err = pick(&map, ".env.staging.local.enc").unwrap_err();
+        assert!(err.contains("no key for \"staging\""), "{err}");
+    }
+
+    #[test]
+    fn globs_match_the_plaintext_path() {
+        assert!(selector_matches(".env.*", &binding(".env.production.enc")));
+        assert!(selector_matches("/.env", &binding(".env.enc")));
+        assert!(!selector_matches("/.env", &binding("api/.env.enc")));
+        assert!(selector_matches("api/.env*", &binding("api/.env.test.enc")));
+        assert!(!selector_matches("api/.env*", &binding("web/.env.enc")));
+    }
+
+    #[test]
+    fn environment_wins_then_the_longest_glob() {
+        let map = map();
+        assert_eq!(pick(&map, ".env.enc").unwrap(), "default");
+        assert_eq!(pick(&map, "services/api/.env.enc").unwrap(), "services");
+        assert_eq!(pick(&map, "services/billing/.env.enc").unwrap(), "billing");
+        // "production" names the environment, so it beats both globs
+        assert_eq!(
+            pick(&map, "services/billing/.env.production.enc").unwrap(),
+            "production"
+        );
+        assert_eq!(
+            map.selector_for(&binding("services/billing/.env.enc")),
+            Some("services/billing/.env*")
+        );
+        assert_eq!(map.selector_for(&binding(".env.enc")), None);
+    }
+
+    #[test]
+    fn entries_without_a_key_are_not_available() {
+        let map = map();
+        let err = pick(&map, ".env.staging.enc").unwrap_err();
+        assert!(err.contains("no key for \"staging\""), "{err}");
+
+        let no_default = KeyMap::new(None, vec![("production".into(), keyring("p"))]);
+        let err = pick(&no_default, ".env.enc").unwrap_err();
+        assert!(err.contains("no key for env default"), "{err}");
+        assert_eq!(pick(&no_default, ".env.production.enc").unwrap(), "p");
+    }
+
+    #[test]
+    fn selects_counts_new_entries_as_added() {
+        let map = map();
+        let api = binding("services/api/.env.enc");
+        assert!(map.selects(Some("services/*/.env*"), &api));
+        assert!(!map.selects(None, &api));
+        // a more specific entry that doesn't exist yet would take it over
+        assert!(map.selects(Some("services/api/.env*"), &api));
+        // a less specific one wouldn't
+        assert!(!map.selects(Some("*"), &api));
+        assert!(map.selects(None, &binding(".env.enc")));
+        assert!(map.selects(Some("development"), &binding(".env.development.enc")));
+    }
+
+    #[test]
+    fn scheme_matches_exact_params_and_recipient_sets() {
+        let params = KdfParams::cheap();
+        let key = Keyring::from_secret("k").unwrap();
+        let shared = KeyScheme::Shared(params.clone());
+        let (header, _) = shared.new_header(&key, &binding(".env.enc")).unwrap();
+        assert!(shared.matches(&header));
+        assert!(!KeyScheme::Shared(KdfParams::cheap()).matches(&header));
+
+        let ids: Vec<Identity> = (0..3).map(|_| Identity::generate()).collect();
+        let pks = |i: &[usize]| KeyScheme::Recipients(i.iter().map(|&i| ids[i].public()).collect());
+        let (header, _) = pks(&[0, 1]).new_header(&key, &binding(".env.enc")).unwrap();
+        assert!(pks(&[0, 1]).matches(&header));
+        assert!(pks(&[1, 0]).matches(&header));
+        assert!(!pks(&[0, 2]).matches(&header));
+        assert!(!pks(&[0]).matches(&header));
+        assert!(!pks(&[0, 1, 2]).matches(&header));
+        assert!(!shared.matches(&header));
+    }
+
+    #[test]
+    fn key_ids_differ_per_key_and_name_the_mismatch() {
+        let params = KdfParams::cheap();
+        let (a, b) = (
+            Keyring::from_secret("a").unwrap(),
+            Keyring::from_secret("b").unwrap(),
+        );
+        assert_ne!(a.key_id(&params).unwrap(), b.key_id(&params).unwrap());
+        let (header, _) = KeyScheme::Shared(params.clone())
+            .new_header(&a, &binding(".env.enc"))
+            .unwrap();
+        assert!(a.content_key(&header).is_ok());
+        let err = b.content_key(&header).unwrap_err().to_string();
+        let theirs = fingerprint(&a.key_id(&params).unwrap());
+        assert!(
+            err.contains(&format!("encrypted with key {theirs}")),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{Mutex, OnceLock},
+};
+
+// Where the default key comes from, first match wins:
+//
+//   1. --key-file <path> / --key-stdin
+//   2. the EENV_KEY environment variable
+//   3. "key_command" in eenv.config.json (its stdout)
+//   4. "key" in eenv.config.json
+//
+// 1-3 never write the key to disk. The result is resolved once per process,
+// since stdin can only be read once and a key command may prompt.
+pub const KEY_ENV_VAR: &str = "EENV_KEY";
+
+#[derive(Debug)]
+pub enum CliSource {
+    File(PathBuf),
+    Stdin,
+}
+
+static CLI: OnceLock<Option<CliSource>> = OnceLock::new();
+static RESOLVED: Mutex<Option<Option<(String, &'static str)>>> = Mutex::new(None);
+
+/// Records `--key-file` / `--key-stdin`; called once from the CLI.
+pub fn set_cli_source(source: Option<CliSource>) {
+    let _ = CLI.set(source);
+}
+
+fn cli_source() -> Option<&'static CliSource> {
+    CLI.get().and_then(|s| s.as_ref())
+}
+
+fn env_key() -> Option<String> {
+    std::env::var(KEY_ENV_VAR)
+        .ok()
+        .filter(|k| !k.trim().is_empty())
+}
+
+fn key_command(map: &Map<String, Value>) -> Option<&str> {
+    map.get("key_command")
+        .and_then(|c| c.as_str())
+        .filter(|c| !c.trim().is_empty())
+}
+
+/// Whether a source other than `"key"` supplies the key (without reading it).
+pub fn has_external(map: &Map<String, Value>) -> bool {
+    cli_source().is_some() || env_key().is_some() || key_command(map).is_some()
+}
+
+fn non_empty(key: String, source: &str) -> io::Result<String> {
+    let key = key.trim().to_string();
+    if key.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("empty key from {source}"),
+        ));
+    }
+    Ok(key)
+}
+
+fn run_key_command(cmd: &str) -> io::Result<String> {
+    let mut command = if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.args(["/C", cmd]);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    };
+    let out = command
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("key_command `{cmd}`: {e}")))?;
+    if !out.status.success() {
+        return Err(io::Error::other(format!(
+            "key_command `{cmd}` failed ({})",
+            out.status
+        )));
+    }
+    String::from_utf8(out.stdout).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("key_command `{cmd}` printed non-UTF-8 output"),
+        )
+    })
+}
+
+impl CliSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CliSource::File(_) => "--key-file",
+            CliSource::Stdin => "--key-stdin",
+        }
+    }
+
+    /// Reads the key from the file or stdin.
+    pub fn read(&self) -> io::Result<String> {
+        let key = match self {
+            CliSource::File(path) => fs::read_to_string(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("--key-file {}: {e}", path.display()))
+            })?,
+            CliSource::Stdin => {
+                let mut key = String::new();
+                io::stdin().read_to_string(&mut key)?;
+                key
+            }
+        };
+        non_empty(key, self.name())
+    }
+}
+
+fn resolve(map: &Map<String, Value>) -> io::Result<Option<(String, &'static str)>> {
+    pick(cli_source(), env_key(), map)
+}
+
+// The precedence above, given the CLI source and `EENV_KEY`.
+fn pick(
+    cli: Option<&CliSource>,
+    env: Option<String>,
+    map: &Map<String, Value>,
+) -> io::Result<Option<(String, &'static str)>> {
+    if let Some(source) = cli {
+        return Ok(Some((source.read()?, source.name())));
+    }
+    if let Some(key) = env {
+        return Ok(Some((key.trim().to_string(), KEY_ENV_VAR)));
+    }
+    if let Some(cmd) = key_command(map) {
+        return Ok(Some((
+            non_empty(run_key_command(cmd)?, "key_command")?,
+            "key_command",
+        )));
+    }
+    Ok(None)
+}
+
+/// The default key from sources 1-3 above with the name of the source, or
+/// `None` to fall back to `"key"` in eenv.config.json.
+pub fn external_key(map: &Map<String, Value>) -> io::Result<Option<(String, &'static str)>> {
+    let mut resolved = RESOLVED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(r) = resolved.as_ref() {
+        return Ok(r.clone());
+    }
+    let r = resolve(map)?;
+    *resolved = Some(r.clone());
+    Ok(r)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::util::scratch_dir;
+    use serde_json::json;
+
+    fn config(v: Value) -> Map<String, Value> {
+        v.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn sources_are_tried_in_order() {
+        let dir = scratch_dir("keysource-order");
+        let file = dir.join("key");
+        fs::write(&file, "from-file\n").unwrap();
+        let cli = CliSource::File(file);
+        let env = || Some("from-env".to_string());
+        let map = config(json!({ "key": "from-config", "key_command": "echo from-command" }));
+
+        let got = pick(Some(&cli), env(), &map).unwrap();
+        assert_eq!(got, Some(("from-file".into(), "--key-file")));
+        let got = pick(None, env(), &map).unwrap();
+        assert_eq!(got, Some(("from-env".into(), KEY_ENV_VAR)));
+        let got = pick(None, None, &map).unwrap();
+        assert_eq!(got, Some(("from-command".into(), "key_command")));
+        // "key" itself is the caller's fallback
+        let got = pick(None, None, &config(json!({ "key": "from-config" }))).unwrap();
+        assert_eq!(got, None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn blank_command_is_no_source() {
+        let map = config(json!({ "key_command": "  " }));
+        assert!(key_command(&map).is_none());
+        assert_eq!(pick(None, None, &map).unwrap(), None);
+    }
+
+    #[test]
+    fn empty_or_failing_sources_are_errors_not_fallbacks() {
+        let dir = scratch_dir("keysource-errors");
+        let file = dir.join("key");
+        fs::write(&file, "\n").unwrap();
+        let map = config(json!({ "key": "from-config", "key_command": "echo from-command" }));
+        let err = pick(Some(&CliSource::File(file)), None, &map).unwrap_err();
+        assert!(err.to_string().contains("--key-file"), "{err}");
+        let missing = CliSource::File(dir.join("missing"));
+        assert!(pick(Some(&missing), None, &map).is_err());
+
+        for cmd in ["true", "exit 3"] {
+            let map = config(json!({ "key": "from-config", "key_command": cmd }));
+            let err = pick(None, None, &map).unwrap_err();
+            assert!(err.to_string().contains("key_command"), "{err}");
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
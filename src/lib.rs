@@ -1,15 +1,37 @@
+mod about;
+mod agefmt;
+mod check;
 mod cli;
 mod config;
+mod container;
 mod crypto;
+mod dotenv;
+mod drift;
 mod envscan;
 mod examples;
+mod expand;
+mod fingerprint;
 mod gitignore;
 mod hooks;
 mod init;
-mod about;
+mod kdf;
+mod keyring;
+mod keysource;
+mod manifest;
+mod passphrase;
 mod precommit;
+mod recipients;
+mod resolve;
+mod rotate;
+mod run;
+mod schema;
+mod shamir;
+mod signing;
+mod stream;
+mod sync;
 mod types;
 mod util;
+mod valuecrypt;
 
 pub use crate::cli::Cli;
 pub use crate::types::*;
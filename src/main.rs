@@ -1,3 +1,3 @@
 fn main() -> std::io::Result<()> {
     eenv::run()
-}
\ No newline at end of file
+}
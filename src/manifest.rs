@@ -0,0 +1,809 @@
+use crate::config::{read_manifest_seen, read_signing_key, write_manifest_seen};
+use crate::crypto::{binding_for, is_eenv_file, repo_key_scheme};
+use crate::envscan::find_enc_files_recursive;
+use crate::keyring::{KeyMap, KeyScheme};
+use crate::signing::{self, SigningIdentity};
+use crate::types::ManifestAction;
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use ed25519_dalek::{Signature, Verifier};
+use serde_json::{Map, Value, json};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::Path,
+    path::PathBuf,
+};
+
+// eenv.manifest lists every encrypted file with the BLAKE3 hash of its
+// ciphertext and a version that goes up whenever it changes:
+//
+//   { "files": { ".env.production.enc": { "blake3": "<hex>", "version": 3, "mac": "<hex>" } } }
+//
+// Each entry is authenticated with the key that protects its file: a MAC
+// under a key derived from that file's shared key, so someone holding only
+// the development key can't vouch for production, or with eenv.recipients an
+// ed25519 signature ("signer", "sig") from one of the file's signers in
+// eenv.signers. Entries that can't be authenticated are refused. With
+// eenv.recipients, a file no line of eenv.signers covers has nobody who could
+// sign for it, so its entry is written unsigned: it still catches missing,
+// replaced and rolled-back files, but not a forged entry. A deleted .enc
+// keeps its entry as a tombstone ("blake3": null).
+//
+// Each machine remembers the highest version of every entry it has verified,
+// so restoring an older ciphertext together with its older entry, dropping
+// an entry or deleting the manifest shows up too (versions it only wrote,
+// e.g. in an aborted commit, don't count).
+pub const MANIFEST_FILE: &str = "eenv.manifest";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Auth {
+    Mac(String),
+    Sig { signer: String, sig: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    /// `None` for a deleted file.
+    hash: Option<String>,
+    version: u64,
+    auth: Option<Auth>,
+}
+
+/// A file whose state disagrees with the manifest.
+#[derive(Debug)]
+pub enum Problem {
+    Missing(String),
+    Changed(String),
+    Unlisted(String),
+    Unauthenticated(String),
+    RolledBack {
+        path: String,
+        version: u64,
+        seen: u64,
+    },
+    Dropped {
+        path: String,
+        seen: u64,
+    },
+    /// A listed file moved by hand: `from` is missing and `to`, unlisted,
+    /// holds the same ciphertext.
+    Moved {
+        from: String,
+        to: String,
+    },
+}
+
+impl Problem {
+    pub fn path(&self) -> &str {
+        match self {
+            Problem::Missing(p)
+            | Problem::Changed(p)
+            | Problem::Unlisted(p)
+            | Problem::Unauthenticated(p)
+            | Problem::RolledBack { path: p, .. }
+            | Problem::Dropped { path: p, .. }
+            | Problem::Moved { to: p, .. } => p,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Missing(p) => write!(f, "{p} is listed in {MANIFEST_FILE} but missing"),
+            Problem::Changed(p) => write!(
+                f,
+                "{p} does not match {MANIFEST_FILE} (rolled back or replaced outside eenv?)"
+            ),
+            Problem::Unlisted(p) => write!(f, "{p} is not listed in {MANIFEST_FILE}"),
+            Problem::Unauthenticated(p) => write!(
+                f,
+                "{p}: its {MANIFEST_FILE} entry is not authenticated by the key that protects it \
+                 (edited by hand, or written without that key?)"
+            ),
+            Problem::RolledBack {
+                path,
+                version,
+                seen,
+            } => write!(
+                f,
+                "{path} was rolled back: version {version} in {MANIFEST_FILE}, \
+                 but this machine has seen version {seen}"
+            ),
+            Problem::Dropped { path, seen } => write!(
+                f,
+                "{path} was dropped from {MANIFEST_FILE} (this machine has seen version {seen})"
+            ),
+            Problem::Moved { from, to } => write!(
+                f,
+                "{from} was moved to {to} outside eenv; run `eenv mv {to} {to}` to re-bind it"
+            ),
+        }
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub fn manifest_path(repo_root: &Path) -> PathBuf {
+    repo_root.join(MANIFEST_FILE)
+}
+
+// what an entry's MAC or signature covers
+fn message(path: &str, hash: Option<&str>, version: u64) -> Vec<u8> {
+    let mut msg = b"eenv manifest v2\0".to_vec();
+    msg.extend_from_slice(path.as_bytes());
+    msg.push(0);
+    msg.extend_from_slice(hash.unwrap_or("").as_bytes());
+    msg.push(0);
+    msg.extend_from_slice(&version.to_le_bytes());
+    msg
+}
+
+#[derive(PartialEq, Eq)]
+enum Trust {
+    Verified,
+    /// Recipients mode, and eenv.signers names nobody for the file.
+    Unsigned,
+    /// This machine has no key for the file, so it can't tell.
+    Unknown,
+    Forged,
+}
+
+/// Authenticates entries with the keys this machine has.
+struct Voucher<'a> {
+    repo_root: &'a Path,
+    keys: &'a KeyMap,
+    scheme: KeyScheme,
+    signer: Option<SigningIdentity>,
+}
+
+impl<'a> Voucher<'a> {
+    // `signing` loads this machine's signing key, for writing entries
+    fn new(repo_root: &'a Path, keys: &'a KeyMap, signing: bool) -> io::Result<Self> {
+        let scheme = repo_key_scheme(repo_root)?;
+        let signer = match scheme {
+            KeyScheme::Recipients(_) if signing => read_signing_key(repo_root)?,
+            _ => None,
+        };
+        Ok(Voucher {
+            repo_root,
+            keys,
+            scheme,
+            signer,
+        })
+    }
+
+    // MAC key from the shared key protecting `path`, through the repo KDF so
+    // the manifest is no faster to brute-force than the .enc files
+    fn mac_key(&self, path: &str) -> io::Result<Option<[u8; 32]>> {
+        let KeyScheme::Shared(params) = &self.scheme else {
+            return Ok(None);
+        };
+        let binding = binding_for(self.repo_root, &self.repo_root.join(path));
+        let Some(keyring) = self
+            .keys
+            .keyring_for(&binding)
+            .ok()
+            .filter(|k| k.secret().is_some())
+        else {
+            return Ok(None);
+        };
+        let derived = keyring.derive(params)?;
+        Ok(Some(blake3::derive_key("eenv manifest v2", &derived)))
+    }
+
+    fn mac(key: &[u8; 32], msg: &[u8]) -> String {
+        blake3::keyed_hash(key, msg).to_hex().to_string()
+    }
+
+    fn verify(&self, path: &str, entry: &Entry) -> io::Result<Trust> {
+        let msg = message(path, entry.hash.as_deref(), entry.version);
+        if let KeyScheme::Shared(_) = self.scheme {
+            let Some(key) = self.mac_key(path)? else {
+                return Ok(Trust::Unknown);
+            };
+            return Ok(match &entry.auth {
+                Some(Auth::Mac(mac)) if *mac == Self::mac(&key, &msg) => Trust::Verified,
+                _ => Trust::Forged,
+            });
+        }
+        let binding = binding_for(self.repo_root, &self.repo_root.join(path));
+        let approved = signing::approved_signers(self.repo_root, &binding)?;
+        if approved.is_empty() {
+            return Ok(Trust::Unsigned);
+        }
+        // once the file has signers, an entry without a signature is refused
+        let Some(Auth::Sig { signer, sig }) = &entry.auth else {
+            return Ok(Trust::Forged);
+        };
+        let valid = signing::parse_public(signer).ok().is_some_and(|pk| {
+            approved.contains(&pk)
+                && B64
+                    .decode(sig)
+                    .ok()
+                    .and_then(|b| Signature::from_slice(&b).ok())
+                    .is_some_and(|sig| pk.verify(&msg, &sig).is_ok())
+        });
+        Ok(if valid {
+            Trust::Verified
+        } else {
+            Trust::Forged
+        })
+    }
+
+    // a fresh entry, or None when this machine can't vouch for `path`
+    fn vouch(&self, path: &str, hash: Option<String>, version: u64) -> io::Result<Option<Entry>> {
+        let msg = message(path, hash.as_deref(), version);
+        let auth = match &self.scheme {
+            KeyScheme::Shared(_) => self
+                .mac_key(path)?
+                .map(|k| Some(Auth::Mac(Self::mac(&k, &msg)))),
+            KeyScheme::Recipients(_) => {
+                let binding = binding_for(self.repo_root, &self.repo_root.join(path));
+                let approved = signing::approved_signers(self.repo_root, &binding)?;
+                if approved.is_empty() {
+                    Some(None)
+                } else {
+                    self.signer
+                        .as_ref()
+                        .filter(|s| approved.contains(&s.public()))
+                        .map(|s| {
+                            Some(Auth::Sig {
+                                signer: signing::encode_public(&s.public()),
+                                sig: B64.encode(s.sign(&msg).to_bytes()),
+                            })
+                        })
+                }
+            }
+        };
+        Ok(auth.map(|auth| Entry {
+            hash,
+            version,
+            auth,
+        }))
+    }
+}
+
+fn load(repo_root: &Path) -> io::Result<Option<BTreeMap<String, Entry>>> {
+    let path = manifest_path(repo_root);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bad = |msg: &str| invalid(format!("bad {MANIFEST_FILE}: {msg}"));
+    let v: Value =
+        serde_json::from_str(&fs::read_to_string(&path)?).map_err(|e| bad(&e.to_string()))?;
+    let mut files = BTreeMap::new();
+    for (p, e) in v
+        .get("files")
+        .and_then(|x| x.as_object())
+        .ok_or_else(|| bad("\"files\" must be an object"))?
+    {
+        let hash = match e.get("blake3") {
+            Some(Value::Null) => Some(None),
+            Some(Value::String(h)) => Some(Some(h.clone())),
+            _ => None,
+        };
+        let ver = e.get("version").and_then(|x| x.as_u64());
+        let (Some(hash), Some(version)) = (hash, ver) else {
+            return Err(bad(&format!(
+                "entry for {p} needs \"blake3\" and \"version\""
+            )));
+        };
+        let text = |k: &str| e.get(k).and_then(|x| x.as_str()).map(str::to_string);
+        let auth = match (text("mac"), text("signer"), text("sig")) {
+            (Some(mac), None, None) => Some(Auth::Mac(mac)),
+            (None, Some(signer), Some(sig)) => Some(Auth::Sig { signer, sig }),
+            _ => None,
+        };
+        files.insert(
+            p.clone(),
+            Entry {
+                hash,
+                version,
+                auth,
+            },
+        );
+    }
+    Ok(Some(files))
+}
+
+fn save(repo_root: &Path, files: &BTreeMap<String, Entry>) -> io::Result<()> {
+    let files: Map<String, Value> = files
+        .iter()
+        .map(|(p, e)| {
+            let mut v = json!({ "blake3": e.hash, "version": e.version });
+            match &e.auth {
+                Some(Auth::Mac(mac)) => v["mac"] = json!(mac),
+                Some(Auth::Sig { signer, sig }) => {
+                    v["signer"] = json!(signer);
+                    v["sig"] = json!(sig);
+                }
+                None => {}
+            }
+            (p.clone(), v)
+        })
+        .collect();
+    let mut pretty = serde_json::to_string_pretty(&json!({ "files": files }))
+        .map_err(|e| invalid(e.to_string()))?;
+    pretty.push('\n');
+    crate::util::write_string_atomic(&manifest_path(repo_root), &pretty)
+}
+
+// repo-relative path -> ciphertext hash for every eenv .enc file on disk
+fn scan(repo_root: &Path) -> io::Result<BTreeMap<String, String>> {
+    let mut out = BTreeMap::new();
+    for path in find_enc_files_recursive(repo_root)? {
+        let data = fs::read(&path)?;
+        if is_eenv_file(&data) {
+            let rel = binding_for(repo_root, &path).path;
+            out.insert(rel, blake3::hash(&data).to_hex().to_string());
+        }
+    }
+    Ok(out)
+}
+
+/// Verifies eenv.manifest against the .enc files on disk and returns every
+/// file that disagrees with it, or whose entry can't be trusted. `None` when
+/// the repo has no manifest yet; an error when it had one on this machine.
+pub fn check(repo_root: &Path, keys: &KeyMap) -> io::Result<Option<Vec<Problem>>> {
+    let mut seen = read_manifest_seen(repo_root)?;
+    let Some(files) = load(repo_root)? else {
+        // a fresh repo has no manifest, but one this machine verified can't
+        // just disappear
+        if seen.values().any(|&v| v > 0) {
+            return Err(invalid(format!(
+                "{MANIFEST_FILE} is missing, but this machine has verified it before; \
+                 restore it from git, or run `eenv manifest update` if it was deleted on purpose"
+            )));
+        }
+        return Ok(None);
+    };
+    let voucher = Voucher::new(repo_root, keys, false)?;
+    let seen_before = seen.clone();
+    let on_disk = scan(repo_root)?;
+    let mut problems = Vec::new();
+    for (path, entry) in &files {
+        match voucher.verify(path, entry)? {
+            Trust::Forged => {
+                problems.push(Problem::Unauthenticated(path.clone()));
+                continue;
+            }
+            Trust::Verified | Trust::Unsigned => {
+                let last = seen.get(path).copied().unwrap_or(0);
+                if entry.version < last {
+                    problems.push(Problem::RolledBack {
+                        path: path.clone(),
+                        version: entry.version,
+                        seen: last,
+                    });
+                    continue;
+                }
+                seen.insert(path.clone(), entry.version);
+            }
+            Trust::Unknown => {}
+        }
+        match (on_disk.get(path), &entry.hash) {
+            (None, Some(_)) => problems.push(Problem::Missing(path.clone())),
+            (Some(hash), Some(listed)) if hash != listed => {
+                problems.push(Problem::Changed(path.clone()))
+            }
+            (Some(_), None) => problems.push(Problem::Unlisted(path.clone())),
+            _ => {}
+        }
+    }
+    for (path, &last) in &seen_before {
+        if !files.contains_key(path) {
+            problems.push(Problem::Dropped {
+                path: path.clone(),
+                seen: last,
+            });
+        }
+    }
+    for path in on_disk.keys() {
+        if !files.contains_key(path) && !seen_before.contains_key(path) {
+            problems.push(Problem::Unlisted(path.clone()));
+        }
+    }
+    // a file moved by hand shows up as a missing path plus an unlisted one
+    // holding the same ciphertext
+    let moved: Vec<(String, String)> = problems
+        .iter()
+        .filter_map(|p| {
+            let Problem::Unlisted(to) = p else {
+                return None;
+            };
+            let hash = on_disk.get(to)?;
+            let from = problems.iter().find_map(|q| match q {
+                Problem::Missing(m) if files[m].hash.as_ref() == Some(hash) => Some(m),
+                _ => None,
+            })?;
+            Some((from.clone(), to.clone()))
+        })
+        .collect();
+    for (from, to) in moved {
+        problems.retain(|p| match p {
+            Problem::Missing(m) => *m != from,
+            Problem::Unlisted(u) => *u != to,
+            _ => true,
+        });
+        problems.push(Problem::Moved { from, to });
+    }
+    if seen != seen_before {
+        write_manifest_seen(repo_root, &seen)?;
+    }
+    Ok(Some(problems))
+}
+
+/// Fails, listing every problem, unless the .enc files on disk agree with
+/// eenv.manifest (or there is none yet). Commands that rewrite .enc files run
+/// this first, so `update` never vouches for a file that was rolled back or
+/// replaced behind eenv's back. `rebinding` is the file `eenv mv` is about to
+/// re-bind, which may have been moved there by hand.
+pub fn ensure_clean(repo_root: &Path, keys: &KeyMap, rebinding: Option<&str>) -> io::Result<()> {
+    let mut problems = check(repo_root, keys)?.unwrap_or_default();
+    problems.retain(|p| !matches!(p, Problem::Moved { to, .. } if Some(to.as_str()) == rebinding));
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let list: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+    Err(invalid(format!(
+        "{}; restore the files, or run `eenv manifest update` if this is intended",
+        list.join("; ")
+    )))
+}
+
+/// Records the current .enc files in eenv.manifest, bumping the version of
+/// every changed file. Entries for files this machine has no key for are
+/// left as they were. Returns whether the manifest was rewritten. A manifest
+/// that doesn't parse (e.g. with merge conflict markers) is an error, not
+/// replaced.
+pub fn update(repo_root: &Path, keys: &KeyMap) -> io::Result<bool> {
+    let old = load(repo_root)?;
+    let old_files = old.clone().unwrap_or_default();
+    let voucher = Voucher::new(repo_root, keys, true)?;
+    let seen = read_manifest_seen(repo_root)?;
+    let on_disk = scan(repo_root)?;
+
+    let mut files = BTreeMap::new();
+    let mut stuck = Vec::new();
+    // paths this machine has seen stay listed, as tombstones if need be
+    let paths: BTreeSet<&String> = old_files
+        .keys()
+        .chain(on_disk.keys())
+        .chain(seen.keys())
+        .collect();
+    for path in paths {
+        let hash = on_disk.get(path).cloned();
+        let prev = old_files.get(path);
+        let last = seen.get(path).copied().unwrap_or(0);
+        if let Some(e) = prev
+            && e.hash == hash
+            && e.version >= last
+            && matches!(voucher.verify(path, e)?, Trust::Verified | Trust::Unsigned)
+        {
+            files.insert(path.clone(), e.clone());
+            continue;
+        }
+        let base = prev.map_or(0, |e| e.version).max(last);
+        let version = match prev {
+            Some(e) if e.hash == hash && e.version >= last => base,
+            _ => base + 1,
+        };
+        match voucher.vouch(path, hash.clone(), version)? {
+            Some(entry) => {
+                files.insert(path.clone(), entry);
+            }
+            None => {
+                if let Some(e) = prev {
+                    files.insert(path.clone(), e.clone());
+                }
+                if prev.map(|e| &e.hash) != Some(&hash) {
+                    stuck.push(path.as_str());
+                }
+            }
+        }
+    }
+    for path in stuck {
+        eprintln!(
+            "[manifest] WARN: can't vouch for {path} (no key for it{}); its entry was left as it was",
+            match voucher.scheme {
+                KeyScheme::Shared(_) => "",
+                KeyScheme::Recipients(_) => ", or not one of its signers in eenv.signers",
+            }
+        );
+    }
+    if old.as_ref() == Some(&files) {
+        return Ok(false);
+    }
+    save(repo_root, &files)?;
+    Ok(true)
+}
+
+pub fn run(repo_root: &Path, action: ManifestAction) -> io::Result<()> {
+    let keys = crate::config::read_key_map(repo_root)?;
+    match action {
+        ManifestAction::Verify => match check(repo_root, &keys)? {
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no {MANIFEST_FILE} (run `eenv manifest update`)"),
+            )),
+            Some(problems) if problems.is_empty() => {
+                println!("[manifest] ok");
+                Ok(())
+            }
+            Some(problems) => {
+                for p in &problems {
+                    eprintln!("[manifest] {p}");
+                }
+                Err(io::Error::other(format!("{} problem(s)", problems.len())))
+            }
+        },
+        ManifestAction::Update => {
+            if update(repo_root, &keys)? {
+                println!("[manifest] updated {MANIFEST_FILE}; commit it");
+            } else {
+                println!("[manifest] already up to date");
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{read_key_map, write_config_object, write_signing_key};
+    use crate::crypto::encrypt_bytes;
+    use crate::keyring::Keyring;
+    use crate::recipients::{Identity, RECIPIENTS_FILE, encode_public};
+    use crate::util::scratch_dir;
+
+    fn shared_repo(name: &str) -> PathBuf {
+        let dir = scratch_dir(name);
+        let config = json!({ "key": "k", "kdf": crate::kdf::KdfParams::cheap().to_json() });
+        write_config_object(&dir, config.as_object().unwrap().clone()).unwrap();
+        dir
+    }
+
+    // a repo in recipients mode whose config holds the one recipient's identity
+    fn recipients_repo(name: &str) -> PathBuf {
+        let dir = scratch_dir(name);
+        let id = Identity::generate();
+        let config = json!({ "identity": id.to_secret_string() });
+        write_config_object(&dir, config.as_object().unwrap().clone()).unwrap();
+        fs::write(dir.join(RECIPIENTS_FILE), encode_public(&id.public())).unwrap();
+        dir
+    }
+
+    fn write_enc(dir: &Path, name: &str, plain: &str) {
+        let keys = read_key_map(dir).unwrap();
+        let path = dir.join(name);
+        let binding = binding_for(dir, &path);
+        let scheme = repo_key_scheme(dir).unwrap();
+        let key: &Keyring = keys.keyring_for(&binding).unwrap();
+        fs::write(
+            &path,
+            encrypt_bytes(key, &scheme, plain.as_bytes(), &binding).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn problems(dir: &Path) -> Vec<String> {
+        let keys = read_key_map(dir).unwrap();
+        check(dir, &keys)
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|p| p.to_string())
+            .collect()
+    }
+
+    fn update_ok(dir: &Path) -> bool {
+        update(dir, &read_key_map(dir).unwrap()).unwrap()
+    }
+
+    fn edit_manifest(dir: &Path, f: impl FnOnce(&mut Map<String, Value>)) {
+        let path = manifest_path(dir);
+        let mut v: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        f(v["files"].as_object_mut().unwrap());
+        fs::write(&path, v.to_string()).unwrap();
+    }
+
+    fn only(problems: &[String], needle: &str) {
+        assert_eq!(problems.len(), 1, "{problems:?}");
+        assert!(problems[0].contains(needle), "{problems:?}");
+    }
+
+    #[test]
+    fn changed_and_unlisted_files_are_reported() {
+        let dir = shared_repo("manifest-changed");
+        write_enc(&dir, ".env.enc", "A=1\n");
+        write_enc(&dir, ".env.production.enc", "A=2\n");
+        let keys = read_key_map(&dir).unwrap();
+        assert!(check(&dir, &keys).unwrap().is_none());
+        assert!(update_ok(&dir));
+        assert!(problems(&dir).is_empty());
+        assert!(!update_ok(&dir), "nothing changed");
+
+        write_enc(&dir, ".env.enc", "A=changed\n");
+        only(&problems(&dir), ".env.enc does not match");
+        assert!(update_ok(&dir));
+        assert!(problems(&dir).is_empty());
+
+        write_enc(&dir, ".env.staging.enc", "A=3\n");
+        only(&problems(&dir), ".env.staging.enc is not listed");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restoring_an_older_file_and_entry_is_a_rollback() {
+        let dir = shared_repo("manifest-rollback");
+        write_enc(&dir, ".env.enc", "A=old\n");
+        update_ok(&dir);
+        let old = (
+            fs::read(dir.join(".env.enc")).unwrap(),
+            fs::read(manifest_path(&dir)).unwrap(),
+        );
+        write_enc(&dir, ".env.enc", "A=new\n");
+        update_ok(&dir);
+        assert!(problems(&dir).is_empty());
+
+        fs::write(dir.join(".env.enc"), &old.0).unwrap();
+        fs::write(manifest_path(&dir), &old.1).unwrap();
+        only(&problems(&dir), "rolled back: version 1");
+        // and `eenv mv`, `encrypt` and `rotate` won't vouch for it
+        let keys = read_key_map(&dir).unwrap();
+        assert!(ensure_clean(&dir, &keys, None).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dropping_an_entry_is_caught() {
+        let dir = shared_repo("manifest-dropped");
+        write_enc(&dir, ".env.enc", "A=1\n");
+        write_enc(&dir, ".env.production.enc", "A=2\n");
+        update_ok(&dir);
+        assert!(problems(&dir).is_empty());
+        edit_manifest(&dir, |files| {
+            files.remove(".env.production.enc");
+        });
+        only(&problems(&dir), ".env.production.enc was dropped");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deleted_files_become_tombstones() {
+        let dir = shared_repo("manifest-tombstone");
+        write_enc(&dir, ".env.enc", "A=1\n");
+        write_enc(&dir, ".env.production.enc", "A=2\n");
+        update_ok(&dir);
+        let production = fs::read(dir.join(".env.production.enc")).unwrap();
+        fs::remove_file(dir.join(".env.production.enc")).unwrap();
+        only(&problems(&dir), "listed in eenv.manifest but missing");
+
+        assert!(update_ok(&dir));
+        let v: Value =
+            serde_json::from_str(&fs::read_to_string(manifest_path(&dir)).unwrap()).unwrap();
+        assert_eq!(v["files"][".env.production.enc"]["blake3"], Value::Null);
+        assert_eq!(v["files"][".env.production.enc"]["version"], 2);
+        assert!(problems(&dir).is_empty());
+
+        // bringing the deleted file back isn't silently accepted
+        fs::write(dir.join(".env.production.enc"), production).unwrap();
+        only(&problems(&dir), "not listed");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hand_edited_entries_are_unauthenticated() {
+        let dir = shared_repo("manifest-forged");
+        write_enc(&dir, ".env.enc", "A=1\n");
+        update_ok(&dir);
+        write_enc(&dir, ".env.enc", "A=evil\n");
+        let hash = blake3::hash(&fs::read(dir.join(".env.enc")).unwrap()).to_hex();
+        edit_manifest(&dir, |files| {
+            files[".env.enc"]["blake3"] = json!(hash.to_string());
+            files[".env.enc"]["version"] = json!(7);
+        });
+        only(&problems(&dir), "not authenticated");
+
+        // another key can't vouch for it either
+        let other = KeyMap::new(Some(Keyring::from_secret("other").unwrap()), Vec::new());
+        update(&dir, &other).unwrap();
+        only(&problems(&dir), "not authenticated");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deleted_or_corrupt_manifest_is_an_error() {
+        let dir = shared_repo("manifest-corrupt");
+        write_enc(&dir, ".env.enc", "A=1\n");
+        update_ok(&dir);
+        assert!(problems(&dir).is_empty());
+        let good = fs::read_to_string(manifest_path(&dir)).unwrap();
+
+        let conflicted = format!("<<<<<<< HEAD\n{good}=======\n{good}>>>>>>> theirs\n");
+        fs::write(manifest_path(&dir), &conflicted).unwrap();
+        let keys = read_key_map(&dir).unwrap();
+        assert!(update(&dir, &keys).is_err());
+        assert_eq!(fs::read_to_string(manifest_path(&dir)).unwrap(), conflicted);
+        assert!(check(&dir, &keys).is_err());
+
+        fs::remove_file(manifest_path(&dir)).unwrap();
+        let err = check(&dir, &keys).unwrap_err().to_string();
+        assert!(err.contains("verified it before"), "{err}");
+        // deliberately rebuilding it is allowed
+        assert!(update(&dir, &keys).unwrap());
+        assert!(problems(&dir).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_moved_by_hand_are_recognised() {
+        let dir = shared_repo("manifest-moved");
+        write_enc(&dir, ".env.enc", "A=1\n");
+        update_ok(&dir);
+        fs::rename(dir.join(".env.enc"), dir.join(".env.other.enc")).unwrap();
+        only(&problems(&dir), ".env.enc was moved to .env.other.enc");
+        let keys = read_key_map(&dir).unwrap();
+        assert!(ensure_clean(&dir, &keys, None).is_err());
+        assert!(ensure_clean(&dir, &keys, Some(".env.other.enc")).is_ok());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn recipients_without_signers_get_unsigned_entries() {
+        let dir = recipients_repo("manifest-unsigned");
+        write_enc(&dir, ".env.enc", "A=1\n");
+        assert!(update_ok(&dir));
+        let v: Value =
+            serde_json::from_str(&fs::read_to_string(manifest_path(&dir)).unwrap()).unwrap();
+        let entry = &v["files"][".env.enc"];
+        assert!(entry["blake3"].is_string());
+        assert!(entry.get("sig").is_none() && entry.get("mac").is_none());
+        assert!(problems(&dir).is_empty());
+        assert!(!update_ok(&dir));
+
+        write_enc(&dir, ".env.enc", "A=2\n");
+        only(&problems(&dir), "does not match");
+        assert!(update_ok(&dir));
+        assert!(problems(&dir).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn recipients_with_signers_need_a_signature() {
+        let dir = recipients_repo("manifest-signed");
+        let signer = SigningIdentity::generate();
+        fs::write(
+            dir.join(signing::SIGNERS_FILE),
+            format!("* {} # alice\n", signing::encode_public(&signer.public())),
+        )
+        .unwrap();
+        write_signing_key(&dir, &signer).unwrap();
+        write_enc(&dir, ".env.enc", "A=1\n");
+        assert!(update_ok(&dir));
+        assert!(problems(&dir).is_empty());
+
+        // a signature can't just be stripped
+        let saved = fs::read(manifest_path(&dir)).unwrap();
+        edit_manifest(&dir, |files| {
+            let entry = files[".env.enc"].as_object_mut().unwrap();
+            entry.remove("sig");
+            entry.remove("signer");
+        });
+        only(&problems(&dir), "not authenticated");
+        fs::write(manifest_path(&dir), saved).unwrap();
+
+        // a machine that isn't a signer can't vouch for a change
+        let mut config = crate::config::read_config_object(&dir).unwrap();
+        config.remove("signing_key");
+        write_config_object(&dir, config).unwrap();
+        write_enc(&dir, ".env.enc", "A=2\n");
+        assert!(!update_ok(&dir));
+        only(&problems(&dir), "does not match");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,468 @@
+use crate::config::{eenv_config_path, read_config_object, write_config_object};
+use crate::container::NONCE_LEN;
+use crate::kdf::{KdfParams, derive_key};
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use rand::Rng;
+use serde_json::{Map, Value, json};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+// A protected config keeps its secrets ("key", every "keys" entry,
+// "identity" and "signing_key") as `wrapped:v1:<base64 nonce|ciphertext>`,
+// sealed with a key derived from the user's passphrase by Argon2id:
+//
+//   "passphrase": { "kdf": { ...argon2id params... }, "check": "<hex>" }
+//
+// "check" is a key id of the wrapping key, so a wrong passphrase is caught
+// before anything is unwrapped. The wrapping key (never the passphrase) is
+// cached for the login session under $XDG_RUNTIME_DIR, which is private to
+// the user and cleared on logout; without it the prompt comes once per run.
+// A cache file others can read is ignored.
+pub const WRAPPED_PREFIX: &str = "wrapped:v1:";
+const AAD: &[u8] = b"eenv wrapped key v1";
+const PROMPT_ATTEMPTS: usize = 3;
+
+static UNLOCKED: Mutex<Option<HashMap<KdfParams, [u8; 32]>>> = Mutex::new(None);
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub fn is_wrapped(s: &str) -> bool {
+    s.starts_with(WRAPPED_PREFIX)
+}
+
+fn check_value(wrap_key: &[u8; 32]) -> String {
+    let h = blake3::derive_key("eenv passphrase check v1", wrap_key);
+    crate::util::to_hex(&h[..8])
+}
+
+pub fn wrap(wrap_key: &[u8; 32], secret: &str) -> io::Result<String> {
+    let nonce: [u8; NONCE_LEN] = rand::rng().random();
+    let ct = XChaCha20Poly1305::new(wrap_key.into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: secret.as_bytes(),
+                aad: AAD,
+            },
+        )
+        .map_err(|_| io::Error::other("wrap failed"))?;
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&ct);
+    Ok(format!("{WRAPPED_PREFIX}{}", B64.encode(blob)))
+}
+
+pub fn unwrap(wrap_key: &[u8; 32], wrapped: &str) -> io::Result<String> {
+    let blob = wrapped
+        .strip_prefix(WRAPPED_PREFIX)
+        .and_then(|b| B64.decode(b).ok())
+        .filter(|b| b.len() > NONCE_LEN)
+        .ok_or_else(|| invalid("bad eenv.config.json: malformed wrapped key"))?;
+    let (nonce, ct) = blob.split_at(NONCE_LEN);
+    let pt = XChaCha20Poly1305::new(wrap_key.into())
+        .decrypt(XNonce::from_slice(nonce), Payload { msg: ct, aad: AAD })
+        .map_err(|_| invalid("could not unwrap key (wrong passphrase or corrupted config)"))?;
+    String::from_utf8(pt).map_err(|_| invalid("wrapped key is not UTF-8"))
+}
+
+// (kdf params, check) from the "passphrase" field, if the config is protected
+fn protection(map: &Map<String, Value>) -> io::Result<Option<(KdfParams, String)>> {
+    let Some(p) = map.get("passphrase") else {
+        return Ok(None);
+    };
+    let params = KdfParams::from_json(p.get("kdf").unwrap_or(&Value::Null))?;
+    let check = p
+        .get("check")
+        .and_then(|c| c.as_str())
+        .ok_or_else(|| invalid("bad eenv.config.json: \"passphrase\" needs \"check\""))?;
+    Ok(Some((params, check.to_string())))
+}
+
+fn session_file(params: &KdfParams) -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    let name = blake3::hash(&params.salt).to_hex();
+    Some(PathBuf::from(dir).join("eenv").join(&name[..32]))
+}
+
+// a cache file (or its directory) that others can read or write isn't
+// trusted: someone else could have read the key, or planted their own
+#[cfg(unix)]
+fn private(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o077 == 0
+}
+
+#[cfg(not(unix))]
+fn private(_meta: &fs::Metadata) -> bool {
+    true
+}
+
+fn read_session(path: &Path) -> Option<[u8; 32]> {
+    let dir = fs::symlink_metadata(path.parent()?).ok()?;
+    let meta = fs::symlink_metadata(path).ok()?;
+    if !dir.is_dir() || !meta.is_file() || !private(&dir) || !private(&meta) {
+        eprintln!(
+            "[passphrase] WARN: ignoring {}: others can access it",
+            path.display()
+        );
+        return None;
+    }
+    let text = fs::read_to_string(path).ok()?;
+    crate::util::from_hex(text.trim())?.try_into().ok()
+}
+
+fn write_session(path: &Path, wrap_key: &[u8; 32]) -> io::Result<()> {
+    let dir = path.parent().expect("session file has a parent");
+    // a fresh file each time, so an existing one (or a symlink planted in
+    // its place) is never written through
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+        opts.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    fs::create_dir_all(dir)?;
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    // the modes above only apply on creation (and are masked by the umask),
+    // so set them explicitly too
+    let mut file = opts.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(crate::util::to_hex(wrap_key).as_bytes())
+}
+
+fn prompt(msg: &str) -> io::Result<String> {
+    rpassword::prompt_password(msg)
+}
+
+/// The wrapping key for a protected config: from this process, then the
+/// session cache, then by asking for the passphrase.
+fn unlock(params: &KdfParams, check: &str) -> io::Result<[u8; 32]> {
+    let mut unlocked = UNLOCKED.lock().unwrap_or_else(|e| e.into_inner());
+    let unlocked = unlocked.get_or_insert_with(HashMap::new);
+    if let Some(k) = unlocked.get(params) {
+        return Ok(*k);
+    }
+    let session = session_file(params);
+    if let Some(k) = session
+        .as_deref()
+        .and_then(read_session)
+        .filter(|k| check_value(k) == check)
+    {
+        unlocked.insert(params.clone(), k);
+        return Ok(k);
+    }
+    for _ in 0..PROMPT_ATTEMPTS {
+        let pass = prompt("eenv: passphrase for eenv.config.json: ")?;
+        let k = derive_key(&pass, params)?;
+        if check_value(&k) == check {
+            if let Some(path) = &session
+                && let Err(e) = write_session(path, &k)
+            {
+                eprintln!("[passphrase] WARN: could not cache for this session: {e}");
+            }
+            unlocked.insert(params.clone(), k);
+            return Ok(k);
+        }
+        eprintln!("[passphrase] wrong passphrase");
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "wrong passphrase",
+    ))
+}
+
+/// `secret` as stored in a config: unwrapped (asking for the passphrase if
+/// needed) when it is a wrapped key, else as is.
+pub fn reveal(map: &Map<String, Value>, secret: &str) -> io::Result<String> {
+    if !is_wrapped(secret) {
+        return Ok(secret.to_string());
+    }
+    let (params, check) = protection(map)?.ok_or_else(|| {
+        invalid("bad eenv.config.json: wrapped key but no \"passphrase\" settings")
+    })?;
+    unwrap(&unlock(&params, &check)?, secret)
+}
+
+/// How to store a new `secret` in this config: wrapped if it is protected.
+pub fn conceal(map: &Map<String, Value>, secret: &str) -> io::Result<Value> {
+    match protection(map)? {
+        Some((params, check)) => Ok(Value::String(wrap(&unlock(&params, &check)?, secret)?)),
+        None => Ok(Value::String(secret.to_string())),
+    }
+}
+
+// Applies `f` to every secret field ("key", "keys" entries, "identity",
+// "signing_key").
+fn map_secrets(
+    map: &mut Map<String, Value>,
+    mut f: impl FnMut(&str) -> io::Result<String>,
+) -> io::Result<()> {
+    let mut apply = |v: &mut Value| -> io::Result<()> {
+        if let Some(s) = v.as_str().filter(|s| !s.trim().is_empty()) {
+            *v = Value::String(f(s)?);
+        }
+        Ok(())
+    };
+    for field in ["key", "identity", "signing_key"] {
+        if let Some(v) = map.get_mut(field) {
+            apply(v)?;
+        }
+    }
+    if let Some(keys) = map.get_mut("keys").and_then(|k| k.as_object_mut()) {
+        for v in keys.values_mut() {
+            apply(v)?;
+        }
+    }
+    Ok(())
+}
+
+/// Encrypts the secrets in eenv.config.json under a new passphrase.
+pub fn protect(repo_root: &Path) -> io::Result<()> {
+    let map = read_config_object(repo_root)?;
+    if protection(&map)?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "eenv.config.json is already passphrase-protected",
+        ));
+    }
+    let pass = prompt("eenv: new passphrase: ")?;
+    if pass.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "empty passphrase not allowed",
+        ));
+    }
+    if prompt("eenv: repeat passphrase: ")? != pass {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "passphrases don't match",
+        ));
+    }
+    seal(repo_root, map, &pass, KdfParams::generate())?;
+    println!(
+        "[passphrase] keys in {} are now encrypted under your passphrase",
+        eenv_config_path(repo_root).display()
+    );
+    Ok(())
+}
+
+// Wraps the secrets in `map` under `pass` and writes it as the config. The
+// wrapping key stays unlocked for this process and session.
+fn seal(
+    repo_root: &Path,
+    mut map: Map<String, Value>,
+    pass: &str,
+    params: KdfParams,
+) -> io::Result<()> {
+    let wrap_key = derive_key(pass, &params)?;
+    map_secrets(&mut map, |s| {
+        if is_wrapped(s) {
+            Err(invalid(
+                "eenv.config.json has a wrapped key but no passphrase settings",
+            ))
+        } else {
+            wrap(&wrap_key, s)
+        }
+    })?;
+    map.insert(
+        "passphrase".into(),
+        json!({ "kdf": params.to_json(), "check": check_value(&wrap_key) }),
+    );
+    write_config_object(repo_root, map)?;
+    if let Some(path) = session_file(&params)
+        && let Err(e) = write_session(&path, &wrap_key)
+    {
+        eprintln!("[passphrase] WARN: could not cache for this session: {e}");
+    }
+    let mut unlocked = UNLOCKED.lock().unwrap_or_else(|e| e.into_inner());
+    unlocked
+        .get_or_insert_with(HashMap::new)
+        .insert(params, wrap_key);
+    Ok(())
+}
+
+/// Stores the secrets in eenv.config.json in plaintext again.
+pub fn unprotect(repo_root: &Path) -> io::Result<()> {
+    let mut map = read_config_object(repo_root)?;
+    let Some((params, check)) = protection(&map)? else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "eenv.config.json is not passphrase-protected",
+        ));
+    };
+    let wrap_key = unlock(&params, &check)?;
+    map_secrets(&mut map, |s| {
+        if is_wrapped(s) {
+            unwrap(&wrap_key, s)
+        } else {
+            Ok(s.to_string())
+        }
+    })?;
+    map.remove("passphrase");
+    write_config_object(repo_root, map)?;
+    forget(&params)?;
+    println!("[passphrase] keys in eenv.config.json are stored in plaintext again");
+    Ok(())
+}
+
+/// Drops the session cache, so the next command asks for the passphrase.
+pub fn lock(repo_root: &Path) -> io::Result<()> {
+    let map = read_config_object(repo_root)?;
+    if let Some((params, _)) = protection(&map)? {
+        forget(&params)?;
+    }
+    println!("[passphrase] locked");
+    Ok(())
+}
+
+fn forget(params: &KdfParams) -> io::Result<()> {
+    match session_file(params).map(fs::remove_file) {
+        Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("eenv-session-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("eenv").join("cache")
+    }
+
+    #[test]
+    fn cache_is_private_and_round_trips() {
+        let path = scratch("private");
+        write_session(&path, &[7u8; 32]).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        assert_eq!(read_session(&path), Some([7u8; 32]));
+        let _ = fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn rewriting_tightens_loose_permissions() {
+        let path = scratch("loose");
+        write_session(&path, &[1u8; 32]).unwrap();
+        let dir = path.parent().unwrap();
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_session(&path, &[2u8; 32]).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(dir), 0o700);
+        assert_eq!(read_session(&path), Some([2u8; 32]));
+        let _ = fs::remove_dir_all(dir.parent().unwrap());
+    }
+
+    #[test]
+    fn wrapped_secrets_need_the_right_passphrase() {
+        let params = KdfParams::cheap();
+        let right = derive_key("correct horse", &params).unwrap();
+        let wrong = derive_key("battery staple", &params).unwrap();
+        let wrapped = wrap(&right, "s3cret").unwrap();
+        assert!(is_wrapped(&wrapped));
+        assert!(!wrapped.contains("s3cret"));
+        assert_ne!(wrapped, wrap(&right, "s3cret").unwrap());
+        assert_eq!(unwrap(&right, &wrapped).unwrap(), "s3cret");
+        assert!(unwrap(&wrong, &wrapped).is_err());
+        assert_ne!(check_value(&right), check_value(&wrong));
+        assert!(unwrap(&right, "wrapped:v1:AAAA").is_err());
+    }
+
+    #[test]
+    fn protect_then_unprotect_restores_every_secret() {
+        let dir = crate::util::scratch_dir("passphrase-protect");
+        let config = json!({
+            "key": "default-key",
+            "keys": { "production": "prod-key", "staging": "" },
+            "identity": "AGE-SECRET-KEY-1EXAMPLE",
+            "signing_key": "eenv-sign-example",
+            "kdf": "left alone",
+        });
+        let original = config.as_object().unwrap().clone();
+        seal(&dir, original.clone(), "pass", KdfParams::cheap()).unwrap();
+
+        let sealed = read_config_object(&dir).unwrap();
+        for field in ["key", "identity", "signing_key"] {
+            assert!(is_wrapped(sealed[field].as_str().unwrap()), "{field}");
+        }
+        assert!(is_wrapped(sealed["keys"]["production"].as_str().unwrap()));
+        assert_eq!(sealed["keys"]["staging"], "");
+        assert_eq!(sealed["kdf"], "left alone");
+        assert_eq!(
+            reveal(&sealed, sealed["key"].as_str().unwrap()).unwrap(),
+            "default-key"
+        );
+        assert!(protect(&dir).is_err(), "already protected");
+
+        unprotect(&dir).unwrap();
+        assert_eq!(read_config_object(&dir).unwrap(), original);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn readable_cache_is_refused() {
+        let path = scratch("readable");
+        write_session(&path, &[3u8; 32]).unwrap();
+        for loose in [0o640, 0o604, 0o644] {
+            fs::set_permissions(&path, fs::Permissions::from_mode(loose)).unwrap();
+            assert_eq!(read_session(&path), None, "{loose:o}");
+        }
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let dir = path.parent().unwrap();
+        for loose in [0o750, 0o705, 0o777] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(loose)).unwrap();
+            assert_eq!(read_session(&path), None, "{loose:o}");
+        }
+        let _ = fs::remove_dir_all(dir.parent().unwrap());
+    }
+
+    #[test]
+    fn symlinked_cache_is_refused_and_not_written_through() {
+        let path = scratch("symlink");
+        write_session(&path, &[4u8; 32]).unwrap();
+        let target = path.with_file_name("target");
+        fs::write(&target, "untouched").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        fs::remove_file(&path).unwrap();
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+        assert_eq!(read_session(&path), None);
+
+        write_session(&path, &[5u8; 32]).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "untouched");
+        assert_eq!(read_session(&path), Some([5u8; 32]));
+        let _ = fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+}
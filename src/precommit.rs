@@ -5,10 +5,12 @@ pub fn pre_commit(repo_root: &Path, write: bool) -> io::Result<()> {
     let staged = staged_files(repo_root)?;
     let mut offenders = Vec::new();
     for p in &staged {
-        if let Some(name) = p.file_name().and_then(|s| s.to_str()) {
-            if name.starts_with(".env") && !name.ends_with(".example") && !name.ends_with(".enc") {
-                offenders.push(p.clone());
-            }
+        if let Some(name) = p.file_name().and_then(|s| s.to_str())
+            && name.starts_with(".env")
+            && !name.ends_with(".example")
+            && !name.ends_with(".enc")
+        {
+            offenders.push(p.clone());
         }
     }
     if !offenders.is_empty() {
@@ -17,7 +19,20 @@ pub fn pre_commit(repo_root: &Path, write: bool) -> io::Result<()> {
             eprintln!("  - {}", p.display());
         }
         eprintln!("Hint: encrypt them to .env*.enc or add them to .gitignore.");
-        return Err(io::Error::new(io::ErrorKind::Other, "raw .env staged"));
+        return Err(io::Error::other("raw .env staged"));
+    }
+
+    if crate::config::validate_eenv_config(repo_root)? {
+        let keys = crate::config::read_key_map(repo_root)?;
+        let problems = crate::manifest::check(repo_root, &keys)?.unwrap_or_default();
+        if !problems.is_empty() {
+            eprintln!("[pre-commit] ❌ encrypted files don't match eenv.manifest:");
+            for p in &problems {
+                eprintln!("  - {p}");
+            }
+            eprintln!("Hint: restore them, or run `eenv manifest update` if this is intended.");
+            return Err(io::Error::other("manifest mismatch"));
+        }
     }
 
     let (files, _t_find) = crate::util::time_result("find_env_files_recursive", || {
@@ -71,25 +86,143 @@ pub fn pre_commit(repo_root: &Path, write: bool) -> io::Result<()> {
         }
 
         let produced = crate::crypto::encrypt_envs_to_enc(repo_root, &real)?;
-        if !produced.is_empty() {
+        let signed_signers = crate::signing::sign_signers(repo_root)?;
+        if !produced.is_empty() || signed_signers {
             git_add(repo_root, &produced)?;
+            let sigs = crate::signing::signatures_path(repo_root);
+            if sigs.exists() {
+                git_add(repo_root, &[sigs])?;
+            }
+        }
+        let keys = crate::config::read_key_map(repo_root)?;
+        if crate::manifest::update(repo_root, &keys)? {
+            git_add(repo_root, &[crate::manifest::manifest_path(repo_root)])?;
         }
     }
 
+    let problems = verify_staged_encs(repo_root)?;
+    if !problems.is_empty() {
+        eprintln!("[pre-commit] ❌ staged encrypted files failed verification:");
+        for p in &problems {
+            eprintln!("  - {p}");
+        }
+        eprintln!(
+            "Hint: run `eenv pre-commit --write`, `git add` the .enc files, then commit again."
+        );
+        return Err(io::Error::other("staged .enc verification failed"));
+    }
+
+    let problems = crate::signing::verify_staged(repo_root)?;
+    if !problems.is_empty() {
+        eprintln!("[pre-commit] ❌ encrypted files fail the signature check:");
+        for p in &problems {
+            eprintln!("  - {p}");
+        }
+        eprintln!(
+            "Hint: changes to these files must be made by a signer listed in {}.",
+            crate::signing::SIGNERS_FILE
+        );
+        return Err(io::Error::other("signature check failed"));
+    }
+
     Ok(())
 }
 
-fn staged_files(repo_root: &Path) -> io::Result<Vec<PathBuf>> {
+// Decrypts every staged .env*.enc as it is in the index (what will actually
+// be committed) with the current key, and compares it with its plaintext
+// sibling when there is one. Returns one line per bad file; a file with no
+// key to check it (or no usable eenv.config.json) is one too.
+fn verify_staged_encs(repo_root: &Path) -> io::Result<Vec<String>> {
+    let mut keys = None;
+    let mut problems = Vec::new();
+    for path in staged_files_filtered(repo_root, "d")? {
+        let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if !name.starts_with(".env") || !name.ends_with(".enc") {
+            continue;
+        }
+        let binding = crate::crypto::binding_for(repo_root, &path);
+        let rel = binding.path.clone();
+        let blob = staged_blob(repo_root, &rel)?;
+        if !crate::crypto::is_eenv_file(&blob) {
+            problems.push(format!(
+                "{rel}: not an eenv file (truncated or half-written?)"
+            ));
+            continue;
+        }
+        let key = keys
+            .get_or_insert_with(|| crate::config::read_key_map(repo_root))
+            .as_ref()
+            .map_err(|e| io::Error::new(e.kind(), e.to_string()))
+            .and_then(|keys| keys.keyring_for(&binding));
+        let key = match key {
+            Ok(k) => k,
+            Err(e) => {
+                problems.push(format!("{rel}: can't be checked ({e})"));
+                continue;
+            }
+        };
+        let plaintext = match crate::crypto::decrypt_bytes(key, &blob, Some(&binding)) {
+            Ok(p) => p,
+            Err(e) => {
+                problems.push(format!(
+                    "{rel}: does not decrypt with the current key ({e})"
+                ));
+                continue;
+            }
+        };
+        let sibling = crate::crypto::dec_output_path(&path);
+        if let Ok(current) = std::fs::read(&sibling)
+            && current != plaintext
+        {
+            problems.push(format!(
+                "{rel}: staged version doesn't match {} (re-encrypt, or `git add` the new .enc)",
+                sibling
+                    .file_name()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default()
+            ));
+            continue;
+        }
+        println!("[pre-commit] ✔ {rel}");
+    }
+    Ok(problems)
+}
+
+// contents of `rel` in the index
+fn staged_blob(repo_root: &Path, rel: &str) -> io::Result<Vec<u8>> {
     let out = Proc::new("git")
         .arg("-C")
+        .arg(repo_root)
+        .arg("show")
+        .arg(format!(":{rel}"))
+        .output()?;
+    if !out.status.success() {
+        return Err(io::Error::other(format!("git show :{rel} failed")));
+    }
+    Ok(out.stdout)
+}
+
+fn staged_files(repo_root: &Path) -> io::Result<Vec<PathBuf>> {
+    staged_files_filtered(repo_root, "")
+}
+
+// `filter` is passed to `git diff --diff-filter` ("d" leaves out deletions)
+fn staged_files_filtered(repo_root: &Path, filter: &str) -> io::Result<Vec<PathBuf>> {
+    let mut cmd = Proc::new("git");
+    cmd.arg("-C")
         .arg(repo_root)
         .arg("diff")
         .arg("--name-only")
         .arg("--cached")
-        .arg("-z")
-        .output()?;
+        .arg("-z");
+    if !filter.is_empty() {
+        cmd.arg(format!("--diff-filter={filter}"));
+    }
+    let out = cmd.output()?;
     if !out.status.success() {
-        return Err(io::Error::new(io::ErrorKind::Other, "git diff failed"));
+        return Err(io::Error::other("git diff failed"));
     }
     let mut files = Vec::new();
     for name in out.stdout.split(|b| *b == 0u8) {
@@ -113,7 +246,82 @@ fn git_add(repo_root: &Path, paths: &[PathBuf]) -> io::Result<()> {
     }
     let status = cmd.status()?;
     if !status.success() {
-        return Err(io::Error::new(io::ErrorKind::Other, "git add failed"));
+        return Err(io::Error::other("git add failed"));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::eenv_config_path;
+    use crate::keyring::Keyring;
+    use crate::util::{git_in, scratch_keyed_repo, seal_env};
+    use std::fs;
+
+    // a keyed repo with `.env.enc` (holding `A=1`) and `.env` staged as given
+    fn setup(name: &str, staged: impl FnOnce(&Path) -> PathBuf) -> PathBuf {
+        let dir = scratch_keyed_repo(name);
+        fs::write(dir.join(".env"), "A=1\n").unwrap();
+        let enc = staged(&dir);
+        git_in(&dir, &["add", enc.to_str().unwrap()]);
+        dir
+    }
+
+    fn one_problem(dir: &Path, want: &str) {
+        let problems = verify_staged_encs(dir).unwrap();
+        assert_eq!(problems.len(), 1, "{problems:?}");
+        assert!(problems[0].starts_with(".env.enc: "), "{problems:?}");
+        assert!(problems[0].contains(want), "{problems:?}");
+    }
+
+    #[test]
+    fn the_staged_blob_is_checked_not_the_worktree() {
+        let dir = setup("precommit-staged", |d| seal_env(d, ".env.enc", "A=1\n"));
+        assert!(verify_staged_encs(&dir).unwrap().is_empty());
+
+        // re-encrypted but not re-staged
+        fs::write(dir.join(".env"), "A=2\n").unwrap();
+        seal_env(&dir, ".env.enc", "A=2\n");
+        one_problem(&dir, "staged version doesn't match .env");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn truncated_blobs_are_refused() {
+        let dir = setup("precommit-truncated", |d| {
+            let enc = seal_env(d, ".env.enc", "A=1\n");
+            let data = fs::read(&enc).unwrap();
+            fs::write(&enc, &data[..data.len() / 2]).unwrap();
+            enc
+        });
+        one_problem(&dir, "does not decrypt");
+        fs::write(dir.join(".env.enc"), b"").unwrap();
+        git_in(&dir, &["add", ".env.enc"]);
+        one_problem(&dir, "not an eenv file");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_blob_under_another_key_is_refused() {
+        let dir = setup("precommit-wrong-key", |d| {
+            let enc = d.join(".env.enc");
+            let key = Keyring::from_secret("not-the-key").unwrap();
+            let scheme = crate::crypto::repo_key_scheme(d).unwrap();
+            let binding = crate::crypto::binding_for(d, &enc);
+            let out = crate::crypto::encrypt_bytes(&key, &scheme, b"A=1\n", &binding);
+            fs::write(&enc, out.unwrap()).unwrap();
+            enc
+        });
+        one_problem(&dir, "does not decrypt with the current key");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_blob_without_a_key_is_a_problem() {
+        let dir = setup("precommit-no-key", |d| seal_env(d, ".env.enc", "A=1\n"));
+        fs::remove_file(eenv_config_path(&dir)).unwrap();
+        one_problem(&dir, "can't be checked (no key");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
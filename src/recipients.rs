@@ -0,0 +1,276 @@
+use bech32::{FromBase32, ToBase32, Variant};
+use chacha20poly1305::{
+    ChaCha20Poly1305, Nonce,
+    aead::{Aead, KeyInit},
+};
+use rand::Rng;
+use std::{fs, io, path::Path, path::PathBuf};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// Keys use the same bech32 encoding as age (`age1...` / `AGE-SECRET-KEY-1...`).
+const PUBLIC_HRP: &str = "age";
+const SECRET_HRP: &str = "age-secret-key-";
+
+pub const RECIPIENTS_FILE: &str = "eenv.recipients";
+
+// recipient public key | ephemeral public key | wrapped file key + tag
+pub const STANZA_LEN: usize = 32 + 32 + 32 + 16;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    pub fn generate() -> Self {
+        let bytes: [u8; 32] = rand::rng().random();
+        Identity(StaticSecret::from(bytes))
+    }
+
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let (hrp, data, variant) =
+            bech32::decode(s.trim()).map_err(|e| invalid(format!("bad identity: {e}")))?;
+        let bytes =
+            Vec::<u8>::from_base32(&data).map_err(|e| invalid(format!("bad identity: {e}")))?;
+        if hrp != SECRET_HRP || variant != Variant::Bech32 || bytes.len() != 32 {
+            return Err(invalid("bad identity: expected AGE-SECRET-KEY-1..."));
+        }
+        let mut k = [0u8; 32];
+        k.copy_from_slice(&bytes);
+        Ok(Identity(StaticSecret::from(k)))
+    }
+
+    pub fn to_secret_string(&self) -> String {
+        bech32::encode(SECRET_HRP, self.0.to_bytes().to_base32(), Variant::Bech32)
+            .expect("valid hrp")
+            .to_uppercase()
+    }
+
+    pub fn public(&self) -> PublicKey {
+        PublicKey::from(&self.0)
+    }
+}
+
+pub fn encode_public(pk: &PublicKey) -> String {
+    bech32::encode(PUBLIC_HRP, pk.as_bytes().to_base32(), Variant::Bech32).expect("valid hrp")
+}
+
+pub fn parse_public(s: &str) -> io::Result<PublicKey> {
+    let (hrp, data, variant) =
+        bech32::decode(s.trim()).map_err(|e| invalid(format!("bad recipient {s}: {e}")))?;
+    let bytes =
+        Vec::<u8>::from_base32(&data).map_err(|e| invalid(format!("bad recipient {s}: {e}")))?;
+    if hrp != PUBLIC_HRP || variant != Variant::Bech32 || bytes.len() != 32 {
+        return Err(invalid(format!("bad recipient {s}: expected age1...")));
+    }
+    let mut k = [0u8; 32];
+    k.copy_from_slice(&bytes);
+    Ok(PublicKey::from(k))
+}
+
+fn wrap_key(shared: &[u8; 32], eph: &PublicKey, recipient: &PublicKey) -> ChaCha20Poly1305 {
+    let ikm = [&shared[..], eph.as_bytes(), recipient.as_bytes()].concat();
+    let k = blake3::derive_key("eenv x25519 wrap v1", &ikm);
+    ChaCha20Poly1305::new((&k).into())
+}
+
+/// Wraps `file_key` for one recipient. Every stanza uses a fresh ephemeral
+/// key, so the all-zero nonce is never reused under the same wrap key.
+pub fn wrap(file_key: &[u8; 32], recipient: &PublicKey) -> io::Result<Vec<u8>> {
+    let eph_bytes: [u8; 32] = rand::rng().random();
+    let eph = StaticSecret::from(eph_bytes);
+    let eph_pub = PublicKey::from(&eph);
+    let shared = eph.diffie_hellman(recipient);
+    if !shared.was_contributory() {
+        return Err(invalid(format!(
+            "recipient {} is a low-order point",
+            encode_public(recipient)
+        )));
+    }
+    let wrapped = wrap_key(shared.as_bytes(), &eph_pub, recipient)
+        .encrypt(Nonce::from_slice(&[0u8; 12]), &file_key[..])
+        .map_err(|_| io::Error::other("wrap failed"))?;
+    let mut out = Vec::with_capacity(STANZA_LEN);
+    out.extend_from_slice(recipient.as_bytes());
+    out.extend_from_slice(eph_pub.as_bytes());
+    out.extend_from_slice(&wrapped);
+    Ok(out)
+}
+
+pub fn stanza_recipient(stanza: &[u8]) -> Option<PublicKey> {
+    let pk: [u8; 32] = stanza.get(..32)?.try_into().ok()?;
+    Some(PublicKey::from(pk))
+}
+
+pub fn unwrap(stanza: &[u8], identity: &Identity) -> Option<[u8; 32]> {
+    if stanza.len() != STANZA_LEN {
+        return None;
+    }
+    let recipient = identity.public();
+    if stanza_recipient(stanza)? != recipient {
+        return None;
+    }
+    let eph: [u8; 32] = stanza[32..64].try_into().ok()?;
+    let eph = PublicKey::from(eph);
+    let shared = identity.0.diffie_hellman(&eph);
+    let key = wrap_key(shared.as_bytes(), &eph, &recipient)
+        .decrypt(Nonce::from_slice(&[0u8; 12]), &stanza[64..])
+        .ok()?;
+    key.try_into().ok()
+}
+
+pub fn recipients_path(repo_root: &Path) -> PathBuf {
+    repo_root.join(RECIPIENTS_FILE)
+}
+
+/// Public keys listed in `eenv.recipients`, one per line; `#` starts a comment
+/// (e.g. `age1... # alice`). Missing file means no recipients.
+pub fn read_recipients(repo_root: &Path) -> io::Result<Vec<PublicKey>> {
+    let path = recipients_path(repo_root);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    parse_recipients(&fs::read_to_string(&path)?)
+}
+
+fn parse_recipients(text: &str) -> io::Result<Vec<PublicKey>> {
+    let mut out = Vec::new();
+    for line in text.lines() {
+        let core = crate::gitignore::pattern_core(line);
+        if core.is_empty() {
+            continue;
+        }
+        let pk = parse_public(core)?;
+        if !out.contains(&pk) {
+            out.push(pk);
+        }
+    }
+    Ok(out)
+}
+
+/// Whether the recipient set in the working tree's eenv.recipients differs
+/// from the one committed at HEAD (always true without a HEAD). age files
+/// don't say who they are encrypted to, so this is how a changed set is told
+/// apart from an unchanged one.
+pub fn changed_since_head(repo_root: &Path) -> io::Result<bool> {
+    if !crate::signing::has_head(repo_root) {
+        return Ok(true);
+    }
+    let committed = crate::signing::git_file(repo_root, "HEAD", RECIPIENTS_FILE);
+    let committed = String::from_utf8_lossy(&committed);
+    let (mut before, mut now) = (parse_recipients(&committed)?, read_recipients(repo_root)?);
+    let key = |pk: &PublicKey| *pk.as_bytes();
+    before.sort_by_key(key);
+    now.sort_by_key(key);
+    Ok(before != now)
+}
+
+/// Creates this machine's X25519 identity in eenv.config.json (refusing to
+/// replace an existing one unless `force`) and returns its public key.
+pub fn new_identity(repo_root: &Path, force: bool) -> io::Result<PublicKey> {
+    if !force
+        && crate::config::read_identity(repo_root)
+            .ok()
+            .flatten()
+            .is_some()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "an identity already exists (use --force to replace it)",
+        ));
+    }
+    let identity = Identity::generate();
+    crate::config::write_identity(repo_root, &identity)?;
+    crate::config::ensure_gitignore_has_config(repo_root)?;
+    Ok(identity.public())
+}
+
+pub fn show_identity(repo_root: &Path) -> io::Result<PublicKey> {
+    crate::config::read_identity(repo_root)?
+        .map(|id| id.public())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no identity configured (run `eenv identity new`)",
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_round_trip_through_their_text_form() {
+        let id = Identity::generate();
+        let secret = id.to_secret_string();
+        assert!(secret.starts_with("AGE-SECRET-KEY-1"));
+        assert_eq!(Identity::parse(&secret).unwrap().public(), id.public());
+        assert_eq!(
+            Identity::parse(&secret.to_lowercase()).unwrap().public(),
+            id.public()
+        );
+
+        let public = encode_public(&id.public());
+        assert!(public.starts_with("age1"));
+        assert_eq!(parse_public(&format!(" {public}\n")).unwrap(), id.public());
+    }
+
+    #[test]
+    fn wrong_kinds_of_key_are_rejected() {
+        let id = Identity::generate();
+        let public = encode_public(&id.public());
+        assert!(parse_public(&id.to_secret_string()).is_err());
+        assert!(Identity::parse(&public).is_err());
+        assert!(parse_public("age1notakey").is_err());
+        let sig =
+            crate::signing::encode_public(&crate::signing::SigningIdentity::generate().public());
+        assert!(parse_public(&sig).is_err());
+    }
+
+    #[test]
+    fn only_the_recipient_unwraps_its_stanza() {
+        let (alice, bob) = (Identity::generate(), Identity::generate());
+        let file_key = [9u8; 32];
+        let stanza = wrap(&file_key, &alice.public()).unwrap();
+        assert_eq!(stanza.len(), STANZA_LEN);
+        assert_eq!(stanza_recipient(&stanza), Some(alice.public()));
+        assert_eq!(unwrap(&stanza, &alice), Some(file_key));
+        assert_eq!(unwrap(&stanza, &bob), None);
+
+        // fresh ephemeral key every time
+        assert_ne!(wrap(&file_key, &alice.public()).unwrap(), stanza);
+
+        let mut tampered = stanza.clone();
+        tampered[STANZA_LEN - 1] ^= 1;
+        assert_eq!(unwrap(&tampered, &alice), None);
+        assert_eq!(unwrap(&stanza[..STANZA_LEN - 1], &alice), None);
+    }
+
+    #[test]
+    fn low_order_recipients_are_refused() {
+        assert!(wrap(&[1u8; 32], &PublicKey::from([0u8; 32])).is_err());
+    }
+
+    #[test]
+    fn recipients_file_skips_comments_and_duplicates() {
+        let dir = crate::util::scratch_dir("recipients");
+        assert!(read_recipients(&dir).unwrap().is_empty());
+
+        let (a, b) = (Identity::generate().public(), Identity::generate().public());
+        let (ea, eb) = (encode_public(&a), encode_public(&b));
+        fs::write(
+            recipients_path(&dir),
+            format!("# team\n{ea} # alice\n\n  {eb}\n{ea} # alice again\n"),
+        )
+        .unwrap();
+        assert_eq!(read_recipients(&dir).unwrap(), vec![a, b]);
+
+        fs::write(recipients_path(&dir), format!("{ea}\nnot-a-key\n")).unwrap();
+        let err = read_recipients(&dir).unwrap_err();
+        assert!(err.to_string().contains("not-a-key"), "{err}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
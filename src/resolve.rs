@@ -0,0 +1,363 @@
+use crate::check::EnvReader;
+use crate::config::read_layers;
+use crate::crypto::binding_for;
+use crate::dotenv::Document;
+use crate::envscan::{DEFAULT_ENV, find_env_files_recursive, split_env_files};
+use crate::expand;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+// A named environment is a stack of files, lowest precedence first, by
+// default:
+//
+//   .env  <  .env.local  <  .env.<env>  <  .env.<env>.local
+//
+// ("layers" in eenv.config.json, with `{env}` for the name, changes it).
+// Without a name, or with "default" (the environment of .env itself), only
+// the layers without `{env}` apply. `run`, `check` and `resolve` all build
+// environments this way.
+//
+// Each layer is read from its plaintext file, or decrypted in memory from its
+// .enc when there is no plaintext. A key's effective value comes from the
+// top-most layer that sets it.
+
+pub struct Layer {
+    pub path: PathBuf,
+    pub doc: Document,
+}
+
+// The layer file names for `env`; `{env}` layers are left out without one.
+fn layer_names(repo_root: &Path, env: Option<&str>) -> io::Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for layer in read_layers(repo_root)? {
+        let name = match env {
+            Some(env) => layer.replace("{env}", env),
+            None if layer.contains("{env}") => continue,
+            None => layer,
+        };
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+fn source(dir: &Path, name: &str) -> Option<PathBuf> {
+    let plain = dir.join(name);
+    let enc = dir.join(format!("{name}.enc"));
+    [plain, enc].into_iter().find(|p| p.is_file())
+}
+
+// The directory the stack lives in: the nearest one from `cwd` up to the repo
+// root that has any of the layers, else the repo root.
+fn stack_dir(repo_root: &Path, cwd: &Path, names: &[String]) -> PathBuf {
+    let cwd = cwd.canonicalize().unwrap_or_else(|_| cwd.to_path_buf());
+    cwd.ancestors()
+        .take_while(|d| d.starts_with(repo_root))
+        .find(|d| names.iter().any(|n| source(d, n).is_some()))
+        .unwrap_or(repo_root)
+        .to_path_buf()
+}
+
+/// The layers of `env` that exist, lowest precedence first.
+pub fn layers(repo_root: &Path, cwd: &Path, env: Option<&str>) -> io::Result<Vec<Layer>> {
+    let env = env.filter(|e| *e != DEFAULT_ENV);
+    let names = layer_names(repo_root, env)?;
+    let dir = stack_dir(repo_root, cwd, &names);
+    let mut reader = EnvReader::new(repo_root);
+    let mut out = Vec::new();
+    for name in &names {
+        let Some(path) = source(&dir, name) else {
+            continue;
+        };
+        let doc = reader.document(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("{}: {e}", binding_for(repo_root, &path).path),
+            )
+        })?;
+        out.push(Layer { path, doc });
+    }
+    if out.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("none of {} found in {}", names.join(", "), dir.display()),
+        ));
+    }
+    Ok(out)
+}
+
+/// Which environment the layer file `file` (without `.enc`) belongs to:
+/// `Some(None)` for a layer without `{env}`, `None` when it is no layer. A
+/// file fitting several `{env}` layers goes with the most specific one, so
+/// `.env.production.local` is "production", not "production.local".
+pub fn env_of(templates: &[String], file: &str) -> Option<Option<String>> {
+    if templates.iter().any(|t| t == file) {
+        return Some(None);
+    }
+    templates
+        .iter()
+        .filter_map(|t| {
+            let (pre, post) = t.split_once("{env}")?;
+            let name = file.strip_prefix(pre)?.strip_suffix(post)?;
+            (!name.is_empty()).then_some((pre.len() + post.len(), name))
+        })
+        .max_by_key(|(specific, _)| *specific)
+        .map(|(_, name)| Some(name.to_string()))
+}
+
+/// An environment in one directory: `env` is `None` for the default one,
+/// the stack without `{env}` layers.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stack {
+    pub dir: PathBuf,
+    pub env: Option<String>,
+}
+
+/// Every environment with a layer file somewhere in the repo, plus the env
+/// files that belong to none.
+pub fn environments(repo_root: &Path) -> io::Result<(Vec<Stack>, Vec<PathBuf>)> {
+    let templates = read_layers(repo_root)?;
+    let (real, _examples, encs) = split_env_files(find_env_files_recursive(repo_root)?);
+    let mut envs = Vec::new();
+    let mut stray = Vec::new();
+    for path in real.into_iter().chain(encs) {
+        let (Some(dir), Some(file)) = (path.parent(), path.file_name().and_then(|s| s.to_str()))
+        else {
+            continue;
+        };
+        let file = file.strip_suffix(".enc").unwrap_or(file);
+        match env_of(&templates, file) {
+            Some(env) => {
+                let entry = Stack {
+                    dir: dir.to_path_buf(),
+                    env,
+                };
+                if !envs.contains(&entry) {
+                    envs.push(entry);
+                }
+            }
+            None => stray.push(path),
+        }
+    }
+    envs.sort();
+    Ok((envs, stray))
+}
+
+/// `eenv resolve`: prints the effective variables of `env` as a dotenv file,
+/// or with `explain`, where each one comes from and which layers it
+/// overrides (without values).
+pub fn run_resolve(
+    repo_root: &Path,
+    env: Option<&str>,
+    explain: bool,
+    expand: bool,
+) -> io::Result<()> {
+    let cwd = std::env::current_dir()?;
+    let layers = layers(repo_root, &cwd, env)?;
+    let docs: Vec<&Document> = layers.iter().map(|l| &l.doc).collect();
+    let vars = if expand {
+        expand::expand(&docs)?
+    } else {
+        expand::merge(&docs)
+    };
+
+    if !explain {
+        let mut out = Document::default();
+        for (key, value) in &vars {
+            out.insert(None, key, value);
+        }
+        println!("{out}");
+        return Ok(());
+    }
+
+    let rel = |p: &Path| binding_for(repo_root, p).path;
+    println!(
+        "[resolve] {}: {}",
+        env.unwrap_or(DEFAULT_ENV),
+        layers
+            .iter()
+            .map(|l| rel(&l.path))
+            .collect::<Vec<_>>()
+            .join(" < ")
+    );
+    let width = vars.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    for (key, _) in &vars {
+        let mut sets: Vec<String> = Vec::new();
+        for layer in &layers {
+            for pair in layer.doc.pairs().filter(|p| p.key == *key) {
+                sets.push(format!("{}:{}", rel(&layer.path), pair.line));
+            }
+        }
+        let Some(winner) = sets.pop() else {
+            continue;
+        };
+        if sets.is_empty() {
+            println!("  {key:<width$}  {winner}");
+        } else {
+            println!("  {key:<width$}  {winner}  (overrides {})", sets.join(", "));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DEFAULT_LAYERS, write_config_object};
+    use crate::util::scratch_dir;
+    use serde_json::json;
+    use std::fs;
+
+    fn files(dir: &Path, names: &[&str]) {
+        for name in names {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, format!("WHO={name}\n")).unwrap();
+        }
+    }
+
+    fn stack(dir: &Path, cwd: &Path, env: Option<&str>) -> Vec<String> {
+        layers(dir, cwd, env)
+            .unwrap()
+            .iter()
+            .map(|l| binding_for(dir, &l.path).path)
+            .collect()
+    }
+
+    #[test]
+    fn default_layers_stack_lowest_first() {
+        let dir = scratch_dir("resolve-default");
+        files(
+            &dir,
+            &[
+                ".env",
+                ".env.local",
+                ".env.production",
+                ".env.production.local",
+                ".env.staging",
+            ],
+        );
+        assert_eq!(
+            stack(&dir, &dir, Some("production")),
+            [
+                ".env",
+                ".env.local",
+                ".env.production",
+                ".env.production.local"
+            ]
+        );
+        assert_eq!(stack(&dir, &dir, None), [".env", ".env.local"]);
+        assert_eq!(stack(&dir, &dir, Some(DEFAULT_ENV)), [".env", ".env.local"]);
+        assert_eq!(
+            stack(&dir, &dir, Some("staging")),
+            [".env", ".env.local", ".env.staging"]
+        );
+        let top = layers(&dir, &dir, Some("production")).unwrap();
+        let docs: Vec<&Document> = top.iter().map(|l| &l.doc).collect();
+        assert_eq!(
+            expand::merge(&docs),
+            [("WHO".to_string(), ".env.production.local".to_string())]
+        );
+        let empty = scratch_dir("resolve-empty");
+        let err = layers(&empty, &empty, Some("qa")).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains(".env.qa"), "{err}");
+        let _ = fs::remove_dir_all(&empty);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn enc_stands_in_for_a_missing_plaintext_only() {
+        let dir = scratch_dir("resolve-enc");
+        files(&dir, &[".env", ".env.enc", ".env.production.enc"]);
+        let names = layer_names(&dir, Some("production")).unwrap();
+        assert_eq!(source(&dir, &names[0]), Some(dir.join(".env")));
+        assert_eq!(source(&dir, &names[1]), None);
+        assert_eq!(
+            source(&dir, &names[2]),
+            Some(dir.join(".env.production.enc"))
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn configured_layers_replace_the_default() {
+        let dir = scratch_dir("resolve-config");
+        let config = json!({ "layers": [".env.shared", ".env.{env}", ".env.shared"] });
+        write_config_object(&dir, config.as_object().unwrap().clone()).unwrap();
+        files(
+            &dir,
+            &[".env", ".env.local", ".env.shared", ".env.production"],
+        );
+        assert_eq!(
+            stack(&dir, &dir, Some("production")),
+            [".env.shared", ".env.production"]
+        );
+        assert_eq!(stack(&dir, &dir, None), [".env.shared"]);
+
+        for bad in [json!([]), json!(["sub/.env"]), json!("x")] {
+            write_config_object(&dir, json!({ "layers": bad }).as_object().unwrap().clone())
+                .unwrap();
+            assert!(layers(&dir, &dir, None).is_err(), "{bad}");
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn nearest_directory_with_layers_wins() {
+        let dir = scratch_dir("resolve-nearest");
+        files(&dir, &[".env", "apps/web/.env", "apps/web/.env.production"]);
+        let web = dir.join("apps/web");
+        let deep = web.join("src");
+        fs::create_dir_all(&deep).unwrap();
+        assert_eq!(
+            stack(&dir, &deep, Some("production")),
+            ["apps/web/.env", "apps/web/.env.production"]
+        );
+        assert_eq!(stack(&dir, &dir.join("apps"), None), [".env"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_belong_to_the_most_specific_layer() {
+        let templates: Vec<String> = DEFAULT_LAYERS.iter().map(|s| s.to_string()).collect();
+        assert_eq!(env_of(&templates, ".env"), Some(None));
+        assert_eq!(env_of(&templates, ".env.local"), Some(None));
+        assert_eq!(
+            env_of(&templates, ".env.production"),
+            Some(Some("production".into()))
+        );
+        assert_eq!(
+            env_of(&templates, ".env.production.local"),
+            Some(Some("production".into()))
+        );
+        assert_eq!(env_of(&templates, "env.production"), None);
+
+        let dir = scratch_dir("resolve-environments");
+        files(
+            &dir,
+            &[".env", ".env.production.enc", "apps/.env.staging.local"],
+        );
+        fs::write(dir.join(".envrc"), "").unwrap();
+        let config = json!({ "layers": [".env", ".env.{env}", ".env.{env}.local"] });
+        write_config_object(&dir, config.as_object().unwrap().clone()).unwrap();
+        let (envs, stray) = environments(&dir).unwrap();
+        let found: Vec<(String, Option<String>)> = envs
+            .into_iter()
+            .map(|s| (binding_for(&dir, &s.dir.join("x")).path, s.env))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("x".to_string(), None),
+                ("x".to_string(), Some("production".to_string())),
+                ("apps/x".to_string(), Some("staging".to_string())),
+            ]
+        );
+        assert_eq!(stray, [dir.join(".envrc")]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
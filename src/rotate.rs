@@ -0,0 +1,320 @@
+use crate::config::{
+    eenv_config_path, read_config_object, read_identity, read_key_map, write_eenv_config_with_key,
+    write_env_key, write_kdf_params,
+};
+use crate::crypto::{binding_for, decrypt_bytes, encrypt_like, is_eenv_file, repo_key_scheme};
+use crate::envscan::find_enc_files_recursive;
+use crate::kdf::KdfParams;
+use crate::keyring::{KeyScheme, Keyring};
+use crate::keysource::external_key;
+use crate::util::{backup_path_with_ts, generate_key, write_bytes_atomic};
+use std::{fs, io, path::Path, path::PathBuf};
+
+/// Re-encrypts every `.enc` file (env files and `eenv encrypt` artifacts)
+/// that uses the default key (or, with `env`, the `"keys"` entry of that
+/// name) under a new key, generated unless `new_key` is given. Rotating the
+/// default key also picks a fresh KDF salt.
+///
+/// Nothing is written until every file has been checked against
+/// eenv.signers, decrypted with the old key and its new ciphertext verified
+/// to decrypt back to the same plaintext, so rotating never re-signs a file
+/// nobody trusted signed.
+/// The new key then goes into eenv.config.json (the old config is backed up)
+/// before any file is replaced, and if replacing one fails the files already
+/// replaced get their old ciphertext back, so no `.enc` is ever left under a
+/// key that exists nowhere.
+pub fn rotate_key(
+    repo_root: &Path,
+    new_key: Option<String>,
+    env: Option<String>,
+) -> io::Result<()> {
+    if env.is_none()
+        && let Some((_, source)) = external_key(&read_config_object(repo_root)?)?
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "the key comes from {source}, which eenv can't update; rotate on a machine whose eenv.config.json holds the key"
+            ),
+        ));
+    }
+    let keys = read_key_map(repo_root)?;
+    crate::manifest::ensure_clean(repo_root, &keys, None)
+        .map_err(|e| io::Error::new(e.kind(), format!("{e}; nothing was changed")))?;
+    let identities = read_identity(repo_root)?.into_iter().collect::<Vec<_>>();
+    let new_key = new_key.unwrap_or_else(generate_key);
+    let new = Keyring::new(Some(&new_key), identities)?;
+    let (scheme, new_params) = match repo_key_scheme(repo_root)? {
+        KeyScheme::Shared(_) if env.is_none() => {
+            let params = KdfParams::generate();
+            (KeyScheme::Shared(params.clone()), Some(params))
+        }
+        scheme => (scheme, None),
+    };
+
+    let encs = find_enc_files_recursive(repo_root)?;
+
+    // (path, old ciphertext, new ciphertext)
+    let mut staged: Vec<(PathBuf, Vec<u8>, Vec<u8>)> = Vec::new();
+    for enc_path in &encs {
+        let binding = binding_for(repo_root, enc_path);
+        if !keys.selects(env.as_deref(), &binding) {
+            continue;
+        }
+        let old = keys.keyring_for(&binding)?;
+        let data = fs::read(enc_path)?;
+        if !is_eenv_file(&data) {
+            continue;
+        }
+        let plaintext = crate::signing::verify_bytes(repo_root, &binding, &data)
+            .and_then(|_| decrypt_bytes(old, &data, Some(&binding)))
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("{}: {e}; nothing was changed", enc_path.display()),
+                )
+            })?;
+        let out = encrypt_like(&data, &new, &scheme, &plaintext, &binding)?;
+        if decrypt_bytes(&new, &out, Some(&binding))? != plaintext {
+            return Err(io::Error::other(format!(
+                "{}: round trip under the new key failed; nothing was changed",
+                enc_path.display()
+            )));
+        }
+        staged.push((enc_path.clone(), data, out));
+    }
+
+    // the new key is on disk before any file depends on it, and the old one
+    // stays in the backup until every file is replaced
+    let config = eenv_config_path(repo_root);
+    let backup = backup_path_with_ts(&config);
+    fs::copy(&config, &backup)?;
+    let saved = (|| {
+        match &env {
+            Some(selector) => write_env_key(repo_root, selector, &new_key)?,
+            None => write_eenv_config_with_key(repo_root, &new_key)?,
+        }
+        match &new_params {
+            Some(params) => write_kdf_params(repo_root, params),
+            None => Ok(()),
+        }
+    })();
+    if let Err(e) = saved {
+        fs::copy(&backup, &config)?;
+        fs::remove_file(&backup)?;
+        return Err(io::Error::new(
+            e.kind(),
+            format!("could not save the new key ({e}); nothing was changed"),
+        ));
+    }
+
+    let mut replaced = 0;
+    let written = staged.iter().try_for_each(|(path, _, out)| {
+        write_bytes_atomic(path, out)?;
+        replaced += 1;
+        println!("[rotate] re-encrypted {}", path.display());
+        Ok::<_, io::Error>(())
+    });
+    if let Err(e) = written {
+        let restored = staged[..replaced]
+            .iter()
+            .try_for_each(|(path, old, _)| write_bytes_atomic(path, old))
+            .and_then(|()| fs::copy(&backup, &config).map(|_| ()));
+        return Err(match restored {
+            Ok(()) => {
+                fs::remove_file(&backup)?;
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "{e}; the files already re-encrypted were restored, nothing was changed"
+                    ),
+                )
+            }
+            Err(restore) => io::Error::new(
+                e.kind(),
+                format!(
+                    "{e}; restoring the old files failed too ({restore}): the new key is in {}, \
+                     the old one in {}; keep both until every file decrypts",
+                    config.display(),
+                    backup.display()
+                ),
+            ),
+        });
+    }
+    fs::remove_file(&backup)?;
+    let paths: Vec<PathBuf> = staged.iter().map(|(p, _, _)| p.clone()).collect();
+    crate::signing::sign_files(repo_root, &paths)?;
+    crate::manifest::update(repo_root, &read_key_map(repo_root)?)?;
+
+    println!("[rotate] {} file(s) rotated", staged.len());
+    match scheme {
+        KeyScheme::Shared(_) => {
+            println!(
+                "[rotate] new key (share it over a secure channel, then commit the .enc files):"
+            );
+            println!("{new_key}");
+            match &env {
+                Some(selector) => println!(
+                    "[rotate] teammates with access replace \"keys\".\"{selector}\" in their eenv.config.json"
+                ),
+                None => println!("[rotate] teammates replace \"key\" in their eenv.config.json"),
+            }
+        }
+        KeyScheme::Recipients(pks) => {
+            println!(
+                "[rotate] files use fresh file keys for {} recipient(s); nothing to share, commit the .enc files",
+                pks.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{read_config_object, write_config_object};
+    use crate::crypto::encrypt_bytes;
+    use crate::signing;
+    use crate::util::scratch_dir;
+    use serde_json::json;
+
+    const FILES: [(&str, &str); 2] = [
+        (".env.enc", "A=default\n"),
+        (".env.production.enc", "A=production\n"),
+    ];
+
+    // a repo with both files encrypted under "old-key"
+    fn setup(name: &str) -> (PathBuf, KdfParams) {
+        let dir = scratch_dir(name);
+        let params = KdfParams::cheap();
+        write_config_object(
+            &dir,
+            json!({ "key": "old-key", "kdf": params.to_json() })
+                .as_object()
+                .unwrap()
+                .clone(),
+        )
+        .unwrap();
+        let old = Keyring::from_secret("old-key").unwrap();
+        let scheme = KeyScheme::Shared(params.clone());
+        for (name, plain) in FILES {
+            let path = dir.join(name);
+            let out = encrypt_bytes(&old, &scheme, plain.as_bytes(), &binding_for(&dir, &path));
+            fs::write(&path, out.unwrap()).unwrap();
+        }
+        (dir, params)
+    }
+
+    fn decrypts(dir: &Path, name: &str, key: &str) -> Option<String> {
+        let path = dir.join(name);
+        let key = Keyring::from_secret(key).unwrap();
+        decrypt_bytes(
+            &key,
+            &fs::read(&path).unwrap(),
+            Some(&binding_for(dir, &path)),
+        )
+        .ok()
+        .map(|b| String::from_utf8(b).unwrap())
+    }
+
+    fn backups(dir: &Path) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("eenv.config.bak")
+            })
+            .count()
+    }
+
+    #[test]
+    fn rotates_every_file_and_the_config() {
+        let (dir, params) = setup("rotate-all");
+        rotate_key(&dir, Some("new-key".into()), None).unwrap();
+        for (name, plain) in FILES {
+            assert_eq!(decrypts(&dir, name, "new-key").as_deref(), Some(plain));
+            assert_eq!(decrypts(&dir, name, "old-key"), None);
+        }
+        let config = read_config_object(&dir).unwrap();
+        assert_eq!(config["key"], "new-key");
+        assert_ne!(config["kdf"], params.to_json(), "fresh salt");
+        assert_eq!(backups(&dir), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn env_splits_matching_files_off_the_default_key() {
+        let (dir, params) = setup("rotate-env");
+        let untouched = fs::read(dir.join(".env.enc")).unwrap();
+        rotate_key(&dir, Some("prod-key".into()), Some("production".into())).unwrap();
+        assert_eq!(fs::read(dir.join(".env.enc")).unwrap(), untouched);
+        assert_eq!(
+            decrypts(&dir, ".env.production.enc", "prod-key").as_deref(),
+            Some("A=production\n")
+        );
+        assert_eq!(decrypts(&dir, ".env.production.enc", "old-key"), None);
+        let config = read_config_object(&dir).unwrap();
+        assert_eq!(config["key"], "old-key");
+        assert_eq!(config["keys"]["production"], "prod-key");
+        assert_eq!(config["kdf"], params.to_json(), "shared salt is kept");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_write_restores_files_and_config() {
+        let (dir, _) = setup("rotate-fail");
+        let before: Vec<Vec<u8>> = FILES
+            .iter()
+            .map(|(n, _)| fs::read(dir.join(n)).unwrap())
+            .collect();
+        let config_before = fs::read(eenv_config_path(&dir)).unwrap();
+        // the temp file for the second (last) file can't be created
+        fs::create_dir(dir.join(".env.production.tmp~")).unwrap();
+
+        let err = rotate_key(&dir, Some("new-key".into()), None).unwrap_err();
+        assert!(err.to_string().contains("nothing was changed"), "{err}");
+        for ((name, plain), old) in FILES.iter().zip(&before) {
+            assert_eq!(&fs::read(dir.join(name)).unwrap(), old);
+            assert_eq!(decrypts(&dir, name, "old-key").as_deref(), Some(*plain));
+        }
+        assert_eq!(fs::read(eenv_config_path(&dir)).unwrap(), config_before);
+        assert_eq!(backups(&dir), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn undecryptable_file_changes_nothing() {
+        let (dir, _) = setup("rotate-undecryptable");
+        fs::write(dir.join(".env.enc"), b"EENV2 garbage").unwrap();
+        let other = fs::read(dir.join(".env.production.enc")).unwrap();
+        let config_before = fs::read(eenv_config_path(&dir)).unwrap();
+        assert!(rotate_key(&dir, Some("new-key".into()), None).is_err());
+        assert_eq!(fs::read(dir.join(".env.production.enc")).unwrap(), other);
+        assert_eq!(fs::read(eenv_config_path(&dir)).unwrap(), config_before);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unsigned_file_changes_nothing() {
+        let (dir, _) = setup("rotate-unsigned");
+        let signer = signing::SigningIdentity::generate();
+        let line = format!("production {}\n", signing::encode_public(&signer.public()));
+        fs::write(dir.join(signing::SIGNERS_FILE), line).unwrap();
+        let before: Vec<Vec<u8>> = FILES
+            .iter()
+            .map(|(n, _)| fs::read(dir.join(n)).unwrap())
+            .collect();
+        let config_before = fs::read(eenv_config_path(&dir)).unwrap();
+
+        let err = rotate_key(&dir, Some("new-key".into()), None).unwrap_err();
+        assert!(err.to_string().contains("must be signed"), "{err}");
+        assert!(err.to_string().contains("nothing was changed"), "{err}");
+        for ((name, _), old) in FILES.iter().zip(&before) {
+            assert_eq!(&fs::read(dir.join(name)).unwrap(), old);
+        }
+        assert_eq!(fs::read(eenv_config_path(&dir)).unwrap(), config_before);
+        assert!(!signing::signatures_path(&dir).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::dotenv::Document;
+use crate::expand;
+use crate::resolve;
+use crate::schema;
+use std::{io, path::Path, process::Command};
+
+/// Builds `env` from its layers (see `resolve::layers`), decrypting `.enc`
+/// layers in memory, and runs `cmd` with its variables added to the
+/// environment. Nothing is written to disk. On unix the command replaces
+/// this process, so it receives signals directly and its exit code is ours;
+/// elsewhere it is waited for and its exit code passed through. `${VAR}`
+/// references are expanded across the layers unless `expand` is false.
+pub fn run(repo_root: &Path, env: Option<&str>, cmd: &[String], expand: bool) -> io::Result<()> {
+    let Some((program, args)) = cmd.split_first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no command given (eenv run --env <name> -- <cmd>...)",
+        ));
+    };
+    let cwd = std::env::current_dir()?;
+    let vars = child_env(repo_root, &cwd, env, expand)?;
+
+    let mut command = Command::new(program);
+    command.args(args).envs(vars);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let err = command.exec();
+        Err(io::Error::new(err.kind(), format!("{program}: {err}")))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command
+            .status()
+            .map_err(|e| io::Error::new(e.kind(), format!("{program}: {e}")))?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+// The variables `run` adds to the child's environment.
+fn child_env(
+    repo_root: &Path,
+    cwd: &Path,
+    env: Option<&str>,
+    expand: bool,
+) -> io::Result<Vec<(String, String)>> {
+    let layers = resolve::layers(repo_root, cwd, env)?;
+    let docs: Vec<&Document> = layers.iter().map(|l| &l.doc).collect();
+    let mut vars = if expand {
+        expand::expand(&docs)?
+    } else {
+        expand::merge(&docs)
+    };
+    if let Some(schema) = schema::load(repo_root)? {
+        // a default never overrides a variable the caller already exported
+        let schema: Vec<schema::VarSpec> = schema
+            .into_iter()
+            .filter(|s| {
+                vars.iter().any(|(k, _)| *k == s.name) || std::env::var_os(&s.name).is_none()
+            })
+            .collect();
+        schema::apply_defaults(&schema, &mut vars);
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{scratch_dir, scratch_keyed, seal_env};
+    use std::fs;
+
+    fn get<'a>(vars: &'a [(String, String)], key: &str) -> Option<&'a str> {
+        vars.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn upper_layers_win_and_enc_layers_are_decrypted_in_memory() {
+        let dir = scratch_keyed("run-layers");
+        fs::write(dir.join(".env"), "A=base\nB=base\nC=base\n").unwrap();
+        fs::write(dir.join(".env.local"), "B=local\n").unwrap();
+        seal_env(&dir, ".env.production.enc", "C=production\n");
+
+        let vars = child_env(&dir, &dir, Some("production"), true).unwrap();
+        assert_eq!(get(&vars, "A"), Some("base"));
+        assert_eq!(get(&vars, "B"), Some("local"));
+        assert_eq!(get(&vars, "C"), Some("production"));
+        assert!(!dir.join(".env.production").exists());
+
+        // without a name only the layers without {env} apply
+        let vars = child_env(&dir, &dir, None, true).unwrap();
+        assert_eq!(get(&vars, "C"), Some("base"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn expansion_and_schema_defaults() {
+        let dir = scratch_dir("run-expand");
+        fs::write(dir.join(".env"), "HOST=db\nURL=postgres://${HOST}/app\n").unwrap();
+        fs::write(
+            dir.join(schema::SCHEMA_FILE),
+            r#"{ "PORT": { "type": "int", "default": "3000" } }"#,
+        )
+        .unwrap();
+
+        let vars = child_env(&dir, &dir, None, true).unwrap();
+        assert_eq!(get(&vars, "URL"), Some("postgres://db/app"));
+        assert_eq!(get(&vars, "PORT"), Some("3000"));
+
+        let vars = child_env(&dir, &dir, None, false).unwrap();
+        assert_eq!(get(&vars, "URL"), Some("postgres://${HOST}/app"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn defaults_leave_inherited_variables_alone() {
+        let dir = scratch_dir("run-inherited");
+        fs::write(dir.join(".env"), "HOST=\n").unwrap();
+        fs::write(
+            dir.join(schema::SCHEMA_FILE),
+            r#"{ "PATH": { "default": "/nowhere" }, "HOST": { "default": "db" } }"#,
+        )
+        .unwrap();
+        assert!(std::env::var_os("PATH").is_some());
+
+        let vars = child_env(&dir, &dir, None, true).unwrap();
+        // the child inherits PATH as it is
+        assert_eq!(get(&vars, "PATH"), None);
+        // an empty value from a layer would override it, so it still takes the default
+        assert_eq!(get(&vars, "HOST"), Some("db"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_command_or_environment_is_an_error() {
+        let dir = scratch_dir("run-errors");
+        let err = run(&dir, None, &[], true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = child_env(&dir, &dir, Some("staging"), true).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
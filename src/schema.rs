@@ -0,0 +1,314 @@
+use regex::Regex;
+use serde_json::Value;
+use std::{fs, io, path::Path};
+
+// The tracked eenv.schema declares what an environment must contain:
+//
+//   {
+//     "DATABASE_URL": { "type": "url" },
+//     "PORT":         { "type": "int", "default": "3000" },
+//     "DEBUG":        { "type": "bool", "required": false },
+//     "LOG_LEVEL":    { "type": "enum", "values": ["debug", "info", "warn"] },
+//     "API_KEY":      { "type": "regex", "pattern": "sk_[A-Za-z0-9]{32}" },
+//     "APP_NAME":     "string"
+//   }
+//
+// A bare string is shorthand for `{ "type": ... }`. Variables are required
+// unless they have a "default" or `"required": false`; a default must fit
+// the type and stands in for a missing or empty value. A regex must match
+// the whole value. Violations name the variable and the rule, never the value.
+pub const SCHEMA_FILE: &str = "eenv.schema";
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub enum VarType {
+    String,
+    Int,
+    Bool,
+    Url,
+    Enum(Vec<String>),
+    Regex(Regex, String),
+}
+
+pub struct VarSpec {
+    pub name: String,
+    pub ty: VarType,
+    pub required: bool,
+    /// Used when the variable is missing or empty; always fits `ty`.
+    pub default: Option<String>,
+}
+
+fn parse_spec(name: &str, spec: &Value) -> io::Result<VarSpec> {
+    let bad = |msg: &str| invalid(format!("{SCHEMA_FILE}: {name}: {msg}"));
+    let (ty_name, obj) = match spec {
+        Value::String(s) => (s.as_str(), None),
+        Value::Object(o) => (
+            o.get("type").and_then(|t| t.as_str()).unwrap_or("string"),
+            Some(o),
+        ),
+        _ => return Err(bad("expected a type name or an object")),
+    };
+    let field = |k: &str| obj.and_then(|o| o.get(k));
+    let ty = match ty_name {
+        "string" => VarType::String,
+        "int" => VarType::Int,
+        "bool" => VarType::Bool,
+        "url" => VarType::Url,
+        "enum" => {
+            let values = field("values")
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect()
+                })
+                .filter(|v: &Vec<String>| !v.is_empty())
+                .ok_or_else(|| bad("enum needs a non-empty \"values\" list"))?;
+            VarType::Enum(values)
+        }
+        "regex" => {
+            let pattern = field("pattern")
+                .and_then(|p| p.as_str())
+                .ok_or_else(|| bad("regex needs a \"pattern\""))?;
+            let re = Regex::new(&format!("^(?:{pattern})$"))
+                .map_err(|e| bad(&format!("bad pattern: {e}")))?;
+            VarType::Regex(re, pattern.to_string())
+        }
+        other => {
+            return Err(bad(&format!(
+                "unknown type \"{other}\" (expected string, int, bool, url, enum or regex)"
+            )));
+        }
+    };
+    let default = match field("default") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        Some(Value::Bool(b)) => Some(b.to_string()),
+        Some(_) => return Err(bad("\"default\" must be a string, number or bool")),
+    };
+    if let Some(d) = &default {
+        if d.is_empty() {
+            return Err(bad("\"default\" must not be empty"));
+        }
+        if let Some(e) = type_error(&ty, d) {
+            return Err(bad(&format!("\"default\" doesn't fit its type: {e}")));
+        }
+    }
+    let required = match field("required") {
+        None => default.is_none(),
+        Some(Value::Bool(b)) => *b,
+        Some(_) => return Err(bad("\"required\" must be true or false")),
+    };
+    Ok(VarSpec {
+        name: name.to_string(),
+        ty,
+        required,
+        default,
+    })
+}
+
+/// The variables declared in eenv.schema, by name, or `None` when the repo
+/// has no schema.
+pub fn load(repo_root: &Path) -> io::Result<Option<Vec<VarSpec>>> {
+    let path = repo_root.join(SCHEMA_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let map = match serde_json::from_str(&fs::read_to_string(&path)?) {
+        Ok(Value::Object(map)) => map,
+        Ok(_) => return Err(invalid(format!("{SCHEMA_FILE}: expected a JSON object"))),
+        Err(e) => return Err(invalid(format!("{SCHEMA_FILE}: {e}"))),
+    };
+    map.iter()
+        .map(|(name, spec)| parse_spec(name, spec))
+        .collect::<io::Result<Vec<_>>>()
+        .map(Some)
+}
+
+fn is_url(v: &str) -> bool {
+    let Some((scheme, rest)) = v.split_once("://") else {
+        return false;
+    };
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        && !rest.is_empty()
+        && !v.contains(char::is_whitespace)
+}
+
+/// Why `value` doesn't fit `ty`, if it doesn't. Never includes the value.
+fn type_error(ty: &VarType, value: &str) -> Option<String> {
+    let (ok, expected) = match ty {
+        VarType::String => return None,
+        VarType::Int => (
+            value.parse::<i64>().is_ok(),
+            "expected an integer".to_string(),
+        ),
+        VarType::Bool => (
+            matches!(
+                value.to_ascii_lowercase().as_str(),
+                "true" | "false" | "1" | "0" | "yes" | "no" | "on" | "off"
+            ),
+            "expected a boolean (true/false, 1/0, yes/no, on/off)".to_string(),
+        ),
+        VarType::Url => (is_url(value), "expected a URL (scheme://...)".to_string()),
+        VarType::Enum(values) => (
+            values.iter().any(|v| v == value),
+            format!("expected one of {}", values.join(", ")),
+        ),
+        VarType::Regex(re, pattern) => (re.is_match(value), format!("does not match {pattern}")),
+    };
+    (!ok).then_some(expected)
+}
+
+pub struct Report {
+    pub errors: Vec<String>,
+    pub unknown: Vec<String>,
+    /// Variables that took their schema default.
+    pub defaulted: Vec<String>,
+}
+
+/// Sets every variable that is missing or empty and has a default to that
+/// default. Returns their names.
+pub fn apply_defaults(schema: &[VarSpec], vars: &mut Vec<(String, String)>) -> Vec<String> {
+    let mut applied = Vec::new();
+    for spec in schema {
+        let Some(default) = &spec.default else {
+            continue;
+        };
+        match vars.iter_mut().rev().find(|(k, _)| *k == spec.name) {
+            Some((_, v)) if !v.is_empty() => continue,
+            Some((_, v)) => *v = default.clone(),
+            None => vars.push((spec.name.clone(), default.clone())),
+        }
+        applied.push(spec.name.clone());
+    }
+    applied
+}
+
+/// Checks one environment's variables against the schema, after applying
+/// its defaults.
+pub fn validate(schema: &[VarSpec], vars: &[(String, String)]) -> Report {
+    let given = vars;
+    let mut vars = vars.to_vec();
+    let defaulted = apply_defaults(schema, &mut vars);
+    let get = |name: &str| vars.iter().rev().find(|(k, _)| k == name).map(|(_, v)| v);
+    let mut errors = Vec::new();
+    for spec in schema {
+        match get(&spec.name).filter(|v| !v.is_empty()) {
+            Some(value) => {
+                if let Some(e) = type_error(&spec.ty, value) {
+                    errors.push(format!("{}: {e}", spec.name));
+                }
+            }
+            None if spec.required => {
+                let what = if get(&spec.name).is_some() {
+                    "is empty"
+                } else {
+                    "is missing"
+                };
+                errors.push(format!("{}: {what} (required)", spec.name));
+            }
+            None => {}
+        }
+    }
+    let mut unknown: Vec<String> = Vec::new();
+    for (k, _) in given {
+        if !schema.iter().any(|s| &s.name == k) && !unknown.contains(k) {
+            unknown.push(k.clone());
+        }
+    }
+    Report {
+        errors,
+        unknown,
+        defaulted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn spec(name: &str, v: Value) -> io::Result<VarSpec> {
+        parse_spec(name, &v)
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn defaults_must_fit_their_type() {
+        assert!(spec("PORT", json!({ "type": "int", "default": "3000" })).is_ok());
+        assert!(spec("PORT", json!({ "type": "int", "default": 3000 })).is_ok());
+        assert!(spec("DEBUG", json!({ "type": "bool", "default": false })).is_ok());
+        for bad in [
+            json!({ "type": "int", "default": "abc" }),
+            json!({ "type": "bool", "default": "maybe" }),
+            json!({ "type": "url", "default": "localhost" }),
+            json!({ "type": "enum", "values": ["a", "b"], "default": "c" }),
+            json!({ "type": "regex", "pattern": "[0-9]+", "default": "x1" }),
+            json!({ "type": "string", "default": "" }),
+            json!({ "type": "string", "default": ["a"] }),
+        ] {
+            let e = spec("X", bad.clone())
+                .err()
+                .unwrap_or_else(|| panic!("{bad}"));
+            assert!(e.to_string().contains("\"default\""), "{e}");
+        }
+    }
+
+    #[test]
+    fn defaults_fill_missing_and_empty_values() {
+        let schema = vec![
+            spec("PORT", json!({ "type": "int", "default": 3000 })).unwrap(),
+            spec("HOST", json!({ "type": "string", "default": "localhost" })).unwrap(),
+            spec("MODE", json!({ "type": "string", "default": "dev" })).unwrap(),
+        ];
+        let mut env = vars(&[("HOST", ""), ("MODE", "prod")]);
+        let applied = apply_defaults(&schema, &mut env);
+        assert_eq!(applied, ["PORT", "HOST"]);
+        assert_eq!(
+            env,
+            vars(&[("HOST", "localhost"), ("MODE", "prod"), ("PORT", "3000")])
+        );
+
+        let report = validate(&schema, &vars(&[("HOST", "")]));
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.defaulted, ["PORT", "HOST", "MODE"]);
+    }
+
+    #[test]
+    fn required_without_default_is_reported_without_values() {
+        let schema = vec![
+            spec("DATABASE_URL", json!("url")).unwrap(),
+            spec(
+                "PORT",
+                json!({ "type": "int", "required": true, "default": 1 }),
+            )
+            .unwrap(),
+            spec("DEBUG", json!({ "type": "bool", "required": false })).unwrap(),
+        ];
+        let report = validate(
+            &schema,
+            &vars(&[("DATABASE_URL", "not a url"), ("EXTRA", "secret")]),
+        );
+        assert_eq!(
+            report.errors,
+            ["DATABASE_URL: expected a URL (scheme://...)"]
+        );
+        assert_eq!(report.unknown, ["EXTRA"]);
+        assert_eq!(report.defaulted, ["PORT"]);
+        assert!(!report.errors.iter().any(|e| e.contains("not a url")));
+
+        let report = validate(&schema, &[]);
+        assert_eq!(report.errors, ["DATABASE_URL: is missing (required)"]);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::config::read_key_map;
+use crate::util::{from_hex, to_hex};
+use rand::Rng;
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+// Recovery shares of the shared key (Shamir's secret sharing over GF(256)),
+// one printable line each:
+//
+//   eenv-share-v1:<threshold>:<hex share>:<hex checksum>
+//
+// Any `threshold` of them rebuild the key; fewer reveal nothing about it. The
+// checksum only catches typos when a share is typed back in from paper.
+//
+// A share is its x coordinate (1..=255) followed by one y byte per byte of
+// the key: each key byte is the constant term of its own random polynomial
+// of degree threshold - 1. The field uses the polynomial
+// x^8 + x^4 + x^3 + x^2 + 1 (0x11d).
+pub const SHARE_PREFIX: &str = "eenv-share-v1:";
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub fn is_share(s: &str) -> bool {
+    s.trim().starts_with(SHARE_PREFIX)
+}
+
+fn checksum(threshold: u8, share: &[u8]) -> String {
+    let mut h = blake3::Hasher::new();
+    h.update(&[threshold]);
+    h.update(share);
+    to_hex(&h.finalize().as_bytes()[..2])
+}
+
+// multiplication in GF(256), without branches or table lookups on the
+// (secret) operands
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1d & carry);
+        b >>= 1;
+    }
+    product
+}
+
+// a^254, the inverse of a non-zero `a`
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut square = a;
+    for _ in 0..7 {
+        square = gf_mul(square, square);
+        result = gf_mul(result, square);
+    }
+    result
+}
+
+// `shares` shares of `secret` (x = 1, 2, ...), any `threshold` of which
+// rebuild it
+fn deal(secret: &[u8], shares: u8, threshold: u8) -> Vec<Vec<u8>> {
+    let mut rng = rand::rng();
+    // coefficients, constant term first
+    let polys: Vec<Vec<u8>> = secret
+        .iter()
+        .map(|&s| {
+            let mut poly = vec![s];
+            poly.extend((1..threshold).map(|_| rng.random::<u8>()));
+            poly
+        })
+        .collect();
+    (1..=shares)
+        .map(|x| {
+            let mut share = vec![x];
+            share.extend(
+                polys
+                    .iter()
+                    .map(|p| p.iter().rev().fold(0, |acc, &c| gf_mul(acc, x) ^ c)),
+            );
+            share
+        })
+        .collect()
+}
+
+// The secret at x = 0 by Lagrange interpolation. Repeated shares count once;
+// two different shares with the same x can't both be right.
+fn recover(threshold: u8, shares: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    let mut points: Vec<&[u8]> = Vec::new();
+    for share in shares {
+        if share[0] == 0 || share.len() != shares[0].len() {
+            return Err(invalid("shares don't belong together"));
+        }
+        match points.iter().find(|p| p[0] == share[0]) {
+            Some(p) if *p != share.as_slice() => {
+                return Err(invalid("shares don't belong together"));
+            }
+            Some(_) => {}
+            None => points.push(share),
+        }
+    }
+    if points.len() < threshold as usize {
+        return Err(invalid(format!(
+            "not enough shares ({} share(s) given, {threshold} needed)",
+            points.len()
+        )));
+    }
+    let weights: Vec<u8> = points
+        .iter()
+        .map(|pi| {
+            points
+                .iter()
+                .filter(|pj| pj[0] != pi[0])
+                .fold(1, |acc, pj| {
+                    gf_mul(acc, gf_mul(pj[0], gf_inv(pj[0] ^ pi[0])))
+                })
+        })
+        .collect();
+    Ok((1..shares[0].len())
+        .map(|i| {
+            points
+                .iter()
+                .zip(&weights)
+                .fold(0, |acc, (p, &w)| acc ^ gf_mul(w, p[i]))
+        })
+        .collect())
+}
+
+fn encode_share(threshold: u8, share: &[u8]) -> String {
+    format!(
+        "{SHARE_PREFIX}{threshold}:{}:{}",
+        to_hex(share),
+        checksum(threshold, share)
+    )
+}
+
+fn decode_share(line: &str) -> io::Result<(u8, Vec<u8>)> {
+    let bad = || invalid(format!("not a recovery share: {line}"));
+    let rest = line.trim().strip_prefix(SHARE_PREFIX).ok_or_else(bad)?;
+    let mut parts = rest.split(':');
+    let (Some(t), Some(hex), Some(check), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad());
+    };
+    let threshold: u8 = t.parse().map_err(|_| bad())?;
+    let bytes = from_hex(hex).filter(|b| b.len() > 1).ok_or_else(bad)?;
+    if checksum(threshold, &bytes) != check {
+        return Err(invalid(format!(
+            "recovery share {}… has a typo (checksum mismatch)",
+            &hex[..hex.len().min(8)]
+        )));
+    }
+    Ok((threshold, bytes))
+}
+
+/// Splits `secret` into `shares` printable shares, any `threshold` of which
+/// rebuild it.
+pub fn split(secret: &str, shares: u8, threshold: u8) -> io::Result<Vec<String>> {
+    if threshold < 2 || threshold > shares {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "need 2 <= --threshold <= --shares <= 255",
+        ));
+    }
+    Ok(deal(secret.as_bytes(), shares, threshold)
+        .iter()
+        .map(|s| encode_share(threshold, s))
+        .collect())
+}
+
+/// Rebuilds a secret from share lines. All shares must come from the same
+/// split; fewer than its threshold is an error.
+pub fn combine(lines: &[String]) -> io::Result<String> {
+    let mut threshold = None;
+    let mut shares = Vec::new();
+    for line in lines {
+        let (t, bytes) = decode_share(line)?;
+        if *threshold.get_or_insert(t) != t {
+            return Err(invalid("shares come from different splits"));
+        }
+        shares.push(bytes);
+    }
+    let threshold = threshold.ok_or_else(|| invalid("no recovery shares given"))?;
+    let secret = recover(threshold, &shares)?;
+    String::from_utf8(secret).map_err(|_| invalid("shares don't belong together"))
+}
+
+/// Reads shares from stdin, one per line, until the threshold of the first
+/// one is reached (or input ends), starting from `first` if given.
+pub fn read_shares(first: Option<String>) -> io::Result<Vec<String>> {
+    let mut lines: Vec<String> = first.into_iter().collect();
+    let stdin = io::stdin();
+    loop {
+        let needed = match lines.first() {
+            Some(l) => decode_share(l)?.0 as usize,
+            None => usize::MAX,
+        };
+        if lines.len() >= needed {
+            return Ok(lines);
+        }
+        if needed == usize::MAX {
+            print!("Enter recovery share: ");
+        } else {
+            print!("Enter recovery share {} of {needed}: ", lines.len() + 1);
+        }
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(lines);
+        }
+        let line = line.trim();
+        if !line.is_empty() {
+            decode_share(line)?;
+            lines.push(line.to_string());
+        }
+    }
+}
+
+/// `eenv key split`: prints recovery shares of the default shared key.
+pub fn run_split(repo_root: &Path, shares: u8, threshold: u8) -> io::Result<()> {
+    let keys = read_key_map(repo_root)?;
+    let secret = keys.default().and_then(|k| k.secret()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "no shared key to split (recipients-only setups have nothing to escrow)",
+        )
+    })?;
+    let lines = split(secret, shares, threshold)?;
+    eprintln!(
+        "[key] {shares} recovery shares; any {threshold} of them rebuild the key with `eenv key combine`."
+    );
+    eprintln!("[key] give each to a different person and store them apart, offline:");
+    for line in lines {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// `eenv key combine`: rebuilds the key from shares (given, or read from
+/// stdin), checks it against the .enc files, then saves it and decrypts.
+pub fn run_combine(repo_root: &Path, given: Vec<String>) -> io::Result<()> {
+    let lines = if given.is_empty() {
+        read_shares(None)?
+    } else {
+        given
+    };
+    let key = combine(&lines)?;
+    crate::crypto::bootstrap_with_key(repo_root, &key)?;
+    println!("[key] key recovered and saved to eenv.config.json");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "correct horse battery staple";
+
+    // a fixed share line, so the typos below are caught deterministically
+    fn fixed_line() -> String {
+        encode_share(3, &[1u8, 0x3a, 0xc4, 0x5e, 0x07])
+    }
+
+    fn err(lines: &[String]) -> String {
+        combine(lines).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn any_threshold_of_the_shares_rebuild_the_secret() {
+        let lines = split(SECRET, 5, 3).unwrap();
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|l| is_share(l)));
+        for pick in [[0, 1, 2], [2, 3, 4], [4, 0, 2]] {
+            let chosen: Vec<String> = pick.iter().map(|&i| lines[i].clone()).collect();
+            assert_eq!(combine(&chosen).unwrap(), SECRET);
+        }
+        assert_eq!(combine(&lines).unwrap(), SECRET);
+    }
+
+    #[test]
+    fn too_few_shares_fail() {
+        let lines = split(SECRET, 5, 3).unwrap();
+        assert!(err(&lines[..2]).contains("2 share(s) given, 3 needed"));
+        assert!(err(&[]).contains("no recovery shares"));
+    }
+
+    #[test]
+    fn split_rejects_bad_thresholds() {
+        assert!(split(SECRET, 3, 1).is_err());
+        assert!(split(SECRET, 3, 4).is_err());
+        assert!(split(SECRET, 2, 2).is_ok());
+    }
+
+    #[test]
+    fn checksum_catches_typos() {
+        let line = fixed_line();
+        assert!(decode_share(&line).is_ok());
+        let (head, check) = line.rsplit_once(':').unwrap();
+        let hex_start = SHARE_PREFIX.len() + 2;
+        // every single-character typo in the share itself
+        for i in hex_start..head.len() {
+            let mut typo = line.clone().into_bytes();
+            typo[i] = if typo[i] == b'0' { b'1' } else { b'0' };
+            let typo = String::from_utf8(typo).unwrap();
+            let e = decode_share(&typo).unwrap_err().to_string();
+            assert!(e.contains("typo"), "{typo}: {e}");
+        }
+        // ... in the checksum, and in the threshold it covers
+        let bad_check = format!("{head}:{}", if check == "0000" { "0001" } else { "0000" });
+        assert!(decode_share(&bad_check).is_err());
+        let bad_threshold = line.replacen(":3:", ":4:", 1);
+        assert!(decode_share(&bad_threshold).is_err());
+    }
+
+    #[test]
+    fn typo_in_one_share_fails_the_combine() {
+        let mut lines = split(SECRET, 3, 2).unwrap();
+        let (head, _) = lines[1].rsplit_once(':').unwrap();
+        lines[1] = format!("{head}:zzzz");
+        assert!(err(&lines).contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        let line = fixed_line();
+        for bad in [
+            "hello".to_string(),
+            line.replacen(SHARE_PREFIX, "eenv-share-v2:", 1),
+            format!("{line}:extra"),
+            line.replacen(":3:", ":x:", 1),
+            format!("{SHARE_PREFIX}3:0g:0000"),
+            format!("{SHARE_PREFIX}3:01:0000"),
+        ] {
+            assert!(decode_share(&bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn shares_from_different_splits_dont_mix() {
+        let a = split(SECRET, 3, 2).unwrap();
+        let b = split(SECRET, 3, 3).unwrap();
+        assert!(err(&[a[0].clone(), b[1].clone()]).contains("different splits"));
+
+        // same threshold, different secrets: either refused or garbage, never
+        // one of the two secrets
+        let c = split("another secret entirely", 3, 2).unwrap();
+        if let Ok(mixed) = combine(&[a[0].clone(), c[1].clone()]) {
+            assert_ne!(mixed, SECRET);
+            assert_ne!(mixed, "another secret entirely");
+        }
+    }
+
+    #[test]
+    fn field_arithmetic() {
+        assert_eq!(gf_mul(0x80, 0x02), 0x1d);
+        assert_eq!(gf_mul(0x03, 0x07), 0x09);
+        assert_eq!(gf_inv(1), 1);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1, "{a}");
+            assert_eq!(gf_mul(a, 0), 0);
+        }
+    }
+
+    #[test]
+    fn shares_from_earlier_versions_still_combine() {
+        let lines = [
+            "eenv-share-v1:2:012f3481261ba2a362d371e1af90e6e7644ad0a860f5b7174d52c76bea:ed38",
+            "eenv-share-v1:2:02fbd989da99fcc7a4035349d692b1756b0821e2567c13bb0607036266:839e",
+            "eenv-share-v1:2:03b7827a8ee73d10e6b84dda0a6777f06e36852f44f084df3f34b465e9:081a",
+        ]
+        .map(String::from);
+        for pick in [[0, 1], [1, 2], [2, 0]] {
+            let chosen = pick.map(|i| lines[i].clone());
+            assert_eq!(combine(&chosen).unwrap(), SECRET);
+        }
+    }
+
+    #[test]
+    fn repeated_shares_count_once() {
+        let lines = split(SECRET, 5, 3).unwrap();
+        let twice = [lines[0].clone(), lines[0].clone(), lines[1].clone()];
+        assert!(err(&twice).contains("2 share(s) given, 3 needed"));
+        let thrice = [
+            lines[0].clone(),
+            lines[1].clone(),
+            lines[1].clone(),
+            lines[2].clone(),
+        ];
+        assert_eq!(combine(&thrice).unwrap(), SECRET);
+    }
+
+    #[test]
+    fn conflicting_or_zero_x_shares_are_refused() {
+        let a = split(SECRET, 3, 2).unwrap();
+        let b = split(SECRET, 3, 2).unwrap();
+        // same x, different polynomial
+        assert!(err(&[a[0].clone(), b[0].clone()]).contains("don't belong together"));
+        let zero = encode_share(2, &[0, 1, 2, 3]);
+        let one = encode_share(2, &[1, 1, 2, 3]);
+        assert!(err(&[zero, one]).contains("don't belong together"));
+    }
+}
@@ -0,0 +1,658 @@
+use crate::config::{read_signers_seen, read_signing_key, write_signers_seen, write_signing_key};
+use crate::container::Binding;
+use crate::crypto::binding_for;
+use crate::keyring::selector_matches;
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use bech32::{FromBase32, ToBase32, Variant};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::Rng;
+use serde_json::{Map, Value, json};
+use std::process::{Command as Proc, Stdio};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// Optional provenance for .enc files. Each teammate has an ed25519 signing
+// key ("signing_key" in eenv.config.json); whenever eenv writes a .enc it
+// records a detached signature in the tracked eenv.signatures:
+//
+//   { ".env.production.enc": { "signer": "eenvsig1...", "sig": "<base64>" } }
+//
+// over the file's repo-relative path and the BLAKE3 hash of its bytes
+// (header and ciphertext). The tracked eenv.signers lists who may sign what,
+// one `<environment or glob> <eenvsig1...> # name` per line; a file matched
+// by any line must carry a valid signature from one of its signers.
+//
+// eenv.signers is read as committed at HEAD (or as staged, when checking a
+// commit), never from the working tree. A change to it must itself be
+// signed, under the "eenv.signers" entry of eenv.signatures, by a signer of
+// the version before it; each machine remembers the last version it accepted
+// and checks every change since.
+pub const SIGNERS_FILE: &str = "eenv.signers";
+pub const SIGNATURES_FILE: &str = "eenv.signatures";
+const PUBLIC_HRP: &str = "eenvsig";
+const SECRET_HRP: &str = "eenv-signing-key-";
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub struct SigningIdentity(SigningKey);
+
+impl SigningIdentity {
+    pub fn generate() -> Self {
+        let bytes: [u8; 32] = rand::rng().random();
+        SigningIdentity(SigningKey::from_bytes(&bytes))
+    }
+
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let bad = |e: String| invalid(format!("bad signing key: {e}"));
+        let (hrp, data, variant) = bech32::decode(s.trim()).map_err(|e| bad(e.to_string()))?;
+        let bytes = Vec::<u8>::from_base32(&data).map_err(|e| bad(e.to_string()))?;
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .ok()
+            .filter(|_| hrp == SECRET_HRP && variant == Variant::Bech32)
+            .ok_or_else(|| bad("expected EENV-SIGNING-KEY-1...".into()))?;
+        Ok(SigningIdentity(SigningKey::from_bytes(&bytes)))
+    }
+
+    pub fn to_secret_string(&self) -> String {
+        bech32::encode(SECRET_HRP, self.0.to_bytes().to_base32(), Variant::Bech32)
+            .expect("valid hrp")
+            .to_uppercase()
+    }
+
+    pub fn public(&self) -> VerifyingKey {
+        self.0.verifying_key()
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Signature {
+        self.0.sign(msg)
+    }
+}
+
+pub fn encode_public(pk: &VerifyingKey) -> String {
+    bech32::encode(PUBLIC_HRP, pk.as_bytes().to_base32(), Variant::Bech32).expect("valid hrp")
+}
+
+pub fn parse_public(s: &str) -> io::Result<VerifyingKey> {
+    let bad = || invalid(format!("bad signer {s}: expected eenvsig1..."));
+    let (hrp, data, variant) = bech32::decode(s.trim()).map_err(|_| bad())?;
+    let bytes: [u8; 32] = Vec::<u8>::from_base32(&data)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .filter(|_| hrp == PUBLIC_HRP && variant == Variant::Bech32)
+        .ok_or_else(bad)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| bad())
+}
+
+struct SignerRule {
+    selector: String,
+    key: VerifyingKey,
+    name: String,
+}
+
+// `<selector> <eenvsig1...> # name` lines of eenv.signers
+fn parse_signers(contents: &[u8]) -> io::Result<Vec<SignerRule>> {
+    let text = std::str::from_utf8(contents)
+        .map_err(|_| invalid(format!("{SIGNERS_FILE} is not UTF-8")))?;
+    let mut out = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let core = crate::gitignore::pattern_core(line);
+        if core.is_empty() {
+            continue;
+        }
+        let mut parts = core.split_whitespace();
+        let (Some(selector), Some(pk), None) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid(format!(
+                "{SIGNERS_FILE} line {}: expected `<environment or glob> <eenvsig1...>`",
+                i + 1
+            )));
+        };
+        let name = line
+            .split_once('#')
+            .map(|(_, n)| n.trim().to_string())
+            .unwrap_or_default();
+        out.push(SignerRule {
+            selector: selector.to_string(),
+            key: parse_public(pk)?,
+            name,
+        });
+    }
+    Ok(out)
+}
+
+// stdout of `git <args>` in the repo, or None when git fails (no such
+// revision or path, not a repo, no git)
+fn git(repo_root: &Path, args: &[&str]) -> Option<Vec<u8>> {
+    let out = Proc::new("git")
+        .arg("-C")
+        .arg(repo_root)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    out.status.success().then_some(out.stdout)
+}
+
+pub fn has_head(repo_root: &Path) -> bool {
+    git(repo_root, &["rev-parse", "-q", "--verify", "HEAD"]).is_some()
+}
+
+/// Contents of `rel` at `rev` (`HEAD`, a commit, or "" for the index); a
+/// missing file reads as empty.
+pub fn git_file(repo_root: &Path, rev: &str, rel: &str) -> Vec<u8> {
+    git(repo_root, &["show", &format!("{rev}:{rel}")]).unwrap_or_default()
+}
+
+fn hash_hex(contents: &[u8]) -> String {
+    blake3::hash(contents).to_hex().to_string()
+}
+
+/// The rules of eenv.signers as committed at HEAD. The working tree's copy
+/// is never trusted (anyone can edit it), and HEAD's must descend from the
+/// version this machine accepted last through changes each signed by a
+/// signer of the version before it. Without a git HEAD there is nothing to
+/// compare with, so the working tree's copy is used. Nothing is written;
+/// see `accept_signers`.
+fn trusted_signers(repo_root: &Path) -> io::Result<Vec<SignerRule>> {
+    if !has_head(repo_root) {
+        let path = repo_root.join(SIGNERS_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        return parse_signers(&fs::read(&path)?);
+    }
+    let head = git_file(repo_root, "HEAD", SIGNERS_FILE);
+    // the first version a machine sees is taken on trust
+    if let Some(seen) = read_signers_seen(repo_root)?
+        && seen != hash_hex(&head)
+    {
+        check_signers_history(repo_root, &seen)?;
+    }
+    parse_signers(&head)
+}
+
+/// Records HEAD's eenv.signers as the version this machine accepted, once
+/// it checks out, so later changes are checked from there. Only commands
+/// that write `.enc` files call this; reading never moves it.
+pub fn accept_signers(repo_root: &Path) -> io::Result<()> {
+    if !has_head(repo_root) {
+        return Ok(());
+    }
+    trusted_signers(repo_root)?;
+    let head_hash = hash_hex(&git_file(repo_root, "HEAD", SIGNERS_FILE));
+    if read_signers_seen(repo_root)?.as_deref() != Some(head_hash.as_str()) {
+        write_signers_seen(repo_root, &head_hash)?;
+    }
+    Ok(())
+}
+
+// Walks the changes to eenv.signers on HEAD's first-parent history back to
+// the version hashing to `seen`, and checks that each one since is signed
+// (in the eenv.signatures committed with it) by a signer of the version
+// before it.
+fn check_signers_history(repo_root: &Path, seen: &str) -> io::Result<()> {
+    let log = git(
+        repo_root,
+        &[
+            "log",
+            "--first-parent",
+            "--format=%H",
+            "HEAD",
+            "--",
+            SIGNERS_FILE,
+        ],
+    )
+    .unwrap_or_default();
+    let mut newer = Vec::new();
+    let mut base = None;
+    for rev in String::from_utf8_lossy(&log).lines() {
+        let contents = git_file(repo_root, rev, SIGNERS_FILE);
+        if hash_hex(&contents) == seen {
+            base = Some(contents);
+            break;
+        }
+        newer.push((rev.to_string(), contents));
+    }
+    // before its first commit the file didn't exist
+    let base = base
+        .or_else(|| (hash_hex(b"") == seen).then(Vec::new))
+        .ok_or_else(|| {
+            invalid(format!(
+                "{SIGNERS_FILE} at HEAD doesn't descend from the version this machine accepted \
+                 (history rewritten?); remove \"signers_seen\" from eenv.config.json to accept it"
+            ))
+        })?;
+    let mut rules = parse_signers(&base)?;
+    for (rev, contents) in newer.into_iter().rev() {
+        let sigs = parse_signatures(&git_file(repo_root, &rev, SIGNATURES_FILE))?;
+        if !rules.is_empty() && !signers_change_signed(&rules, &sigs, &contents) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{SIGNERS_FILE} was changed in {} without a signature from one of its \
+                     signers before that",
+                    &rev[..rev.len().min(12)]
+                ),
+            ));
+        }
+        rules = parse_signers(&contents)?;
+    }
+    Ok(())
+}
+
+// whether `sigs` holds a valid signature over `contents` of eenv.signers
+// from one of `rules`' keys
+fn signers_change_signed(rules: &[SignerRule], sigs: &Map<String, Value>, contents: &[u8]) -> bool {
+    let msg = message(SIGNERS_FILE, blake3::hash(contents));
+    sigs.get(SIGNERS_FILE)
+        .and_then(|entry| read_entry(SIGNERS_FILE, entry).ok())
+        .is_some_and(|(key, sig)| {
+            rules.iter().any(|r| r.key == key) && key.verify(&msg, &sig).is_ok()
+        })
+}
+
+/// The keys eenv.signers allows to sign the file at `binding`.
+pub fn approved_signers(repo_root: &Path, binding: &Binding) -> io::Result<Vec<VerifyingKey>> {
+    Ok(trusted_signers(repo_root)?
+        .into_iter()
+        .filter(|r| selector_matches(&r.selector, binding))
+        .map(|r| r.key)
+        .collect())
+}
+
+pub fn signatures_path(repo_root: &Path) -> PathBuf {
+    repo_root.join(SIGNATURES_FILE)
+}
+
+fn parse_signatures(contents: &[u8]) -> io::Result<Map<String, Value>> {
+    if contents.is_empty() {
+        return Ok(Map::new());
+    }
+    match serde_json::from_slice(contents) {
+        Ok(Value::Object(map)) => Ok(map),
+        _ => Err(invalid(format!("bad {SIGNATURES_FILE}: not a JSON object"))),
+    }
+}
+
+fn load_signatures(repo_root: &Path) -> io::Result<Map<String, Value>> {
+    let path = signatures_path(repo_root);
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+    parse_signatures(&fs::read(&path)?)
+}
+
+fn save_signatures(repo_root: &Path, map: Map<String, Value>) -> io::Result<()> {
+    let mut pretty =
+        serde_json::to_string_pretty(&Value::Object(map)).map_err(|e| invalid(e.to_string()))?;
+    pretty.push('\n');
+    crate::util::write_string_atomic(&signatures_path(repo_root), &pretty)
+}
+
+fn message(rel: &str, hash: blake3::Hash) -> Vec<u8> {
+    let mut msg = b"eenv signature v1\0".to_vec();
+    msg.extend_from_slice(rel.as_bytes());
+    msg.push(0);
+    msg.extend_from_slice(hash.as_bytes());
+    msg
+}
+
+fn file_hash(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(path)?)?;
+    Ok(hasher.finalize())
+}
+
+fn signature_entry(signer: &SigningIdentity, msg: &[u8]) -> Value {
+    json!({
+        "signer": encode_public(&signer.public()),
+        "sig": B64.encode(signer.sign(msg).to_bytes()),
+    })
+}
+
+// (signer, signature) of an eenv.signatures entry
+fn read_entry(rel: &str, entry: &Value) -> io::Result<(VerifyingKey, Signature)> {
+    let pk = entry.get("signer").and_then(|s| s.as_str()).unwrap_or("");
+    let key = parse_public(pk)?;
+    let sig = entry
+        .get("sig")
+        .and_then(|s| s.as_str())
+        .and_then(|s| B64.decode(s).ok())
+        .and_then(|b| Signature::from_slice(&b).ok())
+        .ok_or_else(|| invalid(format!("bad signature for {rel}")))?;
+    Ok((key, sig))
+}
+
+/// Records signatures for `.enc` files eenv just wrote (or removed) with this
+/// machine's signing key. Without one, their old signatures are dropped,
+/// since they no longer match.
+pub fn sign_files(repo_root: &Path, enc_paths: &[PathBuf]) -> io::Result<()> {
+    accept_signers(repo_root)?;
+    if enc_paths.is_empty() {
+        return Ok(());
+    }
+    let signer = read_signing_key(repo_root)?;
+    let mut sigs = load_signatures(repo_root)?;
+    let before = sigs.clone();
+    for enc_path in enc_paths {
+        let rel = binding_for(repo_root, enc_path).path;
+        match &signer {
+            Some(s) if enc_path.exists() => {
+                let entry = signature_entry(s, &message(&rel, file_hash(enc_path)?));
+                sigs.insert(rel, entry);
+            }
+            _ => {
+                sigs.remove(&rel);
+            }
+        }
+    }
+    if sigs != before {
+        save_signatures(repo_root, sigs)?;
+    }
+    Ok(())
+}
+
+/// Signs the working tree's eenv.signers with this machine's signing key
+/// when it differs from the committed one and isn't signed yet, so the
+/// change can be committed. Returns whether eenv.signatures was rewritten.
+pub fn sign_signers(repo_root: &Path) -> io::Result<bool> {
+    let path = repo_root.join(SIGNERS_FILE);
+    let contents = if path.exists() {
+        fs::read(&path)?
+    } else {
+        Vec::new()
+    };
+    let Some(signer) = read_signing_key(repo_root)? else {
+        return Ok(false);
+    };
+    let mut sigs = load_signatures(repo_root)?;
+    let me = [SignerRule {
+        selector: String::new(),
+        key: signer.public(),
+        name: String::new(),
+    }];
+    if contents == git_file(repo_root, "HEAD", SIGNERS_FILE)
+        || signers_change_signed(&me, &sigs, &contents)
+    {
+        return Ok(false);
+    }
+    let entry = signature_entry(&signer, &message(SIGNERS_FILE, blake3::hash(&contents)));
+    sigs.insert(SIGNERS_FILE.to_string(), entry);
+    save_signatures(repo_root, sigs)?;
+    Ok(true)
+}
+
+// Checks the signature in `sigs` of the file at `binding` whose bytes hash
+// to `hash`, under `rules`.
+fn check_signature(
+    rules: &[SignerRule],
+    sigs: &Map<String, Value>,
+    binding: &Binding,
+    hash: blake3::Hash,
+) -> io::Result<Option<String>> {
+    let signer = match sigs.get(&binding.path) {
+        None => None,
+        Some(entry) => {
+            let (key, sig) = read_entry(&binding.path, entry)?;
+            key.verify(&message(&binding.path, hash), &sig)
+                .map_err(|_| {
+                    invalid(format!(
+                        "{}: signature does not match (changed after it was signed)",
+                        binding.path
+                    ))
+                })?;
+            Some(key)
+        }
+    };
+
+    let allowed: Vec<&SignerRule> = rules
+        .iter()
+        .filter(|r| selector_matches(&r.selector, binding))
+        .collect();
+    let label = |key: &VerifyingKey| {
+        rules
+            .iter()
+            .find(|r| r.key == *key && !r.name.is_empty())
+            .map_or_else(|| encode_public(key), |r| r.name.clone())
+    };
+    match signer {
+        Some(key) if allowed.is_empty() || allowed.iter().any(|r| r.key == key) => {
+            Ok(Some(label(&key)))
+        }
+        None if allowed.is_empty() => Ok(None),
+        found => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} must be signed by an approved signer for {} in {SIGNERS_FILE} ({})",
+                binding.path,
+                allowed[0].selector,
+                match found {
+                    Some(key) => format!("signed by {}", label(&key)),
+                    None => "unsigned".to_string(),
+                }
+            ),
+        )),
+    }
+}
+
+/// Checks the signature of one `.enc` against the committed eenv.signers.
+/// Returns who signed it (the name from eenv.signers when listed), or
+/// `None` when it is unsigned and no rule in eenv.signers covers it. A bad
+/// signature, or a covered file that isn't signed by one of its signers, is
+/// an error.
+pub fn verify_file(repo_root: &Path, enc_path: &Path) -> io::Result<Option<String>> {
+    let rules = trusted_signers(repo_root)?;
+    let binding = binding_for(repo_root, enc_path);
+    let sigs = load_signatures(repo_root)?;
+    check_signature(&rules, &sigs, &binding, file_hash(enc_path)?)
+}
+
+/// Like `verify_file`, for the bytes of a `.enc` as it was signed at
+/// `binding`, which may not be where it is now.
+pub fn verify_bytes(
+    repo_root: &Path,
+    binding: &Binding,
+    data: &[u8],
+) -> io::Result<Option<String>> {
+    let rules = trusted_signers(repo_root)?;
+    let sigs = load_signatures(repo_root)?;
+    check_signature(&rules, &sigs, binding, blake3::hash(data))
+}
+
+/// Checks what is about to be committed, as it is in the index: a change to
+/// eenv.signers must be signed by one of the committed signers, and every
+/// staged `.enc` must be signed as the staged eenv.signers requires. One
+/// line per problem.
+pub fn verify_staged(repo_root: &Path) -> io::Result<Vec<String>> {
+    let committed = if has_head(repo_root) {
+        trusted_signers(repo_root)?
+    } else {
+        Vec::new()
+    };
+    let signers = git_file(repo_root, "", SIGNERS_FILE);
+    let sigs = parse_signatures(&git_file(repo_root, "", SIGNATURES_FILE))?;
+    if signers != git_file(repo_root, "HEAD", SIGNERS_FILE)
+        && !committed.is_empty()
+        && !signers_change_signed(&committed, &sigs, &signers)
+    {
+        return Ok(vec![format!(
+            "{SIGNERS_FILE} changed, but the change isn't signed by one of its current signers \
+             (one of them runs `eenv pre-commit --write`, or stage {SIGNATURES_FILE})"
+        )]);
+    }
+    let rules = parse_signers(&signers)?;
+
+    let listing = git(repo_root, &["ls-files", "-z"])
+        .ok_or_else(|| io::Error::other("git ls-files failed"))?;
+    let mut problems = Vec::new();
+    for name in listing.split(|b| *b == 0) {
+        let rel = String::from_utf8_lossy(name);
+        if !rel.ends_with(".enc") {
+            continue;
+        }
+        let binding = binding_for(repo_root, &repo_root.join(rel.as_ref()));
+        let hash = blake3::hash(&git_file(repo_root, "", &rel));
+        if let Err(e) = check_signature(&rules, &sigs, &binding, hash) {
+            problems.push(e.to_string());
+        }
+    }
+    Ok(problems)
+}
+
+/// Creates this machine's signing key in eenv.config.json (refusing to
+/// replace an existing one unless `force`) and returns its public key.
+pub fn new_signer(repo_root: &Path, force: bool) -> io::Result<VerifyingKey> {
+    if !force && read_signing_key(repo_root).ok().flatten().is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "a signing key already exists (use --force to replace it)",
+        ));
+    }
+    let key = SigningIdentity::generate();
+    write_signing_key(repo_root, &key)?;
+    crate::config::ensure_gitignore_has_config(repo_root)?;
+    Ok(key.public())
+}
+
+pub fn show_signer(repo_root: &Path) -> io::Result<VerifyingKey> {
+    read_signing_key(repo_root)?
+        .map(|k| k.public())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no signing key configured (run `eenv signer new`)",
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::write_config_object;
+    use crate::util::{git_in, scratch_repo};
+
+    // a repo with a config, this machine's signing key, and eenv.signers
+    // approving it (and nobody else) for production, committed
+    fn setup(name: &str) -> (PathBuf, SigningIdentity) {
+        let dir = scratch_repo(name);
+        let config = json!({ "key": "k" });
+        write_config_object(&dir, config.as_object().unwrap().clone()).unwrap();
+        let me = SigningIdentity::generate();
+        write_signing_key(&dir, &me).unwrap();
+        let line = format!("production {} # me\n", encode_public(&me.public()));
+        fs::write(dir.join(SIGNERS_FILE), line).unwrap();
+        commit(&dir, &[SIGNERS_FILE], "signers");
+        (dir, me)
+    }
+
+    fn commit(dir: &Path, files: &[&str], msg: &str) {
+        let mut args = vec!["add", "--"];
+        args.extend_from_slice(files);
+        git_in(dir, &args);
+        git_in(dir, &["commit", "-q", "-m", msg]);
+    }
+
+    fn someone_else() -> String {
+        encode_public(&SigningIdentity::generate().public())
+    }
+
+    fn prod(dir: &Path) -> Binding {
+        binding_for(dir, &dir.join(".env.production.enc"))
+    }
+
+    #[test]
+    fn signers_are_trusted_from_head_and_reading_writes_nothing() {
+        let (dir, me) = setup("signing-head");
+        fs::write(
+            dir.join(SIGNERS_FILE),
+            format!("production {}\n", someone_else()),
+        )
+        .unwrap();
+        assert_eq!(approved_signers(&dir, &prod(&dir)).unwrap(), [me.public()]);
+        let dev = binding_for(&dir, &dir.join(".env.enc"));
+        assert!(approved_signers(&dir, &dev).unwrap().is_empty());
+        assert_eq!(read_signers_seen(&dir).unwrap(), None);
+
+        accept_signers(&dir).unwrap();
+        let head = git_file(&dir, "HEAD", SIGNERS_FILE);
+        assert_eq!(read_signers_seen(&dir).unwrap(), Some(hash_hex(&head)));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unsigned_change_to_signers_is_rejected() {
+        let (dir, me) = setup("signing-unsigned");
+        accept_signers(&dir).unwrap();
+        let seen = read_signers_seen(&dir).unwrap();
+
+        // a signed change is accepted...
+        let both = format!(
+            "production {} # me\nproduction {}\n",
+            encode_public(&me.public()),
+            someone_else()
+        );
+        fs::write(dir.join(SIGNERS_FILE), &both).unwrap();
+        assert!(sign_signers(&dir).unwrap());
+        commit(&dir, &[SIGNERS_FILE, SIGNATURES_FILE], "add a signer");
+        assert_eq!(approved_signers(&dir, &prod(&dir)).unwrap().len(), 2);
+
+        // ...an unsigned one after it is not, and nothing is recorded
+        fs::write(
+            dir.join(SIGNERS_FILE),
+            format!("production {}\n", someone_else()),
+        )
+        .unwrap();
+        commit(&dir, &[SIGNERS_FILE], "take over");
+        let err = approved_signers(&dir, &prod(&dir)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{err}");
+        assert!(accept_signers(&dir).is_err());
+        assert_eq!(read_signers_seen(&dir).unwrap(), seen);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn verify_staged_requires_approved_signatures() {
+        let (dir, me) = setup("signing-staged");
+        let enc = dir.join(".env.production.enc");
+        fs::write(&enc, b"ciphertext").unwrap();
+        git_in(&dir, &["add", ".env.production.enc"]);
+        let problems = verify_staged(&dir).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("unsigned"), "{problems:?}");
+
+        sign_files(&dir, std::slice::from_ref(&enc)).unwrap();
+        git_in(&dir, &["add", SIGNATURES_FILE]);
+        assert!(verify_staged(&dir).unwrap().is_empty());
+        assert_eq!(verify_file(&dir, &enc).unwrap(), Some("me".to_string()));
+        assert_eq!(
+            verify_bytes(&dir, &prod(&dir), b"ciphertext").unwrap(),
+            Some("me".to_string())
+        );
+        assert!(verify_bytes(&dir, &prod(&dir), b"tampered").is_err());
+
+        // changed after it was signed
+        fs::write(&enc, b"other ciphertext").unwrap();
+        git_in(&dir, &["add", ".env.production.enc"]);
+        let problems = verify_staged(&dir).unwrap();
+        assert!(problems[0].contains("does not match"), "{problems:?}");
+
+        // an unsigned change to eenv.signers
+        fs::write(
+            dir.join(SIGNERS_FILE),
+            format!(
+                "production {} # me\n* {}\n",
+                encode_public(&me.public()),
+                someone_else()
+            ),
+        )
+        .unwrap();
+        git_in(&dir, &["add", SIGNERS_FILE]);
+        let problems = verify_staged(&dir).unwrap();
+        assert!(problems[0].contains("isn't signed"), "{problems:?}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
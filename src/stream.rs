@@ -0,0 +1,277 @@
+use crate::container::{NONCE_LEN, TAG_LEN};
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use rand::Rng;
+use std::io::{self, Read, Write};
+
+// STREAM construction (as in age / libsodium secretstream) for large files:
+//
+//   nonce prefix (19 bytes) | chunk 0 | chunk 1 | ... | final chunk
+//
+// Each chunk is up to CHUNK_LEN bytes of plaintext sealed with the nonce
+// prefix | u32 BE chunk counter | last-chunk flag and the header as AAD, so
+// chunks can't be reordered, dropped or moved between files, and cutting the
+// file at a chunk boundary is caught because no chunk carries the final flag.
+// Memory use is two chunks regardless of file size.
+pub const CHUNK_LEN: usize = 64 * 1024;
+pub const PREFIX_LEN: usize = NONCE_LEN - 5;
+const SEALED_LEN: usize = CHUNK_LEN + TAG_LEN;
+
+fn chunk_nonce(prefix: &[u8; PREFIX_LEN], counter: u32, last: bool) -> XNonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    *XNonce::from_slice(&nonce)
+}
+
+// reads until `buf` is full or the input ends; returns the bytes read
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match input.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+fn next_counter(counter: u32) -> io::Result<u32> {
+    counter
+        .checked_add(1)
+        .ok_or_else(|| io::Error::other("file too large for one stream"))
+}
+
+/// Writes the nonce prefix and sealed chunks of `input` to `output`.
+pub fn encrypt_stream(
+    aead: &XChaCha20Poly1305,
+    aad: &[u8],
+    mut input: impl Read,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let prefix: [u8; PREFIX_LEN] = rand::rng().random();
+    output.write_all(&prefix)?;
+
+    let mut buf = vec![0u8; CHUNK_LEN];
+    let mut next = vec![0u8; CHUNK_LEN];
+    let mut n = read_full(&mut input, &mut buf)?;
+    let mut counter = 0u32;
+    loop {
+        // a full chunk is only the last one if nothing follows it
+        let m = if n == CHUNK_LEN {
+            read_full(&mut input, &mut next)?
+        } else {
+            0
+        };
+        let last = m == 0;
+        let sealed = aead
+            .encrypt(
+                &chunk_nonce(&prefix, counter, last),
+                Payload {
+                    msg: &buf[..n],
+                    aad,
+                },
+            )
+            .map_err(|_| io::Error::other("encrypt failed"))?;
+        output.write_all(&sealed)?;
+        if last {
+            return output.flush();
+        }
+        std::mem::swap(&mut buf, &mut next);
+        n = m;
+        counter = next_counter(counter)?;
+    }
+}
+
+/// Opens a stream written by `encrypt_stream`. `base` is the file offset of
+/// the stream, so errors point at the right place in the file.
+pub fn decrypt_stream(
+    aead: &XChaCha20Poly1305,
+    aad: &[u8],
+    base: usize,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut prefix = [0u8; PREFIX_LEN];
+    if read_full(&mut input, &mut prefix)? < PREFIX_LEN {
+        return Err(invalid("enc file too short".into()));
+    }
+
+    let mut buf = vec![0u8; SEALED_LEN];
+    let mut next = vec![0u8; SEALED_LEN];
+    let mut n = read_full(&mut input, &mut buf)?;
+    let mut offset = base + PREFIX_LEN;
+    let mut counter = 0u32;
+    loop {
+        if n < TAG_LEN {
+            return Err(invalid(format!("stream truncated at offset {offset}")));
+        }
+        let m = if n == SEALED_LEN {
+            read_full(&mut input, &mut next)?
+        } else {
+            0
+        };
+        let last = m == 0;
+        let open = |last: bool| {
+            aead.decrypt(
+                &chunk_nonce(&prefix, counter, last),
+                Payload {
+                    msg: &buf[..n],
+                    aad,
+                },
+            )
+        };
+        let plaintext = match open(last) {
+            Ok(pt) => pt,
+            Err(_) if last && open(false).is_ok() => {
+                return Err(invalid(format!(
+                    "stream truncated after offset {} (final chunk missing)",
+                    offset + n
+                )));
+            }
+            Err(_) if counter == 0 => {
+                return Err(invalid(format!(
+                    "decrypt failed at offset {offset} (wrong key or corrupted file)"
+                )));
+            }
+            Err(_) => {
+                return Err(invalid(format!(
+                    "chunk {counter} at offset {offset} is corrupted"
+                )));
+            }
+        };
+        output.write_all(&plaintext)?;
+        if last {
+            return output.flush();
+        }
+        std::mem::swap(&mut buf, &mut next);
+        offset += n;
+        n = m;
+        counter = next_counter(counter)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chacha20poly1305::aead::KeyInit;
+
+    const AAD: &[u8] = b"header";
+
+    fn aead() -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&[7u8; 32].into())
+    }
+
+    fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn seal(plain: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        encrypt_stream(&aead(), AAD, plain, &mut out).unwrap();
+        out
+    }
+
+    fn open(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decrypt_stream(&aead(), AAD, 0, data, &mut out).map(|()| out)
+    }
+
+    // (start, end) of each sealed chunk
+    fn chunks(data: &[u8]) -> Vec<(usize, usize)> {
+        let mut out = Vec::new();
+        let mut at = PREFIX_LEN;
+        while at < data.len() {
+            let end = (at + SEALED_LEN).min(data.len());
+            out.push((at, end));
+            at = end;
+        }
+        out
+    }
+
+    fn err(data: &[u8]) -> String {
+        open(data).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn round_trips_across_chunk_boundaries() {
+        for len in [
+            0,
+            1,
+            CHUNK_LEN - 1,
+            CHUNK_LEN,
+            CHUNK_LEN + 1,
+            3 * CHUNK_LEN + 5,
+        ] {
+            let plain = plaintext(len);
+            let sealed = seal(&plain);
+            assert_eq!(
+                sealed.len(),
+                PREFIX_LEN + len + len.div_ceil(CHUNK_LEN).max(1) * TAG_LEN
+            );
+            assert_eq!(open(&sealed).unwrap(), plain, "len {len}");
+        }
+    }
+
+    #[test]
+    fn truncation_at_a_chunk_boundary_is_caught() {
+        let sealed = seal(&plaintext(3 * CHUNK_LEN + 5));
+        let (_, end) = chunks(&sealed)[1];
+        assert!(err(&sealed[..end]).contains("final chunk missing"));
+        // an exact multiple of the chunk size ends with a full final chunk
+        let sealed = seal(&plaintext(2 * CHUNK_LEN));
+        let (_, end) = chunks(&sealed)[0];
+        assert!(err(&sealed[..end]).contains("final chunk missing"));
+    }
+
+    #[test]
+    fn truncation_inside_a_chunk_is_caught() {
+        let sealed = seal(&plaintext(2 * CHUNK_LEN + 100));
+        assert!(open(&sealed[..sealed.len() - 1]).is_err());
+        assert!(open(&sealed[..PREFIX_LEN + 10]).is_err());
+        assert!(err(&sealed[..PREFIX_LEN - 1]).contains("too short"));
+        assert!(err(&sealed[..PREFIX_LEN]).contains("truncated"));
+    }
+
+    #[test]
+    fn reordered_or_dropped_chunks_fail() {
+        let sealed = seal(&plaintext(3 * CHUNK_LEN + 5));
+        let c = chunks(&sealed);
+        let part = |i: usize| &sealed[c[i].0..c[i].1];
+
+        let swapped = [&sealed[..PREFIX_LEN], part(1), part(0), part(2), part(3)].concat();
+        assert!(err(&swapped).contains("offset"));
+
+        let dropped = [&sealed[..PREFIX_LEN], part(0), part(2), part(3)].concat();
+        assert!(err(&dropped).contains("chunk 1"));
+
+        let repeated = [&sealed[..PREFIX_LEN], part(0), part(0), part(2), part(3)].concat();
+        assert!(open(&repeated).is_err());
+    }
+
+    #[test]
+    fn trailing_data_and_tampering_fail() {
+        let sealed = seal(&plaintext(CHUNK_LEN + 10));
+        let extended = [&sealed[..], &[0u8; TAG_LEN + 1]].concat();
+        assert!(open(&extended).is_err());
+
+        let mut flipped = sealed.clone();
+        flipped[PREFIX_LEN + SEALED_LEN + 3] ^= 1;
+        assert!(err(&flipped).contains("chunk 1"));
+    }
+
+    #[test]
+    fn chunks_are_bound_to_the_header_and_key() {
+        let sealed = seal(&plaintext(100));
+        let mut out = Vec::new();
+        assert!(decrypt_stream(&aead(), b"other", 0, &sealed[..], &mut out).is_err());
+        let other = XChaCha20Poly1305::new(&[8u8; 32].into());
+        assert!(decrypt_stream(&other, AAD, 0, &sealed[..], &mut out).is_err());
+    }
+}
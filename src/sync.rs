@@ -0,0 +1,170 @@
+use crate::check::EnvReader;
+use crate::crypto::binding_for;
+use crate::dotenv::{self, Document};
+use crate::drift::groups;
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+// Where a missing `key` goes in `local`: right after the nearest key before it
+// in `order` that `local` has, else before the nearest one after it, else at
+// the end.
+fn anchor(local: &Document, order: &[String], key: &str) -> Option<usize> {
+    let i = order.iter().position(|k| k == key)?;
+    order[..i]
+        .iter()
+        .rev()
+        .find_map(|k| local.position(k).map(|p| p + 1))
+        .or_else(|| order[i + 1..].iter().find_map(|k| local.position(k)))
+}
+
+enum Answer {
+    Default,
+    Typed(String),
+    Skip,
+}
+
+// Asks for the value of `key`: Enter takes `default`, `-` (or end of input)
+// skips the key.
+fn ask(key: &str, from_enc: bool, default: &str) -> io::Result<Answer> {
+    let hint = if from_enc {
+        "Enter: value from .enc".to_string()
+    } else if !default.is_empty() {
+        format!("Enter: example value {default:?}")
+    } else {
+        "Enter: empty".to_string()
+    };
+    print!("  {key} [{hint}, -: skip]: ");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        println!();
+        return Ok(Answer::Skip);
+    }
+    Ok(match line.trim_end_matches(['\r', '\n']) {
+        "-" => Answer::Skip,
+        "" => Answer::Default,
+        typed => Answer::Typed(typed.to_string()),
+    })
+}
+
+/// `eenv sync`: adds the keys a local env file lacks but its .example or .enc
+/// has. Each value is asked for (Enter takes the one from the .enc, else the
+/// example's, as written there); with `yes` they are taken without asking. A
+/// new key goes right after the key it follows in the .enc or example, and
+/// the rest of the file is left exactly as it was.
+pub fn run_sync(repo_root: &Path, yes: bool) -> io::Result<()> {
+    let mut reader = EnvReader::new(repo_root);
+    for group in groups(repo_root)?.into_values() {
+        let Some(local_path) = &group.local else {
+            continue;
+        };
+        if group.example.is_none() && group.enc.is_none() {
+            continue;
+        }
+        let rel = binding_for(repo_root, local_path).path;
+        let mut local = dotenv::parse(&fs::read_to_string(local_path)?)
+            .map_err(|e| invalid(format!("{rel}: {e}")))?;
+        let mut read = |path: &Option<PathBuf>, what: &str| {
+            let path = path.as_ref()?;
+            match reader.text(path).and_then(|t| dotenv::parse(&t)) {
+                Ok(doc) => Some(doc),
+                Err(e) => {
+                    eprintln!("[sync] WARN: {rel}: can't read {what}: {e}");
+                    None
+                }
+            }
+        };
+        let enc = read(&group.enc, ".enc");
+        let example = read(&group.example, "example");
+
+        // the .enc's order, then example-only keys
+        let mut order: Vec<String> = Vec::new();
+        for doc in [&enc, &example].into_iter().flatten() {
+            for pair in doc.pairs() {
+                if !order.contains(&pair.key) {
+                    order.push(pair.key.clone());
+                }
+            }
+        }
+        let missing: Vec<&String> = order.iter().filter(|k| local.get(k).is_none()).collect();
+        if missing.is_empty() {
+            println!("[sync] ✔ {rel}");
+            continue;
+        }
+
+        println!("[sync] {rel}: {} missing key(s)", missing.len());
+        let mut added = Vec::new();
+        for key in missing {
+            let from_enc = enc.as_ref().and_then(|d| d.get(key));
+            let Some(source) = from_enc.or_else(|| example.as_ref().and_then(|d| d.get(key)))
+            else {
+                continue;
+            };
+            let answer = if yes {
+                Answer::Default
+            } else {
+                ask(key, from_enc.is_some(), &source.value)?
+            };
+            let at = anchor(&local, &order, key);
+            match answer {
+                Answer::Default => local.insert_copy(at, source),
+                Answer::Typed(value) => local.insert(at, key, &value),
+                Answer::Skip => continue,
+            }
+            added.push(key.as_str());
+        }
+        if !added.is_empty() {
+            crate::util::write_string_atomic(local_path, &local.to_string())?;
+            println!("[sync] {rel}: added {}", added.join(", "));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{scratch_keyed, seal_env};
+
+    fn order(keys: &str) -> Vec<String> {
+        keys.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn anchor_follows_the_previous_key_else_precedes_the_next() {
+        let local = dotenv::parse("# top\nB=2\n\nD=4\n").unwrap();
+        let order = order("A B C D E");
+        let pos = |k: &str| local.position(k);
+        assert_eq!(anchor(&local, &order, "A"), pos("B"));
+        assert_eq!(anchor(&local, &order, "C"), pos("B").map(|p| p + 1));
+        assert_eq!(anchor(&local, &order, "E"), pos("D").map(|p| p + 1));
+        assert_eq!(anchor(&dotenv::parse("X=1\n").unwrap(), &order, "A"), None);
+        assert_eq!(anchor(&local, &order, "Z"), None);
+    }
+
+    #[test]
+    fn missing_keys_land_next_to_their_neighbours() {
+        let dir = scratch_keyed("sync-insert");
+        seal_env(&dir, ".env.enc", "A=1\nB=2\nC=${B}\nD=4\n");
+        fs::write(dir.join(".env.example"), "A=\nB=\nC=\nD=\nE=placeholder\n").unwrap();
+        fs::write(dir.join(".env"), "# top\nB=local  # mine\n\nD=4\n").unwrap();
+
+        run_sync(&dir, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join(".env")).unwrap(),
+            "# top\nA=1\nB=local  # mine\nC=${B}\n\nD=4\nE=placeholder\n"
+        );
+        // nothing left to add
+        let before = fs::read(dir.join(".env")).unwrap();
+        run_sync(&dir, true).unwrap();
+        assert_eq!(fs::read(dir.join(".env")).unwrap(), before);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,7 +1,22 @@
 use clap::ValueEnum;
 
 #[derive(ValueEnum, Clone, Debug)]
-pub enum HookAction { Install, Uninstall }
+pub enum HookAction {
+    Install,
+    Uninstall,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+pub enum IdentityAction {
+    New,
+    Show,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+pub enum ManifestAction {
+    Verify,
+    Update,
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct EenvState {
@@ -9,4 +24,13 @@ pub struct EenvState {
     pub example: bool,
     pub env: bool,
     pub eenvjson: bool,
-}
\ No newline at end of file
+}
+
+/// How an env file is encrypted: one opaque blob, value-by-value so the
+/// .enc stays a readable, diffable dotenv file, or an age file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncMode {
+    File,
+    Values,
+    Age,
+}
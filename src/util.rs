@@ -64,6 +64,31 @@ pub fn write_bytes_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
     fs::rename(tmp, path)
 }
 
+/// Like `write_bytes_atomic`, for contents produced by `fill` a piece at a
+/// time. The temp file is removed if `fill` fails.
+pub fn write_stream_atomic(
+    path: &Path,
+    fill: impl FnOnce(&mut io::BufWriter<&File>) -> io::Result<()>,
+) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp~");
+    let result = (|| {
+        let f = File::create(&tmp)?;
+        let mut w = io::BufWriter::new(&f);
+        fill(&mut w)?;
+        w.flush()?;
+        drop(w);
+        f.sync_all()
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(tmp, path)
+}
+
 pub fn backup_path_with_ts(p: &Path) -> PathBuf {
     let ts = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -102,3 +127,96 @@ where
     eprintln!("[time] {label}: {:.3} ms", dt.as_secs_f64() * 1000.0);
     (out, dt)
 }
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// An empty directory under the system temp dir for one test, canonicalized
+/// so paths found by walking it strip cleanly against it.
+#[cfg(test)]
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("eenv-test-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
+
+/// A scratch directory with a fresh git repository in it.
+#[cfg(test)]
+pub fn scratch_repo(name: &str) -> PathBuf {
+    let dir = scratch_dir(name);
+    git_in(&dir, &["init", "-q"]);
+    git_in(&dir, &["config", "user.email", "test@example.com"]);
+    git_in(&dir, &["config", "user.name", "test"]);
+    git_in(&dir, &["config", "commit.gpgsign", "false"]);
+    dir
+}
+
+/// Runs git in `dir` for a test, panicking if it fails.
+#[cfg(test)]
+pub fn git_in(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?} failed");
+}
+
+/// The shared key of `scratch_keyed` repos.
+#[cfg(test)]
+pub const TEST_KEY: &str = "k";
+
+/// A scratch directory whose eenv.config.json holds the shared key
+/// `TEST_KEY`, with cheap KDF params.
+#[cfg(test)]
+pub fn scratch_keyed(name: &str) -> PathBuf {
+    with_test_key(scratch_dir(name))
+}
+
+/// Like `scratch_keyed`, with a fresh git repository in it.
+#[cfg(test)]
+pub fn scratch_keyed_repo(name: &str) -> PathBuf {
+    with_test_key(scratch_repo(name))
+}
+
+#[cfg(test)]
+fn with_test_key(dir: PathBuf) -> PathBuf {
+    let config = serde_json::json!({
+        "key": TEST_KEY,
+        "kdf": crate::kdf::KdfParams::cheap().to_json(),
+    });
+    crate::config::write_config_object(&dir, config.as_object().unwrap().clone()).unwrap();
+    dir
+}
+
+/// Encrypts `text` to `dir/name` under the key of a `scratch_keyed` repo
+/// and returns its path.
+#[cfg(test)]
+pub fn seal_env(dir: &Path, name: &str, text: &str) -> PathBuf {
+    use crate::keyring::{KeyScheme, Keyring};
+    let params = crate::config::read_kdf_params(dir).unwrap().unwrap();
+    let path = dir.join(name);
+    let out = crate::crypto::encrypt_bytes(
+        &Keyring::from_secret(TEST_KEY).unwrap(),
+        &KeyScheme::Shared(params),
+        text.as_bytes(),
+        &crate::crypto::binding_for(dir, &path),
+    );
+    fs::write(&path, out.unwrap()).unwrap();
+    path
+}
@@ -0,0 +1,377 @@
+use crate::container::{self, Binding, Header, NONCE_LEN};
+use crate::dotenv::{self, Entry};
+use crate::keyring::{KeyScheme, Keyring};
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use rand::Rng;
+use std::{collections::HashMap, io};
+
+// Per-value mode keeps the .enc a readable dotenv file:
+//
+//   # eenv:values v1 <base64 header> <hex mac>
+//   # comments, blank lines and ordering as in the source
+//   DATABASE_URL=enc:v1:<base64 nonce|ciphertext>
+//
+// Each value is sealed with the header + key name as AAD, so values can't be
+// moved between keys or files. The MAC covers the header and the rest of the
+// file byte for byte (key names, tokens, comments and their order), and every
+// assignment must hold a token, so nothing can be added or edited by hand.
+pub const VALUES_MAGIC: &str = "# eenv:values ";
+const VALUES_VERSION: &str = "v1";
+const TOKEN_PREFIX: &str = "enc:v1:";
+
+pub fn is_values_file(data: &[u8]) -> bool {
+    data.starts_with(VALUES_MAGIC.as_bytes())
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn mac_key(content_key: &[u8; 32]) -> [u8; 32] {
+    blake3::derive_key("eenv values-mac v1", content_key)
+}
+
+fn seal_value(
+    aead: &XChaCha20Poly1305,
+    header_bytes: &[u8],
+    name: &str,
+    value: &str,
+) -> io::Result<String> {
+    let aad = [header_bytes, name.as_bytes()].concat();
+    let nonce_bytes: [u8; NONCE_LEN] = rand::rng().random();
+    let ct = aead
+        .encrypt(
+            XNonce::from_slice(&nonce_bytes),
+            Payload {
+                msg: value.as_bytes(),
+                aad: &aad,
+            },
+        )
+        .map_err(|_| io::Error::other("encrypt failed"))?;
+    Ok(format!(
+        "{TOKEN_PREFIX}{}",
+        B64.encode([&nonce_bytes[..], &ct].concat())
+    ))
+}
+
+fn open_value(
+    aead: &XChaCha20Poly1305,
+    header_bytes: &[u8],
+    name: &str,
+    token: &str,
+) -> io::Result<String> {
+    let raw = token
+        .strip_prefix(TOKEN_PREFIX)
+        .and_then(|b| B64.decode(b.trim()).ok())
+        .filter(|r| r.len() > NONCE_LEN)
+        .ok_or_else(|| invalid(format!("{name}: malformed enc:v1 token")))?;
+    let aad = [header_bytes, name.as_bytes()].concat();
+    let (nonce, ct) = raw.split_at(NONCE_LEN);
+    let pt = aead
+        .decrypt(XNonce::from_slice(nonce), Payload { msg: ct, aad: &aad })
+        .map_err(|_| invalid(format!("decrypt failed for {name} (wrong key?)")))?;
+    String::from_utf8(pt).map_err(|_| invalid(format!("{name}: value is not utf-8")))
+}
+
+/// Opened contents of a values file: the header and, per key, its plaintext
+/// and token, plus the reassembled plaintext.
+struct Opened {
+    header: Header,
+    header_bytes: Vec<u8>,
+    content_key: [u8; 32],
+    tokens: HashMap<String, (String, String)>,
+    plaintext: String,
+}
+
+struct Split<'a> {
+    header_bytes: Vec<u8>,
+    mac_hex: &'a str,
+    body: &'a str,
+}
+
+fn split_values(data: &[u8]) -> io::Result<Split<'_>> {
+    let text = std::str::from_utf8(data).map_err(|_| invalid("values file is not utf-8"))?;
+    let (first, body) = text.split_once('\n').unwrap_or((text, ""));
+    let meta = first
+        .strip_prefix(VALUES_MAGIC)
+        .ok_or_else(|| invalid("bad values header"))?;
+    let mut parts = meta.split_whitespace();
+    parts
+        .next()
+        .filter(|v| *v == VALUES_VERSION)
+        .ok_or_else(|| invalid("unsupported values file version (upgrade eenv?)"))?;
+    let header_bytes = parts
+        .next()
+        .and_then(|h| B64.decode(h).ok())
+        .ok_or_else(|| invalid("bad values header"))?;
+    let mac_hex = parts
+        .next()
+        .ok_or_else(|| invalid("values file missing mac"))?;
+    Ok(Split {
+        header_bytes,
+        mac_hex,
+        body,
+    })
+}
+
+pub fn read_header(data: &[u8]) -> io::Result<Header> {
+    let split = split_values(data)?;
+    Ok(container::parse_header(&split.header_bytes)?.0)
+}
+
+fn open_values(key: &Keyring, data: &[u8]) -> io::Result<Opened> {
+    let Split {
+        header_bytes,
+        mac_hex,
+        body,
+    } = split_values(data)?;
+    let (header, _) = container::parse_header(&header_bytes)?;
+    let content_key = key.content_key(&header)?;
+    let aead = XChaCha20Poly1305::new((&content_key).into());
+
+    let mut mac = blake3::Hasher::new_keyed(&mac_key(&content_key));
+    mac.update(&header_bytes);
+    mac.update(body.as_bytes());
+    let mut tokens = HashMap::new();
+    let mut out = Vec::new();
+    for entry in dotenv::parse(body)?.entries {
+        let pair = match entry {
+            Entry::Pair(p) => p,
+            other => {
+                out.push(other.to_string());
+                continue;
+            }
+        };
+        let (name, token) = (pair.key.as_str(), pair.value.as_str());
+        if !token.starts_with(TOKEN_PREFIX) {
+            return Err(invalid(format!(
+                "line {}: {name} is not encrypted (added outside eenv?)",
+                pair.line + 1
+            )));
+        }
+        let lhs = pair.lhs();
+        let value = open_value(&aead, &header_bytes, name, token)?;
+        out.push(format!("{lhs}={value}"));
+        tokens.insert(name.to_string(), (value, token.to_string()));
+    }
+    let expected = crate::util::from_hex(mac_hex).unwrap_or_default();
+    if mac.finalize() != blake3::Hash::from_slice(&expected).unwrap_or([0u8; 32].into()) {
+        return Err(invalid("values file MAC mismatch (edited outside eenv?)"));
+    }
+    Ok(Opened {
+        header,
+        header_bytes,
+        content_key,
+        tokens,
+        plaintext: out.join("\n"),
+    })
+}
+
+/// Encrypts a dotenv file value by value. Tokens from `previous` (the current
+/// .enc, if any) are kept for values that didn't change, so diffs only show
+/// the keys that actually changed.
+pub fn encrypt_values(
+    key: &Keyring,
+    scheme: &KeyScheme,
+    plaintext: &[u8],
+    binding: &Binding,
+    previous: Option<&[u8]>,
+) -> io::Result<Vec<u8>> {
+    let text = std::str::from_utf8(plaintext)
+        .map_err(|_| invalid("per-value mode needs a utf-8 env file"))?;
+
+    // keep the previous header (and with it the content key) while the scheme
+    // and binding are unchanged, otherwise start over with fresh tokens
+    let reuse = previous
+        .filter(|prev| is_values_file(prev))
+        .and_then(|prev| open_values(key, prev).ok())
+        .filter(|o| scheme.matches(&o.header) && o.header.binding.as_ref() == Some(binding));
+    let (header_bytes, content_key, reuse) = match reuse {
+        Some(o) => (o.header_bytes, o.content_key, o.tokens),
+        None => {
+            let (mut header, content_key) = scheme.new_header(key, binding)?;
+            header.flags = 0;
+            (header.encode()?, content_key, HashMap::new())
+        }
+    };
+    let aead = XChaCha20Poly1305::new((&content_key).into());
+
+    let mut body = Vec::new();
+    let doc = dotenv::parse(text)
+        .map_err(|e| invalid(format!("{e}, refusing to write it in the clear")))?;
+    for entry in doc.entries {
+        match entry {
+            Entry::Pair(pair) => {
+                let (name, lhs, value) = (pair.key.as_str(), pair.lhs(), pair.rhs_raw());
+                let token = match reuse.get(name) {
+                    Some((old, token)) if *old == value => token.clone(),
+                    _ => seal_value(&aead, &header_bytes, name, &value)?,
+                };
+                body.push(format!("{lhs}={token}"));
+            }
+            other => body.push(other.to_string()),
+        }
+    }
+    let body = body.join("\n");
+    let mut mac = blake3::Hasher::new_keyed(&mac_key(&content_key));
+    mac.update(&header_bytes);
+    mac.update(body.as_bytes());
+    let first = format!(
+        "{VALUES_MAGIC}{VALUES_VERSION} {} {}",
+        B64.encode(&header_bytes),
+        mac.finalize().to_hex()
+    );
+    Ok(format!("{first}\n{body}").into_bytes())
+}
+
+pub fn decrypt_values(
+    key: &Keyring,
+    data: &[u8],
+    expected: Option<&Binding>,
+) -> io::Result<Vec<u8>> {
+    let opened = open_values(key, data)?;
+    crate::crypto::check_binding(&opened.header, expected)?;
+    Ok(opened.plaintext.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdf::KdfParams;
+
+    const SOURCE: &str = "# database\nexport DATABASE_URL=\"postgres://u:p@h/db\" # main\n\nAPI_KEY='sk_live_123'\nEMPTY=\n";
+
+    fn setup() -> (Keyring, KeyScheme, Binding) {
+        let binding = Binding {
+            path: ".env.enc".into(),
+            env: "default".into(),
+        };
+        (
+            Keyring::from_secret("correct horse").unwrap(),
+            KeyScheme::Shared(KdfParams::cheap()),
+            binding,
+        )
+    }
+
+    fn encrypt(source: &str) -> (Keyring, Binding, String) {
+        let (key, scheme, binding) = setup();
+        let enc = encrypt_values(&key, &scheme, source.as_bytes(), &binding, None).unwrap();
+        (key, binding, String::from_utf8(enc).unwrap())
+    }
+
+    fn decrypt(key: &Keyring, binding: &Binding, enc: &str) -> io::Result<String> {
+        decrypt_values(key, enc.as_bytes(), Some(binding)).map(|b| String::from_utf8(b).unwrap())
+    }
+
+    fn is_mac_error(e: &io::Error) -> bool {
+        e.to_string().contains("MAC mismatch")
+    }
+
+    #[test]
+    fn round_trips_byte_for_byte() {
+        let (key, binding, enc) = encrypt(SOURCE);
+        assert!(enc.starts_with("# eenv:values v1 "));
+        assert!(enc.contains("# database\n"));
+        assert!(!enc.contains("sk_live_123"));
+        assert!(!enc.contains("postgres"));
+        assert_eq!(decrypt(&key, &binding, &enc).unwrap(), SOURCE);
+        assert!(decrypt(&key, &binding, &enc.replacen(" v1 ", " v2 ", 1)).is_err());
+    }
+
+    #[test]
+    fn unchanged_values_keep_their_tokens() {
+        let (key, scheme, binding) = setup();
+        let first = encrypt_values(&key, &scheme, SOURCE.as_bytes(), &binding, None).unwrap();
+        let edited = SOURCE.replace("sk_live_123", "sk_live_456");
+        let second =
+            encrypt_values(&key, &scheme, edited.as_bytes(), &binding, Some(&first)).unwrap();
+        let (first, second) = (
+            String::from_utf8(first).unwrap(),
+            String::from_utf8(second).unwrap(),
+        );
+        let line = |s: &str, k: &str| s.lines().find(|l| l.contains(k)).unwrap().to_string();
+        assert_eq!(line(&first, "DATABASE_URL"), line(&second, "DATABASE_URL"));
+        assert_ne!(line(&first, "API_KEY"), line(&second, "API_KEY"));
+        assert_eq!(decrypt(&key, &binding, &second).unwrap(), edited);
+    }
+
+    #[test]
+    fn rejects_hand_added_plaintext() {
+        let (key, binding, enc) = encrypt(SOURCE);
+        let tampered = format!("{enc}LD_PRELOAD=/tmp/evil.so\n");
+        let err = decrypt(&key, &binding, &tampered).unwrap_err();
+        assert!(
+            err.to_string().contains("LD_PRELOAD is not encrypted"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn mac_covers_comments_and_order() {
+        let (key, binding, enc) = encrypt(SOURCE);
+        let commented = enc.replace("# database", "# database (edited)");
+        assert!(is_mac_error(
+            &decrypt(&key, &binding, &commented).unwrap_err()
+        ));
+
+        let mut lines: Vec<&str> = enc.lines().collect();
+        let (a, b) = (
+            lines
+                .iter()
+                .position(|l| l.starts_with("export DATABASE_URL"))
+                .unwrap(),
+            lines.iter().position(|l| l.starts_with("API_KEY")).unwrap(),
+        );
+        lines.swap(a, b);
+        let reordered = lines.join("\n") + "\n";
+        assert!(is_mac_error(
+            &decrypt(&key, &binding, &reordered).unwrap_err()
+        ));
+
+        let dropped = enc.replace(
+            &enc.lines()
+                .find(|l| l.starts_with("EMPTY"))
+                .unwrap()
+                .to_string(),
+            "",
+        );
+        assert!(is_mac_error(
+            &decrypt(&key, &binding, &dropped).unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn tokens_are_bound_to_their_key() {
+        let (key, binding, enc) = encrypt("A=one\nB=two\n");
+        let token = |k: &str| {
+            enc.lines()
+                .find_map(|l| l.strip_prefix(&format!("{k}=")))
+                .unwrap()
+                .to_string()
+        };
+        let swapped = enc
+            .replace(&token("A"), "@")
+            .replace(&token("B"), &token("A"))
+            .replace('@', &token("B"));
+        let err = decrypt(&key, &binding, &swapped).unwrap_err();
+        assert!(err.to_string().contains("decrypt failed for A"), "{err}");
+    }
+
+    #[test]
+    fn wrong_key_and_binding_fail() {
+        let (_, binding, enc) = encrypt(SOURCE);
+        let other = Keyring::from_secret("wrong").unwrap();
+        assert!(decrypt(&other, &binding, &enc).is_err());
+
+        let (key, _, enc) = encrypt(SOURCE);
+        let moved = Binding {
+            path: ".env.production.enc".into(),
+            env: "production".into(),
+        };
+        assert!(decrypt(&key, &moved, &enc).is_err());
+    }
+}